@@ -2,7 +2,7 @@ use std::process::exit;
 
 use reinforcement::{
     simulation::{acceleration::Acceleration, with_egui},
-    training::tests::test_policy_adam,
+    training::tests::{test_policy_adam, test_ppo_adam},
 };
 
 fn main() {
@@ -10,7 +10,7 @@ fn main() {
         use reinforcement::training::{
             activations::{id::Id, tanh::Tanh},
             mlp::MLP,
-            tests::test_value_adam,
+            tests::{benchmark_optimizers, test_value_adam},
             trainer::Trainer,
         };
         let mut net: Trainer<f64> = Trainer::new(MLP::new(2, vec![Tanh::layer(3), Id::layer(1)]));
@@ -28,7 +28,9 @@ fn main() {
             ],
         );
         test_value_adam();
+        benchmark_optimizers();
         test_policy_adam();
+        test_ppo_adam();
 
         exit(0);
     }
@@ -1,4 +1,5 @@
 use activation::Activation;
+use rand::Rng;
 
 pub mod activation;
 pub mod layer;
@@ -14,15 +15,20 @@ pub trait ForwardNetwork<const NI: usize, const NO: usize>: JoinNetwork<NI> {
     fn forward(&mut self, input: [Float; NI]) -> [Float; NO];
 }
 pub trait Network<const NI: usize, const NO: usize>: ForwardNetwork<NI, NO> + Clone {
-    fn randomize(&mut self);
+    fn randomize(&mut self, rng: &mut impl Rng);
     /// The parameter $r$ is the ratio $0 < r <= 1$ for the relaxation of the update of the gradient. A value of $r = 1$ correspond to keep only the new value of the gradient, whereas $r = 0.5$ will average the new and last value.
     fn update_gradient(&mut self, relaxation: Float, delta: [Float; NO]) -> [Float; NI];
     fn reset_gradient(&mut self);
     fn apply_gradient(&mut self, alpha: Float);
     fn rescale_gradient(&mut self, a: Float);
     fn norm2_gradient(&self) -> Float;
+    /// Rescale the gradient to a unit norm, a zero or non finite gradient is left untouched
+    /// instead of producing NaNs.
     fn normalize_gradient(&mut self) {
-        self.rescale_gradient(self.norm2_gradient().sqrt().recip());
+        let norm = self.norm2_gradient().sqrt();
+        if norm > 0.0 && norm.is_finite() {
+            self.rescale_gradient(norm.recip());
+        }
     }
     fn add_gradient(&mut self, rhs: &Self);
 
@@ -22,7 +22,8 @@ impl<const NI: usize, const NO: usize, A: Activation> Default for Layer<NI, NO,
             activations: boxarray(0.0),
             inputs: boxarray(0.0),
         };
-        s.randomize();
+        // NOTE: use `randomize` with a seeded generator for reproducible weights
+        s.randomize(&mut rand::rng());
         s
     }
 }
@@ -45,8 +46,7 @@ impl<const NI: usize, const NO: usize, A: Activation> ForwardNetwork<NI, NO> for
     }
 }
 impl<const NI: usize, const NO: usize, A: Activation> Network<NI, NO> for Layer<NI, NO, A> {
-    fn randomize(&mut self) {
-        let mut rng = rand::rng();
+    fn randomize(&mut self, rng: &mut impl Rng) {
         let a = 1.0 / NO as Float;
         let uni = rand::distr::Uniform::new(-a, a).unwrap();
         self.weights.iter_mut().for_each(|(ws, bias, _)| {
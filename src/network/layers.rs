@@ -1,3 +1,5 @@
+use rand::Rng;
+
 use super::{Float, ForwardNetwork, JoinNetwork, Network, activation::Activation, layer::Layer};
 
 #[derive(Default, Clone)]
@@ -22,9 +24,9 @@ impl<const NI: usize, const NH: usize, const NO: usize, A: Activation, O: Networ
 impl<const NI: usize, const NH: usize, const NO: usize, A: Activation, O: Network<NH, NO>>
     Network<NI, NO> for Layers<NI, NH, A, O>
 {
-    fn randomize(&mut self) {
-        self.layer_in.randomize();
-        self.layer_out.randomize();
+    fn randomize(&mut self, rng: &mut impl Rng) {
+        self.layer_in.randomize(rng);
+        self.layer_out.randomize(rng);
     }
     fn update_gradient(&mut self, relaxation: Float, delta: [Float; NO]) -> [Float; NI] {
         self.layer_in.update_gradient(
@@ -1,6 +1,7 @@
 use std::f64::consts::PI;
 
 use array_vector_space::ArrayVectorSpace;
+use rand::{Rng, SeedableRng, rngs::StdRng};
 use rand_distr::Distribution;
 
 use crate::network::activation::Sigmoid;
@@ -16,6 +17,8 @@ pub struct MetaParameters {
     pub alpha_score: Float,
     pub relaxation: Float,
     pub sigma: Float,
+    /// Seed of the initial weights and of the exploration noise
+    pub seed: u64,
 }
 
 pub trait Reinforce {
@@ -32,7 +35,7 @@ pub trait Reinforce {
     );
 }
 
-#[derive(Default, Clone)]
+#[derive(Clone)]
 pub struct Reinforcement<
     const NI: usize,
     const NO: usize,
@@ -42,13 +45,32 @@ pub struct Reinforcement<
     network: N,
     score_network: S,
     relaxation: Float,
+    rng: StdRng,
+}
+impl<
+    const NI: usize,
+    const NO: usize,
+    N: Network<NI, NO> + Default,
+    S: Network<NI, 1, OutA = Id> + Default,
+> Default for Reinforcement<NI, NO, N, S>
+{
+    fn default() -> Self {
+        Self {
+            network: Default::default(),
+            score_network: Default::default(),
+            relaxation: Default::default(),
+            rng: StdRng::seed_from_u64(0),
+        }
+    }
 }
 impl<const NI: usize, const NO: usize, N: Network<NI, NO>, S: Network<NI, 1, OutA = Id>>
     Reinforcement<NI, NO, N, S>
 {
-    pub fn randomize(&mut self) {
-        self.network.randomize();
-        self.score_network.randomize();
+    /// Randomize the weights and restart the exploration noise from `seed`.
+    pub fn randomize(&mut self, seed: u64) {
+        self.rng = StdRng::seed_from_u64(seed);
+        self.network.randomize(&mut self.rng);
+        self.score_network.randomize(&mut self.rng);
     }
     fn normal_forward(
         &mut self,
@@ -65,7 +87,7 @@ impl<const NI: usize, const NO: usize, N: Network<NI, NO>, S: Network<NI, 1, Out
             let max = max.unwrap_or(Float::INFINITY).min(p + nr);
             rand_distr::Normal::new(p, sigma)
                 .unwrap()
-                .sample(&mut rand::rng())
+                .sample(&mut self.rng)
                 .clamp(min, max)
         });
         let proba = target
@@ -100,7 +122,15 @@ impl<const NI: usize, const NO: usize, N: Network<NI, NO>, S: Network<NI, 1, Out
         self.relaxation = meta_parameters.relaxation;
 
         // let mut nets: Box<[Self; NC]> = boxarray(self.clone()); //FIXME: this fails in wasm with "memory access out of bounds" and "Uncaught TypeError: Cannot read properties of null (reading 'querySelector')"
-        let mut nets: Vec<Self> = ctx_list.iter().map(|_| self.clone()).collect();
+        let mut nets: Vec<Self> = ctx_list
+            .iter()
+            .map(|_| {
+                // NOTE: every context draws its own noise from a stream derived from the main one
+                let mut net = self.clone();
+                net.rng = StdRng::seed_from_u64(self.rng.random());
+                net
+            })
+            .collect();
         ctx_list
             .iter_mut()
             .zip(nets.iter_mut())
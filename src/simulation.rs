@@ -32,6 +32,11 @@ pub enum Parameter<T> {
         logarithmic: bool,
         range: RangeInclusive<Float>,
     },
+    /// Exact integer, such as a seed, which a float slider would round
+    Integer {
+        tag: T,
+        value: u64,
+    },
     Toggle {
         tag: T,
         enable: bool,
@@ -43,6 +48,7 @@ pub enum Parameter<T> {
 
 pub enum UpadeParameter<T> {
     Slider { tag: T, value: Float },
+    Integer { tag: T, value: u64 },
     Toggle { tag: T, enable: bool },
     Button { tag: T },
 }
@@ -57,6 +63,8 @@ pub trait Simulation: Send + 'static {
     fn reset(&mut self);
 
     fn meta_parameters(&self) -> MetaParameters;
+    /// Called after each reinforcement, for instance to count them for a `Schedule`
+    fn reinforced(&mut self) {}
     fn egui_parameters(&self) -> Vec<Parameter<Self::Tag>>;
     fn update_parameter(&mut self, update: UpadeParameter<Self::Tag>);
 
@@ -101,6 +109,7 @@ pub trait Simulation: Send + 'static {
                 Self::score_goal(ctx, info)
             },
         );
+        self.reinforced();
     }
     fn simulate(&mut self) -> Vec<Vec<Self::Ctx>> {
         self.ctx_list()
@@ -216,6 +225,21 @@ impl<S: Simulation, FS: Fn() -> S> eframe::App for SimulationGUI<S, FS> {
                             }));
                         }
                     }
+                    Parameter::Integer { tag, value } => {
+                        let changed = ui
+                            .horizontal(|ui| {
+                                let changed = ui.add(egui::DragValue::new(value)).changed();
+                                ui.label(tag.str());
+                                changed
+                            })
+                            .inner;
+                        if changed {
+                            requests.push(Request::UpdateParameter(UpadeParameter::Integer {
+                                tag: tag.clone(),
+                                value: *value,
+                            }));
+                        }
+                    }
                     Parameter::Toggle { tag, enable } => {
                         if ui.toggle_value(enable, tag.str()).changed() {
                             requests.push(Request::UpdateParameter(UpadeParameter::Toggle {
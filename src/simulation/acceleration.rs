@@ -3,12 +3,15 @@ use std::sync::Arc;
 use array_vector_space::ArrayVectorSpace;
 use egui::{Color32, Rect, Shape, Stroke, emath::RectTransform, epaint::PathStroke, pos2};
 
-use crate::network::{
-    Float,
-    activation::{Id, Relu},
-    layer::Layer,
-    layers::Layers,
-    reinforcement::{MetaParameters, Reinforcement},
+use crate::{
+    network::{
+        Float,
+        activation::{Id, Relu},
+        layer::Layer,
+        layers::Layers,
+        reinforcement::{MetaParameters, Reinforcement},
+    },
+    training::schedule::Schedule,
 };
 
 use super::{Parameter, Simulation, Tag, UpadeParameter};
@@ -41,9 +44,13 @@ pub struct Acceleration {
     poss: [[Float; 2]; 4],
     speeds: [[Float; 2]; 4],
     meta_parameters: MetaParameters,
+    anneal: bool,
     tot_reinforcement: usize,
 }
 
+/// Number of reinforcements over which alpha and sigma are annealed when enabled
+const ANNEAL_STEPS: usize = 2000;
+
 impl Acceleration {
     pub fn new() -> Self {
         let meta_parameters = MetaParameters {
@@ -51,6 +58,7 @@ impl Acceleration {
             alpha_score: 1e-1,
             relaxation: 1e-4,
             sigma: 1e1,
+            seed: 0,
         };
         let targets = [
             Circle {
@@ -99,9 +107,10 @@ impl Acceleration {
             poss: starts,
             speeds: [[0.0; 2]; 4],
             meta_parameters,
+            anneal: false,
             tot_reinforcement: 0,
         };
-        s.net.randomize();
+        s.net.randomize(s.meta_parameters.seed);
         s
     }
 }
@@ -112,6 +121,8 @@ pub enum Param {
     AlphaScore,
     Relaxation,
     Sigma,
+    Seed,
+    Anneal,
 }
 impl Tag for Param {
     fn str(&self) -> &'static str {
@@ -120,6 +131,8 @@ impl Tag for Param {
             Param::AlphaScore => "alpha_score",
             Param::Relaxation => "relaxation",
             Param::Sigma => "sigma",
+            Param::Seed => "seed",
+            Param::Anneal => "anneal",
         }
     }
 }
@@ -132,13 +145,31 @@ impl Simulation for Acceleration {
 
     fn reset(&mut self) {
         self.tot_reinforcement = 0;
-        self.net.randomize();
+        self.net.randomize(self.meta_parameters.seed);
         self.poss = self.starts;
         self.speeds = [[0.0; 2]; 4];
     }
 
     fn meta_parameters(&self) -> MetaParameters {
-        self.meta_parameters
+        if !self.anneal {
+            return self.meta_parameters;
+        }
+        // NOTE: the sliders give the initial values, which are divided by 100 at the end
+        let cosine = |start: Float| Schedule::Cosine {
+            start,
+            end: start * 1e-2,
+            steps: ANNEAL_STEPS,
+        };
+        let t = self.tot_reinforcement;
+        MetaParameters {
+            alpha: cosine(self.meta_parameters.alpha).value(t),
+            alpha_score: cosine(self.meta_parameters.alpha_score).value(t),
+            sigma: cosine(self.meta_parameters.sigma).value(t),
+            ..self.meta_parameters
+        }
+    }
+    fn reinforced(&mut self) {
+        self.tot_reinforcement += 1;
     }
     fn egui_parameters(&self) -> Vec<Parameter<Param>> {
         use Param::*;
@@ -167,6 +198,14 @@ impl Simulation for Acceleration {
                 logarithmic: true,
                 range: 1e-5..=1e3,
             },
+            Parameter::Integer {
+                tag: Seed,
+                value: self.meta_parameters.seed,
+            },
+            Parameter::Toggle {
+                tag: Anneal,
+                enable: self.anneal,
+            },
         ]
     }
     fn update_parameter(&mut self, update: UpadeParameter<Self::Tag>) {
@@ -176,7 +215,16 @@ impl Simulation for Acceleration {
                 Param::AlphaScore => self.meta_parameters.alpha_score = value,
                 Param::Relaxation => self.meta_parameters.relaxation = value,
                 Param::Sigma => self.meta_parameters.sigma = value,
+                Param::Seed | Param::Anneal => {}
             },
+            UpadeParameter::Integer {
+                tag: Param::Seed,
+                value,
+            } => self.meta_parameters.seed = value,
+            UpadeParameter::Toggle {
+                tag: Param::Anneal,
+                enable,
+            } => self.anneal = enable,
             _ => {}
         }
     }
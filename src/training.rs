@@ -1,13 +1,30 @@
 use num::Float;
-use rand::seq::SliceRandom;
+use rand::{Rng, seq::SliceRandom};
+
+use checkpoint::OptimizerState;
+use serialize::LoadError;
 
 pub mod activations;
+pub mod advantage;
+pub mod checkpoint;
+pub mod dqn;
+pub mod env;
+pub mod gradcheck;
+pub mod init;
 pub mod layer_matrix;
 pub mod least_squar_value;
 pub mod mlp;
+pub mod model_based;
 pub mod ops;
 pub mod optimizers;
+pub mod planning;
 pub mod policies;
+pub mod ppo;
+pub mod replay;
+pub mod sac;
+pub mod schedule;
+pub mod serialize;
+pub mod td3;
 pub mod tests;
 pub mod trainer;
 
@@ -30,7 +47,11 @@ pub trait Eval<T: Float>: Weights<T> {
 }
 // NOTE: It might be more efficient to add to the existing gradient in back_prop and compute gradient
 pub trait BackProp<T: Float>: Eval<T> {
-    /// This function is supposed to completely overwrite the gradient, not add to it
+    /// This function is supposed to completely overwrite the gradient, not add to it.
+    /// The first `output_len` values of `back` are the gradient with respect to the output, and the
+    /// gradient with respect to the input is written in the first `input_len` values of `front`.
+    /// Both `front` and `back` are used as buffers and must be at least `back_front_len` long.
+    /// An empty `gradient` skips the gradient with respect to the weights.
     fn back_prop(
         &self,
         input: &[T],
@@ -40,18 +61,56 @@ pub trait BackProp<T: Float>: Eval<T> {
         back: &mut [T],
         gradient: &mut [T],
     );
+    fn back_front_len(&self) -> usize {
+        self.input_len().max(self.output_len())
+    }
+    /// Vector-Jacobian product with respect to the input: the first `output_len` values of `back`
+    /// are the vector, as in `back_prop`, and the returned slice of `front` is the product.
+    /// The gradient with respect to the weights is not computed.
+    fn input_gradient<'a>(
+        &self,
+        input: &[T],
+        weights: &[T],
+        state: &[T],
+        front: &'a mut [T],
+        back: &mut [T],
+    ) -> &'a [T] {
+        self.back_prop(input, weights, state, front, back, &mut []);
+        &front[..self.input_len()]
+    }
+}
+/// Split the gradient of a composition at `mid`, an empty gradient stays empty on both sides.
+pub(crate) fn split_gradient<T>(gradient: &mut [T], mid: usize) -> (&mut [T], &mut [T]) {
+    if gradient.is_empty() {
+        (&mut [], gradient)
+    } else {
+        gradient.split_at_mut(mid)
+    }
 }
 pub trait Gradient<T: Float>: Eval<T> {
     /// This function is supposed to completely overwrite the gradient, not add to it.
     /// Also, it is not supposed to call eval, but instead call output.
     fn compute_gradient(&self, input: &[T], weights: &[T], state: &mut [T], gradient: &mut [T]);
+    /// The scalar of which `compute_gradient` computes the gradient.
+    fn objective(&self, state: &[T]) -> T;
 }
 pub trait Policy<T: Float> {}
 /// It is `Gradient` with respect to the scalar probability
 pub trait StochasticPolicy<T: Float>: Gradient<T> {
     fn probability(&self, state: &[T]) -> T;
-    fn stochastic_eval(&self, input: &[T], weights: &[T], state: &mut [T]);
+    fn entropy(&self, state: &[T]) -> T;
+    fn stochastic_eval(&self, input: &[T], weights: &[T], state: &mut [T], rng: &mut impl Rng);
     fn stochastic_output<'a>(&self, state: &'a [T]) -> &'a [T];
+    /// Same as `compute_gradient` but with respect to `probability_scale * probability + entropy_scale * entropy`
+    fn compute_scaled_gradient(
+        &self,
+        input: &[T],
+        weights: &[T],
+        state: &mut [T],
+        probability_scale: T,
+        entropy_scale: T,
+        gradient: &mut [T],
+    );
 }
 
 pub trait Value<T: Float>: Gradient<T> {
@@ -62,6 +121,8 @@ pub trait Value<T: Float>: Gradient<T> {
 
 pub trait Activation<T: Float>: BackProp<T> {
     fn range(&self) -> (Option<T>, Option<T>);
+    /// Name under which the activation is known to an `ActivationRegistry`
+    fn name(&self) -> &'static str;
 }
 
 pub struct TimeStep<'a, T: Float> {
@@ -69,49 +130,387 @@ pub struct TimeStep<'a, T: Float> {
     pub state: &'a mut [T],
 }
 
+/// Time steps given to `Optimizer::optimize`, which accesses them by index in a random order.
+pub trait TimeSteps<T: Float> {
+    fn len(&self) -> usize;
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    /// Input and state of the time step `i`
+    fn get(&self, i: usize) -> (&[T], &[T]);
+    fn get_mut(&mut self, i: usize) -> TimeStep<'_, T>;
+}
+impl<T: Float> TimeSteps<T> for [TimeStep<'_, T>] {
+    fn len(&self) -> usize {
+        <[TimeStep<T>]>::len(self)
+    }
+    fn get(&self, i: usize) -> (&[T], &[T]) {
+        (self[i].input, self[i].state)
+    }
+    fn get_mut(&mut self, i: usize) -> TimeStep<'_, T> {
+        let TimeStep { input, state } = &mut self[i];
+        TimeStep { input, state }
+    }
+}
+
+/// Time steps whose inputs and states are stored one after the other in two buffers, as in
+/// `env::RolloutBuffer` and `replay::Batch`, so that no `TimeStep` has to be collected.
+pub struct TimeStepChunks<'a, T: Float> {
+    inputs: &'a [T],
+    input_len: usize,
+    states: &'a mut [T],
+    state_len: usize,
+    /// Indices of the time steps in use, all of them when `None`
+    selection: Option<&'a [usize]>,
+}
+impl<'a, T: Float> TimeStepChunks<'a, T> {
+    pub fn new(inputs: &'a [T], input_len: usize, states: &'a mut [T], state_len: usize) -> Self {
+        debug_assert!(
+            inputs.len() * state_len == states.len() * input_len,
+            "as many inputs as states"
+        );
+        TimeStepChunks {
+            inputs,
+            input_len,
+            states,
+            state_len,
+            selection: None,
+        }
+    }
+    /// Only use the time steps at the indices in `selection`
+    pub fn with_selection(mut self, selection: &'a [usize]) -> Self {
+        self.selection = Some(selection);
+        self
+    }
+    fn index(&self, i: usize) -> usize {
+        self.selection.map_or(i, |selection| selection[i])
+    }
+}
+impl<T: Float> TimeSteps<T> for TimeStepChunks<'_, T> {
+    fn len(&self) -> usize {
+        self.selection
+            .map_or(self.states.len() / self.state_len, |selection| {
+                selection.len()
+            })
+    }
+    fn get(&self, i: usize) -> (&[T], &[T]) {
+        let i = self.index(i);
+        (
+            &self.inputs[i * self.input_len..][..self.input_len],
+            &self.states[i * self.state_len..][..self.state_len],
+        )
+    }
+    fn get_mut(&mut self, i: usize) -> TimeStep<'_, T> {
+        let i = self.index(i);
+        TimeStep {
+            input: &self.inputs[i * self.input_len..][..self.input_len],
+            state: &mut self.states[i * self.state_len..][..self.state_len],
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum Direction {
     Ascent,
     Descent,
 }
+/// Called by `Optimizer::optimize` after each epoch with the optimized component, the statistics
+/// of the epoch and the time steps, whose states are the ones evaluated during the epoch.
+/// Returning `true` ends the optimization. It is implemented by closures.
+pub trait EarlyStop<T: Float, G, S: ?Sized> {
+    fn stop(&mut self, to_optimize: &G, epoch: &EpochStats<T>, time_steps: &S) -> bool;
+}
+impl<T: Float, G, S: ?Sized, F: FnMut(&G, &EpochStats<T>, &S) -> bool> EarlyStop<T, G, S> for F {
+    fn stop(&mut self, to_optimize: &G, epoch: &EpochStats<T>, time_steps: &S) -> bool {
+        self(to_optimize, epoch, time_steps)
+    }
+}
+/// Do all the epochs, the default of `OptimizeOptions`
+#[derive(Clone, Copy, Debug, Default)]
+pub struct NoEarlyStop;
+impl<T: Float, G, S: ?Sized> EarlyStop<T, G, S> for NoEarlyStop {
+    fn stop(&mut self, _: &G, _: &EpochStats<T>, _: &S) -> bool {
+        false
+    }
+}
+/// Knobs of `Optimizer::optimize`: `epochs` passes over the time steps in minibatches of
+/// `minibatch_size` in the given `direction`, ended by `early_stop`. Each minibatch gradient is
+/// safeguarded after its normalization: first clip every value to `[-max_value, max_value]`, then
+/// rescale the whole gradient if its norm exceeds `max_norm`.
+#[derive(Clone, Copy, Debug)]
+pub struct OptimizeOptions<T: Float, E = NoEarlyStop> {
+    pub epochs: usize,
+    pub minibatch_size: usize,
+    pub direction: Direction,
+    pub max_value: Option<T>,
+    pub max_norm: Option<T>,
+    /// Also do a step with the last minibatch when it is smaller than `minibatch_size`
+    pub include_remainder: bool,
+    pub early_stop: E,
+}
+impl<T: Float> Default for OptimizeOptions<T> {
+    fn default() -> Self {
+        OptimizeOptions {
+            epochs: 1,
+            minibatch_size: 1,
+            direction: Direction::Descent,
+            max_value: None,
+            max_norm: None,
+            include_remainder: false,
+            early_stop: NoEarlyStop,
+        }
+    }
+}
+impl<T: Float, E> OptimizeOptions<T, E> {
+    pub fn with_epochs(mut self, epochs: usize) -> Self {
+        self.epochs = epochs;
+        self
+    }
+    pub fn with_minibatch_size(mut self, minibatch_size: usize) -> Self {
+        self.minibatch_size = minibatch_size;
+        self
+    }
+    pub fn with_direction(mut self, direction: Direction) -> Self {
+        self.direction = direction;
+        self
+    }
+    pub fn with_remainder(mut self, include_remainder: bool) -> Self {
+        self.include_remainder = include_remainder;
+        self
+    }
+    pub fn with_max_value(mut self, max_value: T) -> Self {
+        self.max_value = Some(max_value);
+        self
+    }
+    pub fn with_max_norm(mut self, max_norm: T) -> Self {
+        self.max_norm = Some(max_norm);
+        self
+    }
+    /// The types of the arguments of a closure must be written, as they are only known by
+    /// `Optimizer::optimize`.
+    pub fn with_early_stop<F>(self, early_stop: F) -> OptimizeOptions<T, F> {
+        OptimizeOptions {
+            epochs: self.epochs,
+            minibatch_size: self.minibatch_size,
+            direction: self.direction,
+            max_value: self.max_value,
+            max_norm: self.max_norm,
+            include_remainder: self.include_remainder,
+            early_stop,
+        }
+    }
+    /// Apply the safeguards to `gradient` and return whether it was rescaled to `max_norm`.
+    pub fn clip(&self, gradient: &mut [T]) -> bool {
+        if let Some(max_value) = self.max_value {
+            gradient
+                .iter_mut()
+                .for_each(|g| *g = g.max(-max_value).min(max_value));
+        }
+        if let Some(max_norm) = self.max_norm {
+            let norm = gradient
+                .iter()
+                .fold(T::zero(), |acc, &g| acc + g * g)
+                .sqrt();
+            if norm > max_norm {
+                gradient.iter_mut().for_each(|g| *g = *g * max_norm / norm);
+                return true;
+            }
+        }
+        false
+    }
+}
+/// Gradients and shuffled indices used by `Optimizer::optimize`, allocated once for a component
+/// of `weights_len` weights optimized on `time_steps_len` time steps, so that `optimize` does not
+/// allocate.
+pub struct OptimizeBuffers<T: Float> {
+    gradient: Box<[T]>,
+    tmp_gradient: Box<[T]>,
+    indices: Box<[usize]>,
+}
+impl<T: Float> OptimizeBuffers<T> {
+    pub fn new(weights_len: usize, time_steps_len: usize) -> Self {
+        OptimizeBuffers {
+            gradient: vec![T::zero(); weights_len].into_boxed_slice(),
+            tmp_gradient: vec![T::zero(); weights_len].into_boxed_slice(),
+            indices: (0..time_steps_len).collect(),
+        }
+    }
+}
+/// What `Optimizer::optimize` did. A minibatch whose objective or gradient is not finite is
+/// skipped, so that a single diverging sample does not turn the weights into NaN.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct OptimizeReport {
+    pub steps: usize,
+    /// Number of minibatches skipped because of a NaN or infinite objective or gradient
+    pub skipped: usize,
+    /// Number of steps whose gradient was rescaled to `max_norm`
+    pub clipped: usize,
+}
+impl OptimizeReport {
+    pub fn is_finite(&self) -> bool {
+        self.skipped == 0
+    }
+}
+impl std::ops::AddAssign for OptimizeReport {
+    fn add_assign(&mut self, rhs: Self) {
+        self.steps += rhs.steps;
+        self.skipped += rhs.skipped;
+        self.clipped += rhs.clipped;
+    }
+}
+/// Statistics of one epoch of `Optimizer::optimize`, over the minibatches that were not skipped.
+/// The objective is the one of `Gradient::objective`, whatever the direction, and the norm is the
+/// one of the normalized gradient before clipping.
+#[derive(Clone, Copy, Debug)]
+pub struct EpochStats<T: Float> {
+    pub report: OptimizeReport,
+    pub mean_objective: T,
+    pub mean_gradient_norm: T,
+}
+/// Summary of a call to `Optimizer::optimize`, the first and last epochs being `None` when no
+/// epoch was done.
+#[derive(Clone, Copy, Debug)]
+pub struct OptimizeStats<T: Float> {
+    /// Counts cumulated over all the epochs
+    pub total: OptimizeReport,
+    pub epochs: usize,
+    pub first: Option<EpochStats<T>>,
+    pub last: Option<EpochStats<T>>,
+    /// The early stopping returned `true`
+    pub stopped_early: bool,
+}
+impl<T: Float> Default for OptimizeStats<T> {
+    fn default() -> Self {
+        OptimizeStats {
+            total: OptimizeReport::default(),
+            epochs: 0,
+            first: None,
+            last: None,
+            stopped_early: false,
+        }
+    }
+}
 pub trait Optimizer<T: Float> {
     /// This function is supposed to perform gradient descent
     fn step(&mut self, weights: &mut [T], gradient: &mut [T]);
-    fn optimize<G: Gradient<T>>(
+    fn learning_rate(&self) -> T;
+    /// Change the learning rate for the next steps, see `schedule::Scheduled`
+    fn set_learning_rate(&mut self, alpha: T);
+    /// Snapshot of the internal state, to be saved in a checkpoint
+    fn state(&self) -> OptimizerState<T>;
+    /// Restore a snapshot taken by `state`, such that the next steps are the same as if the
+    /// optimization had never been interrupted.
+    fn load_state(&mut self, state: &OptimizerState<T>) -> Result<(), LoadError>;
+    /// A single `step` on a gradient computed by the caller, with the same safeguards as
+    /// `optimize`: a gradient which is not finite is skipped, otherwise it is clipped by `options`.
+    fn guarded_step<E>(
         &mut self,
-        epochs: usize,
-        minibatch_size: usize,
-        to_optimize: &mut G,
         weights: &mut [T],
         gradient: &mut [T],
-        tmp_gradient: &mut [T],
-        time_steps: &mut [TimeStep<T>],
-        direction: Direction,
-    ) {
+        options: &OptimizeOptions<T, E>,
+    ) -> OptimizeReport {
+        let mut report = OptimizeReport::default();
+        if !gradient.iter().all(|g| g.is_finite()) {
+            report.skipped += 1;
+            return report;
+        }
+        if options.clip(gradient) {
+            report.clipped += 1;
+        }
+        self.step(weights, gradient);
+        report.steps += 1;
+        report
+    }
+    /// Do `options.epochs` passes over `time_steps` in minibatches of `options.minibatch_size`, in
+    /// a random order. After each epoch, `options.early_stop` can end the optimization.
+    fn optimize<G: Gradient<T>, S: TimeSteps<T> + ?Sized, E: EarlyStop<T, G, S>>(
+        &mut self,
+        to_optimize: &mut G,
+        weights: &mut [T],
+        time_steps: &mut S,
+        buffers: &mut OptimizeBuffers<T>,
+        mut options: OptimizeOptions<T, E>,
+        rng: &mut impl Rng,
+    ) -> OptimizeStats<T> {
+        let minibatch_size = options.minibatch_size;
         debug_assert!(minibatch_size > 0, "minibatch size must be positive");
-        let ascent_sign = match direction {
+        debug_assert!(
+            buffers.gradient.len() == weights.len(),
+            "the buffers must be allocated for the weights"
+        );
+        debug_assert!(
+            buffers.indices.len() == time_steps.len(),
+            "the buffers must be allocated for the time steps"
+        );
+        let OptimizeBuffers {
+            gradient,
+            tmp_gradient,
+            indices,
+        } = buffers;
+        let ascent_sign = match options.direction {
             Direction::Ascent => -T::one(),
             Direction::Descent => T::one(),
         };
+        let used = if options.include_remainder {
+            indices.len()
+        } else {
+            indices.len() - indices.len() % minibatch_size
+        };
 
-        let mut rng = rand::rng();
-
-        let recip_minibatch_size = T::from(minibatch_size).unwrap().recip();
-        let normalization = ascent_sign * recip_minibatch_size;
-        for _ in 0..epochs {
-            time_steps.shuffle(&mut rng); // FIXME: it might be more efficient to have an array of indices and shuffle that array instead of the array of time_steps. However, this array would have to be provided as &mut [usize] in order to avoid allocation in this part for later GPU switching 
-            for minibatch in time_steps.chunks_exact_mut(minibatch_size) {
+        let mut stats = OptimizeStats::default();
+        for _ in 0..options.epochs {
+            // NOTE: the indices stay a permutation of the time steps from one call to the next
+            indices.shuffle(rng);
+            let mut report = OptimizeReport::default();
+            let (mut objective_sum, mut samples, mut norm_sum) = (T::zero(), 0, T::zero());
+            for minibatch in indices[..used].chunks(minibatch_size) {
                 gradient.iter_mut().for_each(|g| *g = T::zero());
-                for TimeStep { input, state } in minibatch {
+                let mut objective = T::zero();
+                for &i in minibatch {
+                    let TimeStep { input, state } = time_steps.get_mut(i);
                     to_optimize.eval(input, weights, state);
                     to_optimize.compute_gradient(input, weights, state, tmp_gradient);
+                    objective = objective + to_optimize.objective(state);
                     gradient
                         .iter_mut()
                         .zip(tmp_gradient.iter())
                         .for_each(|(g, tmp_g)| *g = *g + *tmp_g);
                 }
+                let normalization = ascent_sign / T::from(minibatch.len()).unwrap();
                 gradient.iter_mut().for_each(|g| *g = *g * normalization);
+                if !objective.is_finite() || !gradient.iter().all(|g| g.is_finite()) {
+                    report.skipped += 1;
+                    continue;
+                }
+                objective_sum = objective_sum + objective;
+                samples += minibatch.len();
+                norm_sum = norm_sum
+                    + gradient
+                        .iter()
+                        .fold(T::zero(), |acc, &g| acc + g * g)
+                        .sqrt();
+                if options.clip(gradient) {
+                    report.clipped += 1;
+                }
                 self.step(weights, gradient);
+                report.steps += 1;
+            }
+            // NOTE: the means are zero for an epoch without any step
+            let mean = |sum: T, n: usize| sum / T::from(n.max(1)).unwrap();
+            let epoch = EpochStats {
+                report,
+                mean_objective: mean(objective_sum, samples),
+                mean_gradient_norm: mean(norm_sum, report.steps),
+            };
+            stats.total += report;
+            stats.epochs += 1;
+            stats.first.get_or_insert(epoch);
+            stats.last = Some(epoch);
+            if options.early_stop.stop(to_optimize, &epoch, time_steps) {
+                stats.stopped_early = true;
+                break;
             }
         }
+        stats
     }
 }
@@ -60,8 +60,8 @@ impl<T: Float> BackProp<T> for Id {
         debug_assert!(weights.len() == 0, "Id, weights");
         debug_assert!(state.len() == self.inputs, "Id state");
         debug_assert!(gradient.len() == 0, "Id gradient");
-        debug_assert!(front.len() == self.inputs, "Id front");
-        debug_assert!(back.len() == self.inputs, "Id back");
+        debug_assert!(front.len() >= self.inputs, "Id front");
+        debug_assert!(back.len() >= self.inputs, "Id back");
         front
             .iter_mut()
             .zip(input.iter())
@@ -73,4 +73,7 @@ impl<T: Float> Activation<T> for Id {
     fn range(&self) -> (Option<T>, Option<T>) {
         (None, None)
     }
+    fn name(&self) -> &'static str {
+        "id"
+    }
 }
@@ -60,8 +60,8 @@ impl<T: Float> BackProp<T> for ReLu {
         debug_assert!(weights.len() == 0, "ReLu, weights");
         debug_assert!(state.len() == self.inputs, "ReLu state");
         debug_assert!(gradient.len() == 0, "ReLu gradient");
-        debug_assert!(front.len() == self.inputs, "ReLu front");
-        debug_assert!(back.len() == self.inputs, "ReLu back");
+        debug_assert!(front.len() >= self.inputs, "ReLu front");
+        debug_assert!(back.len() >= self.inputs, "ReLu back");
         front
             .iter_mut()
             .zip(input.iter())
@@ -73,4 +73,7 @@ impl<T: Float> Activation<T> for ReLu {
     fn range(&self) -> (Option<T>, Option<T>) {
         (Some(T::zero()), None)
     }
+    fn name(&self) -> &'static str {
+        "relu"
+    }
 }
@@ -60,8 +60,8 @@ impl<T: Float> BackProp<T> for Tanh {
         debug_assert!(weights.len() == 0, "Tanh, weights");
         debug_assert!(state.len() == self.inputs, "Tanh state");
         debug_assert!(gradient.len() == 0, "Tanh gradient");
-        debug_assert!(front.len() == self.inputs, "Tanh front");
-        debug_assert!(back.len() == self.inputs, "Tanh back");
+        debug_assert!(front.len() >= self.inputs, "Tanh front");
+        debug_assert!(back.len() >= self.inputs, "Tanh back");
         front
             .iter_mut()
             .zip(input.iter())
@@ -73,4 +73,7 @@ impl<T: Float> Activation<T> for Tanh {
     fn range(&self) -> (Option<T>, Option<T>) {
         (None, None)
     }
+    fn name(&self) -> &'static str {
+        "tanh"
+    }
 }
@@ -0,0 +1,183 @@
+use num::Float;
+
+use super::Value;
+
+/// Discounted returns $R_t = r_t + \gamma (1 - d_t) R_{t+1}$ where the return after the last step is `bootstrap`.
+pub fn discounted_returns<T: Float>(
+    rewards: &[T],
+    dones: &[bool],
+    bootstrap: T,
+    gamma: T,
+    returns: &mut [T],
+) {
+    debug_assert!(rewards.len() == dones.len() && rewards.len() == returns.len());
+    let mut next = bootstrap;
+    for ((ret, &r), &done) in returns.iter_mut().zip(rewards).zip(dones).rev() {
+        if done {
+            next = T::zero();
+        }
+        *ret = r + gamma * next;
+        next = *ret;
+    }
+}
+
+/// Returns that sum at most `n` discounted rewards before bootstrapping on `values`, or on
+/// `bootstrap` for the value after the last step. The sum stops early at the end of an episode.
+pub fn n_step_returns<T: Float>(
+    rewards: &[T],
+    dones: &[bool],
+    values: &[T],
+    bootstrap: T,
+    gamma: T,
+    n: usize,
+    returns: &mut [T],
+) {
+    debug_assert!(n > 0, "n must be positive");
+    debug_assert!(
+        rewards.len() == dones.len()
+            && rewards.len() == values.len()
+            && rewards.len() == returns.len()
+    );
+    let len = rewards.len();
+    for (t, ret) in returns.iter_mut().enumerate() {
+        let mut acc = T::zero();
+        let mut discount = T::one();
+        let mut k = t;
+        let mut done = false;
+        while k < len && k < t + n {
+            acc = acc + discount * rewards[k];
+            discount = discount * gamma;
+            done = dones[k];
+            k += 1;
+            if done {
+                break;
+            }
+        }
+        if !done {
+            let next_value = if k < len { values[k] } else { bootstrap };
+            acc = acc + discount * next_value;
+        }
+        *ret = acc;
+    }
+}
+
+/// Rescale the advantages to zero mean and unit standard deviation.
+pub fn normalize<T: Float>(advantages: &mut [T]) {
+    let n = T::from(advantages.len().max(1)).unwrap();
+    let mean = advantages.iter().fold(T::zero(), |acc, &a| acc + a) / n;
+    let variance = advantages
+        .iter()
+        .fold(T::zero(), |acc, &a| acc + (a - mean).powi(2))
+        / n;
+    let std = variance.sqrt() + T::epsilon();
+    advantages.iter_mut().for_each(|a| *a = (*a - mean) / std);
+}
+
+/// Read the predictions of `value` from consecutive value states.
+pub fn values<T: Float, V: Value<T>>(value: &V, value_states: &[T], values: &mut [T]) {
+    debug_assert!(value_states.len() == values.len() * value.state_len());
+    values
+        .iter_mut()
+        .zip(value_states.chunks(value.state_len()))
+        .for_each(|(v, state)| *v = value.value(state));
+}
+
+/// Write the regression targets of `value` in consecutive value states.
+pub fn set_targets<T: Float, V: Value<T>>(value: &mut V, value_states: &mut [T], targets: &[T]) {
+    debug_assert!(value_states.len() == targets.len() * value.state_len());
+    let state_len = value.state_len();
+    value_states
+        .chunks_mut(state_len)
+        .zip(targets.iter())
+        .for_each(|(state, &target)| value.set_target(target, state));
+}
+
+/// Generalized Advantage Estimation GAE(λ).
+pub struct Gae<T: Float> {
+    gamma: T,
+    lambda: T,
+    normalize: bool,
+}
+
+impl<T: Float> Gae<T> {
+    pub fn new(gamma: T, lambda: T) -> Self {
+        Gae {
+            gamma,
+            lambda,
+            normalize: false,
+        }
+    }
+    pub fn with_normalization(mut self, normalize: bool) -> Self {
+        self.normalize = normalize;
+        self
+    }
+    pub fn gamma(&self) -> T {
+        self.gamma
+    }
+    pub fn lambda(&self) -> T {
+        self.lambda
+    }
+    /// Compute the advantages of consecutive steps from the predicted `values`, where `bootstrap` is the value after the last step.
+    pub fn advantages(
+        &self,
+        rewards: &[T],
+        dones: &[bool],
+        values: &[T],
+        bootstrap: T,
+        advantages: &mut [T],
+    ) {
+        debug_assert!(values.len() == advantages.len());
+        advantages.copy_from_slice(values);
+        self.advantages_in_place(rewards, dones, bootstrap, advantages);
+        if self.normalize {
+            normalize(advantages);
+        }
+    }
+    /// Same as `advantages` but the values are read from consecutive value states, in which the
+    /// returns (advantage plus value) are then written as the regression targets.
+    /// The targets are computed before the optional normalization of the advantages.
+    pub fn estimate<V: Value<T>>(
+        &self,
+        value: &mut V,
+        value_states: &mut [T],
+        rewards: &[T],
+        dones: &[bool],
+        bootstrap: T,
+        advantages: &mut [T],
+    ) {
+        values(value, value_states, advantages);
+        self.advantages_in_place(rewards, dones, bootstrap, advantages);
+        let state_len = value.state_len();
+        value_states
+            .chunks_mut(state_len)
+            .zip(advantages.iter())
+            .for_each(|(state, &a)| value.set_target(a + value.value(state), state));
+        if self.normalize {
+            normalize(advantages);
+        }
+    }
+    /// GAE recursion where `advantages` holds the values on entry. The value of a step is read
+    /// before its advantage overwrites it, as the recursion goes backward.
+    fn advantages_in_place(
+        &self,
+        rewards: &[T],
+        dones: &[bool],
+        bootstrap: T,
+        advantages: &mut [T],
+    ) {
+        debug_assert!(rewards.len() == dones.len() && rewards.len() == advantages.len());
+        let mut gae = T::zero();
+        let mut next_value = bootstrap;
+        for ((a, &r), &done) in advantages.iter_mut().zip(rewards).zip(dones).rev() {
+            if done {
+                gae = T::zero();
+                next_value = T::zero();
+            }
+            let value = *a;
+            let delta = r + self.gamma * next_value - value;
+            gae = delta + self.gamma * self.lambda * gae;
+            *a = gae;
+            next_value = value;
+        }
+    }
+}
@@ -0,0 +1,130 @@
+use std::path::Path;
+
+use num::Float;
+
+use super::{
+    mlp::MLP,
+    serialize::{ActivationRegistry, Decoder, Encoder, LoadError},
+};
+
+const CHECKPOINT_MAGIC: [u8; 4] = *b"RCKP";
+
+/// Everything an optimizer needs to resume where it stopped: its hyperparameters and running
+/// products in `scalars`, the integer counters which must be restored exactly in `counters`, and
+/// its per weight buffers such as the moments in `buffers`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct OptimizerState<T: Float> {
+    pub name: String,
+    pub scalars: Vec<T>,
+    pub counters: Vec<u64>,
+    pub buffers: Vec<Box<[T]>>,
+}
+
+impl<T: Float> OptimizerState<T> {
+    /// Verify that the state was produced by the optimizer `name` with `scalars` scalars and
+    /// `buffers` buffers of `buffer_len` values, so that it can be copied back without any further
+    /// check.
+    pub fn check(
+        &self,
+        name: &str,
+        scalars: usize,
+        buffers: usize,
+        buffer_len: usize,
+    ) -> Result<(), LoadError> {
+        if self.name != name {
+            return Err(LoadError::WrongOptimizer {
+                expected: name.to_string(),
+                found: self.name.clone(),
+            });
+        }
+        if self.scalars.len() != scalars {
+            return Err(LoadError::StateLen {
+                what: "scalars",
+                expected: scalars,
+                found: self.scalars.len(),
+            });
+        }
+        if self.buffers.len() != buffers {
+            return Err(LoadError::StateLen {
+                what: "buffers",
+                expected: buffers,
+                found: self.buffers.len(),
+            });
+        }
+        match self.buffers.iter().find(|b| b.len() != buffer_len) {
+            Some(b) => Err(LoadError::WeightsLen {
+                expected: buffer_len,
+                found: b.len(),
+            }),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Content of a checkpoint file: the models with their weights and the states of the optimizers,
+/// in the order they were given to `to_bytes`.
+pub struct Checkpoint<T: Float> {
+    pub models: Vec<(MLP<T>, Box<[T]>)>,
+    pub optimizers: Vec<OptimizerState<T>>,
+}
+
+/// Binary encoding with the same header and checksum as `serialize::to_bytes`, but the magic
+/// bytes `RCKP`.
+pub fn to_bytes<T: Float>(models: &[(&MLP<T>, &[T])], optimizers: &[OptimizerState<T>]) -> Vec<u8> {
+    let mut encoder = Encoder::new(CHECKPOINT_MAGIC);
+    encoder.u64(models.len() as u64);
+    models
+        .iter()
+        .for_each(|(mlp, weights)| encoder.mlp(mlp, weights));
+    encoder.u64(optimizers.len() as u64);
+    for state in optimizers {
+        encoder.str(&state.name);
+        encoder.floats(&state.scalars);
+        encoder.u64(state.counters.len() as u64);
+        state.counters.iter().for_each(|&c| encoder.u64(c));
+        encoder.u64(state.buffers.len() as u64);
+        state.buffers.iter().for_each(|b| encoder.floats(b));
+    }
+    encoder.finish()
+}
+
+pub fn from_bytes<T: Float>(
+    bytes: &[u8],
+    registry: &ActivationRegistry<T>,
+) -> Result<Checkpoint<T>, LoadError> {
+    let mut decoder = Decoder::new(CHECKPOINT_MAGIC, bytes)?;
+    let models = (0..decoder.count()?)
+        .map(|_| decoder.mlp(registry))
+        .collect::<Result<_, _>>()?;
+    let optimizers = (0..decoder.count()?)
+        .map(|_| {
+            Ok(OptimizerState {
+                name: decoder.str()?.to_string(),
+                scalars: decoder.floats()?.into_vec(),
+                counters: (0..decoder.count()?)
+                    .map(|_| decoder.u64())
+                    .collect::<Result<_, _>>()?,
+                buffers: (0..decoder.count()?)
+                    .map(|_| decoder.floats())
+                    .collect::<Result<_, _>>()?,
+            })
+        })
+        .collect::<Result<_, LoadError>>()?;
+    decoder.finish()?;
+    Ok(Checkpoint { models, optimizers })
+}
+
+pub fn save<T: Float>(
+    path: impl AsRef<Path>,
+    models: &[(&MLP<T>, &[T])],
+    optimizers: &[OptimizerState<T>],
+) -> std::io::Result<()> {
+    std::fs::write(path, to_bytes(models, optimizers))
+}
+
+pub fn load<T: Float>(
+    path: impl AsRef<Path>,
+    registry: &ActivationRegistry<T>,
+) -> Result<Checkpoint<T>, LoadError> {
+    from_bytes(&std::fs::read(path)?, registry)
+}
@@ -0,0 +1,367 @@
+use num::Float;
+use rand::{Rng, SeedableRng, rngs::StdRng};
+
+use super::{
+    BackProp, Direction, Eval, Gradient, OptimizeBuffers, OptimizeOptions, OptimizeReport,
+    OptimizeStats, Optimizer, Weights,
+    mlp::MLP,
+    replay::{Batch, ReplayBuffer},
+};
+
+/// Huber loss between the Q-value of the action taken and the TD target, scaled by an importance
+/// sampling weight. The MLP outputs one Q-value per action.
+/// State: MLP state | back | front | action | target | weight
+pub struct TdLoss<T: Float> {
+    mlp: MLP<T>,
+    delta: T,
+}
+
+impl<T: Float> TdLoss<T> {
+    pub fn new(mlp: MLP<T>) -> Self {
+        TdLoss {
+            mlp,
+            delta: T::one(),
+        }
+    }
+    /// Threshold of the Huber loss above which it is linear instead of quadratic
+    pub fn with_delta(mut self, delta: T) -> Self {
+        self.delta = delta;
+        self
+    }
+    pub fn mlp(&self) -> &MLP<T> {
+        &self.mlp
+    }
+    pub fn action_count(&self) -> usize {
+        self.mlp.output_len()
+    }
+    pub fn q_values<'a>(&self, state: &'a [T]) -> &'a [T] {
+        self.output(state)
+    }
+    pub fn action(&self, state: &[T]) -> usize {
+        state[self.state_len() - 3].to_usize().unwrap()
+    }
+    pub fn set_action(&self, action: usize, state: &mut [T]) {
+        state[self.state_len() - 3] = T::from(action).unwrap();
+    }
+    pub fn target(&self, state: &[T]) -> T {
+        state[self.state_len() - 2]
+    }
+    pub fn set_target(&self, target: T, state: &mut [T]) {
+        state[self.state_len() - 2] = target;
+    }
+    pub fn weight(&self, state: &[T]) -> T {
+        state[self.state_len() - 1]
+    }
+    pub fn set_weight(&self, weight: T, state: &mut [T]) {
+        state[self.state_len() - 1] = weight;
+    }
+    /// $Q(s, a) - y$
+    pub fn td_error(&self, state: &[T]) -> T {
+        self.q_values(state)[self.action(state)] - self.target(state)
+    }
+}
+
+impl<T: Float> Weights<T> for TdLoss<T> {
+    fn weights_len(&self) -> usize {
+        self.mlp.weights_len()
+    }
+}
+impl<T: Float> Eval<T> for TdLoss<T> {
+    fn state_len(&self) -> usize {
+        self.mlp.state_len() + 2 * self.mlp.min_back_front_len() + 3
+    }
+    fn eval(&self, input: &[T], weights: &[T], state: &mut [T]) {
+        self.mlp
+            .eval(input, weights, &mut state[..self.mlp.state_len()]);
+    }
+    fn input_len(&self) -> usize {
+        self.mlp.input_len()
+    }
+    fn output_len(&self) -> usize {
+        self.mlp.output_len()
+    }
+    fn output<'a>(&self, state: &'a [T]) -> &'a [T] {
+        self.mlp.output(&state[..self.mlp.state_len()])
+    }
+    fn output_mut<'a>(&self, state: &'a mut [T]) -> &'a mut [T] {
+        self.mlp.output_mut(&mut state[..self.mlp.state_len()])
+    }
+}
+impl<T: Float> Gradient<T> for TdLoss<T> {
+    fn compute_gradient(&self, input: &[T], weights: &[T], state: &mut [T], gradient: &mut [T]) {
+        let action = self.action(state);
+        let d = self.td_error(state).max(-self.delta).min(self.delta) * self.weight(state);
+        let (state, tmp) = state.split_at_mut(self.mlp.state_len());
+        let (back, tmp) = tmp.split_at_mut(self.mlp.min_back_front_len());
+        let front = &mut tmp[..self.mlp.min_back_front_len()];
+        back[..self.mlp.output_len()]
+            .iter_mut()
+            .for_each(|b| *b = T::zero());
+        back[action] = d;
+        self.mlp
+            .back_prop(input, weights, state, front, back, gradient);
+    }
+    fn objective(&self, state: &[T]) -> T {
+        let error = self.td_error(state).abs();
+        let huber = if error <= self.delta {
+            error * error / (T::one() + T::one())
+        } else {
+            self.delta * (error - self.delta / (T::one() + T::one()))
+        };
+        self.weight(state) * huber
+    }
+}
+
+/// How the target weights follow the online weights.
+#[derive(Clone, Copy, Debug)]
+pub enum TargetUpdate<T: Float> {
+    /// Copy the online weights every `period` updates
+    Hard { period: usize },
+    /// $\theta' \leftarrow \tau \theta + (1 - \tau) \theta'$ after every update
+    Polyak { tau: T },
+}
+
+/// Deep Q-Network with experience replay, target weights and epsilon-greedy exploration.
+pub struct Dqn<T: Float, O: Optimizer<T>> {
+    loss: TdLoss<T>,
+    optimizer: O,
+    weights: Box<[T]>,
+    target_weights: Box<[T]>,
+    buffers: OptimizeBuffers<T>,
+    replay: ReplayBuffer<T>,
+    batch: Batch<T>,
+    td_errors: Box<[T]>,
+    q_state: Box<[T]>,
+    target_q_state: Box<[T]>,
+    gamma: T,
+    double: bool,
+    target_update: TargetUpdate<T>,
+    epsilon_start: T,
+    epsilon_end: T,
+    epsilon_decay_steps: usize,
+    steps: usize,
+    updates: usize,
+    rng: StdRng,
+    optimize_options: OptimizeOptions<T>,
+    report: OptimizeReport,
+    stats: OptimizeStats<T>,
+}
+
+impl<T: Float, O: Optimizer<T>> Dqn<T, O> {
+    pub fn new(loss: TdLoss<T>, optimizer: O, replay: ReplayBuffer<T>, batch_size: usize) -> Self {
+        debug_assert!(
+            replay.observation_len() == loss.input_len() && replay.action_len() == 1,
+            "The replay buffer must store the observations of the Q-network and the action index"
+        );
+        Dqn {
+            weights: loss.empty_weights(),
+            target_weights: loss.empty_weights(),
+            buffers: OptimizeBuffers::new(loss.weights_len(), batch_size),
+            batch: Batch::new(batch_size, loss.input_len(), loss.state_len()),
+            td_errors: vec![T::zero(); batch_size].into_boxed_slice(),
+            q_state: loss.mlp().empty_state(),
+            target_q_state: loss.mlp().empty_state(),
+            gamma: T::from(0.99).unwrap(),
+            double: false,
+            target_update: TargetUpdate::Hard { period: 1000 },
+            epsilon_start: T::one(),
+            epsilon_end: T::from(0.05).unwrap(),
+            epsilon_decay_steps: 10000,
+            steps: 0,
+            updates: 0,
+            rng: StdRng::from_os_rng(),
+            optimize_options: OptimizeOptions::default(),
+            report: OptimizeReport::default(),
+            stats: OptimizeStats::default(),
+            loss,
+            optimizer,
+            replay,
+        }
+    }
+    pub fn with_gamma(mut self, gamma: T) -> Self {
+        self.gamma = gamma;
+        self
+    }
+    /// Choose the next action with the online weights and evaluate it with the target weights
+    pub fn with_double_q(mut self, double: bool) -> Self {
+        self.double = double;
+        self
+    }
+    pub fn with_target_update(mut self, target_update: TargetUpdate<T>) -> Self {
+        self.target_update = target_update;
+        self
+    }
+    /// Linear decay of epsilon from `start` to `end` over `decay_steps` calls to `act`
+    pub fn with_epsilon(mut self, start: T, end: T, decay_steps: usize) -> Self {
+        self.epsilon_start = start;
+        self.epsilon_end = end;
+        self.epsilon_decay_steps = decay_steps;
+        self
+    }
+    /// Seed the exploration, the sampling of the batches and their shuffling, for reproducible runs
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.rng = StdRng::seed_from_u64(seed);
+        self
+    }
+    /// Gradient clipping of every update
+    pub fn with_optimize_options(mut self, options: OptimizeOptions<T>) -> Self {
+        self.optimize_options = options;
+        self
+    }
+    /// Steps, skipped minibatches and clipped gradients cumulated over all the updates
+    pub fn report(&self) -> OptimizeReport {
+        self.report
+    }
+    /// Loss and gradient norm of the last update
+    pub fn last_stats(&self) -> OptimizeStats<T> {
+        self.stats
+    }
+    pub fn loss(&self) -> &TdLoss<T> {
+        &self.loss
+    }
+    pub fn weights(&self) -> &[T] {
+        &self.weights
+    }
+    /// Call `sync_target` after modifying the weights directly, for instance at initialization.
+    pub fn weights_mut(&mut self) -> &mut [T] {
+        &mut self.weights
+    }
+    pub fn target_weights(&self) -> &[T] {
+        &self.target_weights
+    }
+    pub fn optimizer(&self) -> &O {
+        &self.optimizer
+    }
+    pub fn optimizer_mut(&mut self) -> &mut O {
+        &mut self.optimizer
+    }
+    pub fn replay(&self) -> &ReplayBuffer<T> {
+        &self.replay
+    }
+    pub fn sync_target(&mut self) {
+        self.target_weights.copy_from_slice(&self.weights);
+    }
+    pub fn epsilon(&self) -> T {
+        let progress = T::from(self.steps.min(self.epsilon_decay_steps)).unwrap()
+            / T::from(self.epsilon_decay_steps.max(1)).unwrap();
+        self.epsilon_start + (self.epsilon_end - self.epsilon_start) * progress
+    }
+    pub fn greedy_action(&mut self, observation: &[T]) -> usize {
+        self.loss
+            .mlp()
+            .eval(observation, &self.weights, &mut self.q_state);
+        argmax(self.loss.mlp().output(&self.q_state))
+    }
+    /// Epsilon-greedy action, which also advances the epsilon decay
+    pub fn act(&mut self, observation: &[T]) -> usize {
+        let epsilon = self.epsilon().to_f64().unwrap();
+        self.steps += 1;
+        if self.rng.random::<f64>() < epsilon {
+            self.rng.random_range(0..self.loss.action_count())
+        } else {
+            self.greedy_action(observation)
+        }
+    }
+    pub fn store(
+        &mut self,
+        observation: &[T],
+        action: usize,
+        reward: T,
+        next_observation: &[T],
+        done: bool,
+    ) {
+        self.replay.push(
+            observation,
+            &[T::from(action).unwrap()],
+            reward,
+            next_observation,
+            done,
+        );
+    }
+
+    /// Do one gradient step on a batch sampled from the replay buffer, then update the priorities
+    /// of the batch and the target weights. Nothing is done until the replay buffer holds a batch.
+    pub fn update(&mut self) {
+        if self.replay.len() < self.batch.len() {
+            return;
+        }
+        let Dqn {
+            loss,
+            weights,
+            target_weights,
+            replay,
+            batch,
+            td_errors,
+            q_state,
+            target_q_state,
+            gamma,
+            double,
+            rng,
+            ..
+        } = self;
+        let mlp = loss.mlp();
+        let mut k = 0;
+        replay.sample(batch, rng, |transition, weight, input, state| {
+            let next_q = if transition.done {
+                T::zero()
+            } else {
+                mlp.eval(transition.next_observation, target_weights, target_q_state);
+                let target_q = mlp.output(target_q_state);
+                let next_action = if *double {
+                    mlp.eval(transition.next_observation, weights, q_state);
+                    argmax(mlp.output(q_state))
+                } else {
+                    argmax(target_q)
+                };
+                target_q[next_action]
+            };
+            input.copy_from_slice(transition.observation);
+            loss.set_action(transition.action[0].to_usize().unwrap(), state);
+            loss.set_target(transition.reward + *gamma * next_q, state);
+            loss.set_weight(weight, state);
+            loss.eval(input, weights, state);
+            td_errors[k] = loss.td_error(state);
+            k += 1;
+        });
+        let options = self
+            .optimize_options
+            .with_minibatch_size(batch.len())
+            .with_direction(Direction::Descent);
+        let mut time_steps = batch.time_steps();
+        self.stats = self.optimizer.optimize(
+            &mut self.loss,
+            &mut self.weights,
+            &mut time_steps,
+            &mut self.buffers,
+            options,
+            &mut self.rng,
+        );
+        self.report += self.stats.total;
+        self.replay
+            .update_priorities(self.batch.indices(), &self.td_errors);
+
+        self.updates += 1;
+        match self.target_update {
+            TargetUpdate::Hard { period } => {
+                if self.updates.is_multiple_of(period.max(1)) {
+                    self.sync_target();
+                }
+            }
+            TargetUpdate::Polyak { tau } => self
+                .target_weights
+                .iter_mut()
+                .zip(self.weights.iter())
+                .for_each(|(t, &w)| *t = tau * w + (T::one() - tau) * *t),
+        }
+    }
+}
+
+fn argmax<T: Float>(values: &[T]) -> usize {
+    values
+        .iter()
+        .enumerate()
+        .fold((0, T::neg_infinity()), |(best, max), (i, &v)| {
+            if v > max { (i, v) } else { (best, max) }
+        })
+        .0
+}
@@ -0,0 +1,292 @@
+use num::Float;
+use rand::Rng;
+
+use super::{StochasticPolicy, TimeStepChunks};
+
+/// Outcome of `Env::step`.
+#[derive(Clone, Copy, Debug)]
+pub struct Step<T: Float> {
+    pub reward: T,
+    /// The episode reached a terminal state, so there is nothing to bootstrap on
+    pub terminated: bool,
+    /// The episode was cut, for instance by a time limit, so the value of the next observation still matters
+    pub truncated: bool,
+}
+
+impl<T: Float> Step<T> {
+    pub fn done(&self) -> bool {
+        self.terminated || self.truncated
+    }
+}
+
+pub trait Env<T: Float> {
+    fn observation_len(&self) -> usize;
+    fn action_len(&self) -> usize;
+    /// Start a new episode and write its first observation.
+    fn reset(&mut self, observation: &mut [T]);
+    /// Apply the action and write the next observation.
+    fn step(&mut self, action: &[T], observation: &mut [T]) -> Step<T>;
+}
+
+/// Preallocated storage of `horizon` time steps for each of `env_count` environments.
+/// The time steps of an environment are contiguous, the step `t` of the environment `e` being at
+/// the index `e * horizon + t`. The episodes continue from one call to `collect` to the next, and an
+/// environment is reset as soon as its episode is done. With `collect_episode` instead, only the
+/// first `recorded_len(e)` steps of the environment `e` are recorded.
+pub struct RolloutBuffer<T: Float> {
+    env_count: usize,
+    horizon: usize,
+    observation_len: usize,
+    action_len: usize,
+    state_len: usize,
+    inputs: Box<[T]>,
+    states: Box<[T]>,
+    rewards: Box<[T]>,
+    dones: Box<[bool]>,
+    truncated: Box<[bool]>,
+    /// Observation after each truncated step, as the environment is reset right after
+    final_observations: Box<[T]>,
+    /// Current observation of each environment, which is the observation after the last recorded step
+    observations: Box<[T]>,
+    needs_reset: Box<[bool]>,
+    action: Box<[T]>,
+    /// Number of recorded steps of each environment
+    lens: Box<[usize]>,
+    /// Indices of the recorded steps, rebuilt by `time_steps` without allocation
+    recorded: Vec<usize>,
+}
+
+impl<T: Float> RolloutBuffer<T> {
+    /// `state_len` is the length of the state of the component evaluated at each time step,
+    /// for instance a policy or `ppo::ClippedSurrogate`.
+    pub fn new(
+        env_count: usize,
+        horizon: usize,
+        observation_len: usize,
+        action_len: usize,
+        state_len: usize,
+    ) -> Self {
+        let steps = env_count * horizon;
+        RolloutBuffer {
+            env_count,
+            horizon,
+            observation_len,
+            action_len,
+            state_len,
+            inputs: vec![T::zero(); steps * observation_len].into_boxed_slice(),
+            states: vec![T::zero(); steps * state_len].into_boxed_slice(),
+            rewards: vec![T::zero(); steps].into_boxed_slice(),
+            dones: vec![false; steps].into_boxed_slice(),
+            truncated: vec![false; steps].into_boxed_slice(),
+            final_observations: vec![T::zero(); steps * observation_len].into_boxed_slice(),
+            observations: vec![T::zero(); env_count * observation_len].into_boxed_slice(),
+            needs_reset: vec![true; env_count].into_boxed_slice(),
+            action: vec![T::zero(); action_len].into_boxed_slice(),
+            lens: vec![horizon; env_count].into_boxed_slice(),
+            recorded: Vec::with_capacity(steps),
+        }
+    }
+    pub fn env_count(&self) -> usize {
+        self.env_count
+    }
+    pub fn horizon(&self) -> usize {
+        self.horizon
+    }
+    pub fn len(&self) -> usize {
+        self.env_count * self.horizon
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    /// Force every environment to be reset at the beginning of the next `collect`.
+    pub fn reset(&mut self) {
+        self.needs_reset.iter_mut().for_each(|r| *r = true);
+    }
+
+    /// Run `horizon` steps in each environment. `act(input, state, action)` must fill the state of
+    /// the time step and write the action to apply.
+    pub fn collect<E: Env<T>>(
+        &mut self,
+        envs: &mut [E],
+        mut act: impl FnMut(&[T], &mut [T], &mut [T]),
+    ) {
+        debug_assert!(envs.len() == self.env_count, "env count");
+        let (observation_len, state_len) = (self.observation_len, self.state_len);
+        for (e, env) in envs.iter_mut().enumerate() {
+            debug_assert!(
+                env.observation_len() == observation_len && env.action_len() == self.action_len,
+                "env dimensions"
+            );
+            let observation = &mut self.observations[e * observation_len..][..observation_len];
+            for t in 0..self.horizon {
+                if self.needs_reset[e] {
+                    env.reset(observation);
+                    self.needs_reset[e] = false;
+                }
+                let i = e * self.horizon + t;
+                let input = &mut self.inputs[i * observation_len..][..observation_len];
+                input.copy_from_slice(observation);
+                act(
+                    input,
+                    &mut self.states[i * state_len..][..state_len],
+                    &mut self.action,
+                );
+                let step = env.step(&self.action, observation);
+                self.rewards[i] = step.reward;
+                self.dones[i] = step.done();
+                self.truncated[i] = step.truncated && !step.terminated;
+                if self.truncated[i] {
+                    self.final_observations[i * observation_len..][..observation_len]
+                        .copy_from_slice(observation);
+                }
+                self.needs_reset[e] = step.done();
+            }
+            self.lens[e] = self.horizon;
+        }
+    }
+    /// Run a single episode of at most `horizon` steps in `env`, from a reset, and record it as
+    /// the steps of the environment `e`. `act` is the same as for `collect`.
+    pub fn collect_episode<E: Env<T>>(
+        &mut self,
+        e: usize,
+        env: &mut E,
+        mut act: impl FnMut(&[T], &mut [T], &mut [T]),
+    ) {
+        debug_assert!(
+            env.observation_len() == self.observation_len && env.action_len() == self.action_len,
+            "env dimensions"
+        );
+        let (observation_len, state_len) = (self.observation_len, self.state_len);
+        let observation = &mut self.observations[e * observation_len..][..observation_len];
+        env.reset(observation);
+        let mut len = self.horizon;
+        for t in 0..self.horizon {
+            let i = e * self.horizon + t;
+            let input = &mut self.inputs[i * observation_len..][..observation_len];
+            input.copy_from_slice(observation);
+            act(
+                input,
+                &mut self.states[i * state_len..][..state_len],
+                &mut self.action,
+            );
+            let step = env.step(&self.action, observation);
+            self.rewards[i] = step.reward;
+            self.dones[i] = step.done();
+            self.truncated[i] = step.truncated && !step.terminated;
+            if self.truncated[i] {
+                self.final_observations[i * observation_len..][..observation_len]
+                    .copy_from_slice(observation);
+            }
+            if step.done() {
+                len = t + 1;
+                break;
+            }
+        }
+        self.lens[e] = len;
+        self.truncated[e * self.horizon + len..(e + 1) * self.horizon]
+            .iter_mut()
+            .for_each(|t| *t = false);
+        self.needs_reset[e] = true;
+    }
+    /// Same as `collect` with the actions sampled by `policy`.
+    pub fn collect_policy<E: Env<T>, P: StochasticPolicy<T>>(
+        &mut self,
+        envs: &mut [E],
+        policy: &P,
+        weights: &[T],
+        rng: &mut impl Rng,
+    ) {
+        debug_assert!(policy.state_len() == self.state_len, "policy state len");
+        self.collect(envs, |input, state, action| {
+            policy.stochastic_eval(input, weights, state, rng);
+            action.copy_from_slice(policy.stochastic_output(state));
+        });
+    }
+    /// Add $\gamma V(s')$ to the reward of every truncated step, where $s'$ is the observation
+    /// after the step. The truncated steps can then be handled as terminal ones, as `dones` does.
+    pub fn bootstrap_truncated(&mut self, gamma: T, mut value: impl FnMut(&[T]) -> T) {
+        let observation_len = self.observation_len;
+        for i in 0..self.len() {
+            if self.truncated[i] {
+                let observation =
+                    &self.final_observations[i * observation_len..][..observation_len];
+                self.rewards[i] = self.rewards[i] + gamma * value(observation);
+            }
+        }
+    }
+
+    pub fn inputs(&self) -> &[T] {
+        &self.inputs
+    }
+    pub fn states(&self) -> &[T] {
+        &self.states
+    }
+    pub fn states_mut(&mut self) -> &mut [T] {
+        &mut self.states
+    }
+    pub fn rewards(&self) -> &[T] {
+        &self.rewards
+    }
+    /// Whether each step ended its episode, either terminated or truncated
+    pub fn dones(&self) -> &[bool] {
+        &self.dones
+    }
+    pub fn truncated(&self) -> &[bool] {
+        &self.truncated
+    }
+    /// Observation after the last recorded step of the environment `env`, to bootstrap on when
+    /// the episode is not done
+    pub fn last_observation(&self, env: usize) -> &[T] {
+        &self.observations[env * self.observation_len..][..self.observation_len]
+    }
+    /// Range of the indices of the recorded time steps of the environment `env`
+    pub fn env_steps(&self, env: usize) -> std::ops::Range<usize> {
+        env * self.horizon..env * self.horizon + self.lens[env]
+    }
+    /// Number of recorded steps of the environment `env`, which is `horizon` after `collect`
+    pub fn recorded_len(&self, env: usize) -> usize {
+        self.lens[env]
+    }
+    /// Total number of recorded steps
+    pub fn recorded_steps(&self) -> usize {
+        self.lens.iter().sum()
+    }
+    /// Mean reward over the recorded steps
+    pub fn mean_reward(&self) -> T {
+        let total = (0..self.env_count).fold(T::zero(), |acc, e| {
+            self.rewards[self.env_steps(e)]
+                .iter()
+                .fold(acc, |acc, &r| acc + r)
+        });
+        total / T::from(self.recorded_steps().max(1)).unwrap()
+    }
+    /// Recorded time steps ready to be given to `Optimizer::optimize`, without allocation.
+    pub fn time_steps(&mut self) -> TimeStepChunks<'_, T> {
+        self.update_recorded();
+        TimeStepChunks::new(
+            &self.inputs,
+            self.observation_len,
+            &mut self.states,
+            self.state_len,
+        )
+        .with_selection(&self.recorded)
+    }
+    /// Same as `time_steps` with the states of another component evaluated on the same inputs,
+    /// such as a value function, stored with the same indices.
+    pub fn time_steps_with<'a>(
+        &'a mut self,
+        states: &'a mut [T],
+        state_len: usize,
+    ) -> TimeStepChunks<'a, T> {
+        self.update_recorded();
+        TimeStepChunks::new(&self.inputs, self.observation_len, states, state_len)
+            .with_selection(&self.recorded)
+    }
+    fn update_recorded(&mut self) {
+        self.recorded.clear();
+        for e in 0..self.env_count {
+            let steps = self.env_steps(e);
+            self.recorded.extend(steps);
+        }
+    }
+}
@@ -0,0 +1,238 @@
+use std::fmt::{Display, LowerExp};
+
+use num::Float;
+
+use super::{BackProp, Gradient};
+
+/// Compare the analytic gradients of `BackProp` and `Gradient` implementors with central finite differences.
+pub struct GradCheck<T: Float> {
+    epsilon: T,
+    tolerance: T,
+    tied_weights_len: Option<usize>,
+}
+
+/// Result of a gradient check. The error of each derivative is
+/// $|a - n| / \max(1, |a|, |n|)$ where $a$ is the analytic and $n$ the numerical derivative.
+#[derive(Clone, Copy, Debug)]
+pub struct GradCheckReport<T: Float> {
+    pub tolerance: T,
+    /// Largest error over the weights
+    pub max_error: T,
+    /// Index of the weight with the largest error
+    pub worst_index: Option<usize>,
+    /// Layer of the weight with the largest error, see `with_layer`
+    pub worst_layer: Option<usize>,
+    pub analytic: T,
+    pub numerical: T,
+    /// Largest error over the inputs, only checked for `BackProp`
+    pub max_input_error: T,
+    pub worst_input_index: Option<usize>,
+}
+
+impl<T: Float> Default for GradCheck<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: Float> GradCheck<T> {
+    pub fn new() -> Self {
+        GradCheck {
+            epsilon: T::from(1e-6).unwrap(),
+            tolerance: T::from(1e-5).unwrap(),
+            tied_weights_len: None,
+        }
+    }
+    pub fn with_epsilon(mut self, epsilon: T) -> Self {
+        self.epsilon = epsilon;
+        self
+    }
+    pub fn with_tolerance(mut self, tolerance: T) -> Self {
+        self.tolerance = tolerance;
+        self
+    }
+    /// The weights are made of copies of the first `len` weights that are perturbed together, as
+    /// for the tied weights of `Sub` and `Mul` when both operands have the same number of weights.
+    pub fn with_tied_weights(mut self, len: usize) -> Self {
+        self.tied_weights_len = Some(len);
+        self
+    }
+
+    fn error(&self, analytic: T, numerical: T) -> T {
+        (analytic - numerical).abs() / T::one().max(analytic.abs()).max(numerical.abs())
+    }
+    fn report(&self) -> GradCheckReport<T> {
+        GradCheckReport {
+            tolerance: self.tolerance,
+            max_error: T::zero(),
+            worst_index: None,
+            worst_layer: None,
+            analytic: T::zero(),
+            numerical: T::zero(),
+            max_input_error: T::zero(),
+            worst_input_index: None,
+        }
+    }
+    /// Central difference of `f` with respect to the weight `index` and its tied copies.
+    fn weight_derivative(
+        &self,
+        weights: &mut [T],
+        index: usize,
+        mut f: impl FnMut(&[T]) -> T,
+    ) -> T {
+        let stride = self.tied_weights_len.unwrap_or(weights.len());
+        let saved = weights[index];
+        let perturb = |weights: &mut [T], delta: T| {
+            weights
+                .iter_mut()
+                .skip(index)
+                .step_by(stride)
+                .for_each(|w| *w = saved + delta);
+        };
+        perturb(weights, self.epsilon);
+        let plus = f(weights);
+        perturb(weights, -self.epsilon);
+        let minus = f(weights);
+        perturb(weights, T::zero());
+        (plus - minus) / (self.epsilon + self.epsilon)
+    }
+    fn checked_weights(&self, weights_len: usize) -> usize {
+        self.tied_weights_len.unwrap_or(weights_len)
+    }
+
+    /// Check `back_prop` for the objective $\sum_i c_i o_i$ where $o$ is the output and $c$ the `cotangent`.
+    /// Both the gradient with respect to the weights and to the input are checked.
+    pub fn back_prop<B: BackProp<T>>(
+        &self,
+        component: &B,
+        input: &[T],
+        weights: &[T],
+        cotangent: &[T],
+    ) -> GradCheckReport<T> {
+        debug_assert!(cotangent.len() == component.output_len(), "cotangent len");
+        let mut state = component.empty_state();
+        let objective = |input: &[T], weights: &[T], state: &mut [T]| {
+            component.eval(input, weights, state);
+            component
+                .output(state)
+                .iter()
+                .zip(cotangent.iter())
+                .fold(T::zero(), |acc, (&o, &c)| acc + o * c)
+        };
+
+        let len = component.back_front_len();
+        let mut front = vec![T::zero(); len];
+        let mut back = vec![T::zero(); len];
+        let mut gradient = component.empty_weights();
+        component.eval(input, weights, &mut state);
+        back[..cotangent.len()].copy_from_slice(cotangent);
+        component.back_prop(input, weights, &state, &mut front, &mut back, &mut gradient);
+
+        let mut report = self.report();
+        let mut weights = weights.to_vec();
+        for i in 0..self.checked_weights(weights.len()) {
+            let numerical =
+                self.weight_derivative(&mut weights, i, |w| objective(input, w, &mut state));
+            report.update_weight(
+                i,
+                gradient[i],
+                numerical,
+                self.error(gradient[i], numerical),
+            );
+        }
+        let mut input = input.to_vec();
+        for i in 0..input.len() {
+            let saved = input[i];
+            input[i] = saved + self.epsilon;
+            let plus = objective(&input, &weights, &mut state);
+            input[i] = saved - self.epsilon;
+            let minus = objective(&input, &weights, &mut state);
+            input[i] = saved;
+            let numerical = (plus - minus) / (self.epsilon + self.epsilon);
+            let error = self.error(front[i], numerical);
+            if error > report.max_input_error || report.worst_input_index.is_none() {
+                report.max_input_error = error;
+                report.worst_input_index = Some(i);
+            }
+        }
+        report
+    }
+
+    /// Check `compute_gradient` against the derivatives of `objective`. The `state` must already
+    /// hold what is not computed by `eval`, such as a target or a sampled action.
+    pub fn gradient<G: Gradient<T>>(
+        &self,
+        component: &G,
+        input: &[T],
+        weights: &[T],
+        state: &[T],
+    ) -> GradCheckReport<T> {
+        let mut tmp_state = state.to_vec();
+        let mut objective = |weights: &[T]| {
+            tmp_state.copy_from_slice(state);
+            component.eval(input, weights, &mut tmp_state);
+            component.objective(&tmp_state)
+        };
+        let mut gradient = component.empty_weights();
+        let mut analytic_state = state.to_vec();
+        component.eval(input, weights, &mut analytic_state);
+        component.compute_gradient(input, weights, &mut analytic_state, &mut gradient);
+
+        let mut report = self.report();
+        let mut weights = weights.to_vec();
+        for i in 0..self.checked_weights(weights.len()) {
+            let numerical = self.weight_derivative(&mut weights, i, &mut objective);
+            report.update_weight(
+                i,
+                gradient[i],
+                numerical,
+                self.error(gradient[i], numerical),
+            );
+        }
+        report
+    }
+}
+
+impl<T: Float> GradCheckReport<T> {
+    fn update_weight(&mut self, index: usize, analytic: T, numerical: T, error: T) {
+        if error > self.max_error || self.worst_index.is_none() {
+            self.max_error = error;
+            self.worst_index = Some(index);
+            self.analytic = analytic;
+            self.numerical = numerical;
+        }
+    }
+    pub fn is_ok(&self) -> bool {
+        self.max_error <= self.tolerance && self.max_input_error <= self.tolerance
+    }
+    /// Find the layer of the worst weight, for instance with `MLP::layer_of_weight`.
+    pub fn with_layer(mut self, layer_of_weight: impl Fn(usize) -> Option<usize>) -> Self {
+        self.worst_layer = self.worst_index.and_then(layer_of_weight);
+        self
+    }
+}
+
+impl<T: Float + LowerExp> Display for GradCheckReport<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "max error {:.2e}", self.max_error)?;
+        if let Some(i) = self.worst_index {
+            write!(f, " at weight {i}")?;
+            if let Some(l) = self.worst_layer {
+                write!(f, " (layer {l})")?;
+            }
+            write!(
+                f,
+                " [analytic {:.6e}, numerical {:.6e}]",
+                self.analytic, self.numerical
+            )?;
+        }
+        if let Some(i) = self.worst_input_index {
+            write!(
+                f,
+                ", max input error {:.2e} at input {i}",
+                self.max_input_error
+            )?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,111 @@
+use num::Float;
+use rand::Rng;
+use rand_distr::{Distribution, StandardNormal};
+
+/// Initialization of the weights of a `LayerMatrix`, where `fan_in` and `fan_out` are its number of
+/// inputs and outputs. The biases are always set to zero.
+#[derive(Clone, Copy, Debug)]
+pub enum Init<T: Float> {
+    /// $U(-a, a)$
+    Uniform(T),
+    /// Glorot: $U(-a, a)$ with $a = \sqrt{6 / (fan_{in} + fan_{out})}$
+    XavierUniform,
+    /// Glorot: $N(0, 2 / (fan_{in} + fan_{out}))$
+    XavierNormal,
+    /// Kaiming, for ReLU: $U(-a, a)$ with $a = \sqrt{6 / fan_{in}}$
+    HeUniform,
+    /// Kaiming, for ReLU: $N(0, 2 / fan_{in})$
+    HeNormal,
+    /// Random orthogonal rows (or columns if there are more outputs than inputs) multiplied by `gain`
+    Orthogonal { gain: T },
+}
+
+impl<T: Float> Init<T> {
+    /// Fill the `outputs` rows of `weights`, each being the bias followed by `inputs` weights.
+    pub(crate) fn fill(
+        &self,
+        inputs: usize,
+        outputs: usize,
+        weights: &mut [T],
+        rng: &mut impl Rng,
+    ) {
+        debug_assert!(
+            weights.len() == (inputs + 1) * outputs,
+            "LayerMatrix weights"
+        );
+        let (fan_in, fan_out) = (inputs as f64, outputs as f64);
+        let mut uniform = |a: f64| T::from(rng.random_range(-a..=a)).unwrap();
+        match *self {
+            Init::Uniform(a) => {
+                let a = a.to_f64().unwrap();
+                fill_matrix(inputs, weights, |_| uniform(a))
+            }
+            Init::XavierUniform => {
+                let a = (6.0 / (fan_in + fan_out)).sqrt();
+                fill_matrix(inputs, weights, |_| uniform(a))
+            }
+            Init::HeUniform => {
+                let a = (6.0 / fan_in).sqrt();
+                fill_matrix(inputs, weights, |_| uniform(a))
+            }
+            Init::XavierNormal => {
+                let std = (2.0 / (fan_in + fan_out)).sqrt();
+                fill_matrix(inputs, weights, |_| normal(rng, std))
+            }
+            Init::HeNormal => {
+                let std = (2.0 / fan_in).sqrt();
+                fill_matrix(inputs, weights, |_| normal(rng, std))
+            }
+            Init::Orthogonal { gain } => {
+                fill_matrix(inputs, weights, |_| normal(rng, 1.0));
+                orthonormalize(inputs, outputs, weights);
+                fill_matrix(inputs, weights, |w| w * gain);
+            }
+        }
+    }
+}
+
+fn normal<T: Float>(rng: &mut impl Rng, std: f64) -> T {
+    let xi: f64 = StandardNormal.sample(rng);
+    T::from(std * xi).unwrap()
+}
+
+/// Apply `f` to every weight of the matrix and set the biases to zero.
+fn fill_matrix<T: Float>(inputs: usize, weights: &mut [T], mut f: impl FnMut(T) -> T) {
+    for row in weights.chunks_exact_mut(inputs + 1) {
+        row[0] = T::zero();
+        row[1..].iter_mut().for_each(|w| *w = f(*w));
+    }
+}
+
+/// Modified Gram-Schmidt, in place, on the rows of the matrix if there are no more rows than
+/// columns, and on its columns otherwise.
+fn orthonormalize<T: Float>(inputs: usize, outputs: usize, weights: &mut [T]) {
+    let stride = inputs + 1;
+    let (vectors, len) = if outputs <= inputs {
+        (outputs, inputs)
+    } else {
+        (inputs, outputs)
+    };
+    let index = |k: usize, j: usize| {
+        if outputs <= inputs {
+            k * stride + 1 + j
+        } else {
+            j * stride + 1 + k
+        }
+    };
+    for k in 0..vectors {
+        for p in 0..k {
+            let dot = (0..len).fold(T::zero(), |acc, j| {
+                acc + weights[index(k, j)] * weights[index(p, j)]
+            });
+            (0..len).for_each(|j| {
+                weights[index(k, j)] = weights[index(k, j)] - dot * weights[index(p, j)]
+            });
+        }
+        let norm = (0..len)
+            .fold(T::zero(), |acc, j| acc + weights[index(k, j)].powi(2))
+            .sqrt();
+        (0..len).for_each(|j| weights[index(k, j)] = weights[index(k, j)] / norm);
+    }
+}
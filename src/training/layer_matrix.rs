@@ -2,7 +2,9 @@ use std::marker::PhantomData;
 
 use num::Float;
 
-use super::{BackProp, Eval, Weights};
+use rand::Rng;
+
+use super::{BackProp, Eval, Weights, init::Init};
 
 pub struct LayerMatrix<T: Float> {
     inputs: usize,
@@ -24,6 +26,9 @@ impl<T: Float> LayerMatrix<T> {
     pub fn outputs(&self) -> usize {
         self.outputs
     }
+    pub fn init_weights(&self, weights: &mut [T], init: Init<T>, rng: &mut impl Rng) {
+        init.fill(self.inputs, self.outputs, weights, rng);
+    }
 }
 
 impl<T: Float> Weights<T> for LayerMatrix<T> {
@@ -80,20 +85,23 @@ impl<T: Float> BackProp<T> for LayerMatrix<T> {
             input.len() == self.inputs
                 && weights.len() == self.weights_len()
                 && state.len() == self.outputs
-                && gradient.len() == self.weights_len()
-                && front.len() == self.inputs
-                && back.len() == self.outputs,
+                && (gradient.len() == self.weights_len() || gradient.is_empty())
+                && front.len() >= self.inputs
+                && back.len() >= self.outputs,
             "LayerMatrix"
         );
-        front.iter_mut().for_each(|f| *f = T::zero());
+        front[..self.inputs].iter_mut().for_each(|f| *f = T::zero());
         back.iter()
             .zip(weights.chunks(self.inputs + 1))
-            .zip(gradient.chunks_mut(self.inputs + 1))
-            .for_each(|((b, ws), gs)| {
-                gs[0] = *b;
+            .for_each(|(b, ws)| {
                 front.iter_mut().zip(ws[1..].iter()).for_each(|(f, w)| {
                     *f = *f + *b * *w;
                 });
+            });
+        back.iter()
+            .zip(gradient.chunks_mut(self.inputs + 1))
+            .for_each(|(b, gs)| {
+                gs[0] = *b;
                 gs[1..].iter_mut().zip(input.iter()).for_each(|(g, i)| {
                     *g = *b * *i;
                 });
@@ -13,6 +13,14 @@ impl<T: Float> LeastSquareValue<T> {
         );
         LeastSquareValue { mlp }
     }
+    pub fn mlp(&self) -> &MLP<T> {
+        &self.mlp
+    }
+    /// Same as `Value::set_target` without borrowing `self` mutably, the target is the last value
+    /// of the state.
+    pub fn target_mut<'a>(&self, state: &'a mut [T]) -> &'a mut T {
+        &mut state[self.state_len() - 1]
+    }
 }
 
 impl<T: Float> Weights<T> for LeastSquareValue<T> {
@@ -51,11 +59,14 @@ impl<T: Float> Gradient<T> for LeastSquareValue<T> {
         self.mlp
             .back_prop(input, weights, state, front, back, gradient);
     }
+    fn objective(&self, state: &[T]) -> T {
+        (self.value(state) - self.target(state)).powi(2) / (T::one() + T::one())
+    }
 }
 
 impl<T: Float> Value<T> for LeastSquareValue<T> {
     fn set_target(&mut self, target: T, state: &mut [T]) {
-        state[self.state_len() - 1] = target;
+        *self.target_mut(state) = target;
     }
     fn target(&self, state: &[T]) -> T {
         state[self.state_len() - 1]
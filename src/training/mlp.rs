@@ -1,6 +1,10 @@
 use num::Float;
 
-use super::{Activation, BackProp, Eval, Weights, layer_matrix::LayerMatrix};
+use rand::Rng;
+
+use super::{
+    Activation, BackProp, Eval, Weights, init::Init, layer_matrix::LayerMatrix, split_gradient,
+};
 
 pub struct MLP<T: Float> {
     layers: Box<[(LayerMatrix<T>, Box<dyn Activation<T>>)]>,
@@ -20,9 +24,42 @@ impl<T: Float> MLP<T> {
                 .into_boxed_slice(),
         }
     }
+    /// Number of outputs and activation of each layer, as given to `new`
+    pub fn layers(&self) -> impl Iterator<Item = (usize, &dyn Activation<T>)> {
+        self.layers.iter().map(|(l, a)| (l.outputs(), a.as_ref()))
+    }
     pub fn output_range(&self) -> (Option<T>, Option<T>) {
         self.layers[self.layers.len() - 1].1.range()
     }
+    /// Index of the layer that the weight at `index` belongs to
+    pub fn layer_of_weight(&self, mut index: usize) -> Option<usize> {
+        self.layers.iter().position(|(l, a)| {
+            let len = l.weights_len() + a.weights_len();
+            let found = index < len;
+            index = index.saturating_sub(len);
+            found
+        })
+    }
+    /// Initialize each `LayerMatrix` with its own fan-in and fan-out. The weights of the activations
+    /// are left untouched.
+    pub fn init_weights(&self, weights: &mut [T], init: Init<T>, rng: &mut impl Rng) {
+        debug_assert!(weights.len() == self.weights_len(), "MLP weights");
+        let mut weights = weights;
+        for (l, a) in self.layers.iter() {
+            let (layer, rest) = weights.split_at_mut(l.weights_len());
+            l.init_weights(layer, init, rng);
+            weights = &mut rest[a.weights_len()..];
+        }
+    }
+    /// Multiply the weights of the last `LayerMatrix` by `scale`, typically a small value such as
+    /// 0.01 for a policy head so that the initial policy is close to uniform.
+    pub fn scale_final_layer(&self, weights: &mut [T], scale: T) {
+        let (l, a) = &self.layers[self.layers.len() - 1];
+        let end = weights.len() - a.weights_len();
+        weights[end - l.weights_len()..end]
+            .iter_mut()
+            .for_each(|w| *w = *w * scale);
+    }
     pub fn min_back_front_len(&self) -> usize {
         self.layers
             .iter()
@@ -102,7 +139,10 @@ impl<T: Float> BackProp<T> for MLP<T> {
         debug_assert!(input.len() == self.layers[0].0.inputs(), "MLP input");
         debug_assert!(weights.len() == self.weights_len(), "MLP weigths");
         debug_assert!(state.len() == self.state_len(), "MLP state");
-        debug_assert!(gradient.len() == self.weights_len(), "MLP gradient");
+        debug_assert!(
+            gradient.len() == self.weights_len() || gradient.is_empty(),
+            "MLP gradient"
+        );
         debug_assert!(front.len() >= min_back_front_len, "MLP front");
         debug_assert!(back.len() >= min_back_front_len, "MLP back");
 
@@ -111,7 +151,7 @@ impl<T: Float> BackProp<T> for MLP<T> {
         let mut gradient = &mut *gradient;
         let mut weights = &*weights;
         for len in self.layers.iter().map(|l| l.0.weights_len()) {
-            let (a, b) = gradient.split_at_mut(len);
+            let (a, b) = split_gradient(gradient, len);
             gradients.push(a);
             gradient = b;
             let (a, b) = weights.split_at(len);
@@ -155,4 +195,7 @@ impl<T: Float> BackProp<T> for MLP<T> {
             });
         front.iter_mut().zip(back.iter()).for_each(|(f, b)| *f = *b);
     }
+    fn back_front_len(&self) -> usize {
+        self.min_back_front_len()
+    }
 }
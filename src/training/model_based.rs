@@ -0,0 +1,465 @@
+use num::Float;
+use rand::{Rng, SeedableRng, rngs::StdRng};
+use rand_distr::{Distribution, StandardNormal};
+
+use super::{
+    BackProp, Direction, Eval, Gradient, OptimizeBuffers, OptimizeOptions, OptimizeReport,
+    OptimizeStats, Optimizer, TimeStep, TimeSteps, Weights,
+    env::{Env, Step},
+    mlp::MLP,
+    planning::Cem,
+    replay::{Batch, ReplayBuffer},
+};
+
+/// Least squares regression of the observation change and of the reward of a transition.
+/// The MLP takes the observation followed by the action and outputs $s' - s$ followed by the reward.
+/// State: MLP state | back | front | target
+pub struct DynamicsLoss<T: Float> {
+    mlp: MLP<T>,
+}
+
+impl<T: Float> DynamicsLoss<T> {
+    pub fn new(mlp: MLP<T>) -> Self {
+        debug_assert!(
+            mlp.output_len() > 1,
+            "The dynamics MLP must output the observation change and the reward"
+        );
+        DynamicsLoss { mlp }
+    }
+    pub fn mlp(&self) -> &MLP<T> {
+        &self.mlp
+    }
+    pub fn observation_len(&self) -> usize {
+        self.mlp.output_len() - 1
+    }
+    pub fn action_len(&self) -> usize {
+        self.mlp.input_len() - self.observation_len()
+    }
+    pub fn target<'a>(&self, state: &'a [T]) -> &'a [T] {
+        &state[self.state_len() - self.output_len()..]
+    }
+    pub fn set_target(&self, target: &[T], state: &mut [T]) {
+        let len = self.state_len();
+        state[len - self.output_len()..].copy_from_slice(target);
+    }
+}
+
+impl<T: Float> Weights<T> for DynamicsLoss<T> {
+    fn weights_len(&self) -> usize {
+        self.mlp.weights_len()
+    }
+}
+impl<T: Float> Eval<T> for DynamicsLoss<T> {
+    fn state_len(&self) -> usize {
+        self.mlp.state_len() + 2 * self.mlp.min_back_front_len() + self.mlp.output_len()
+    }
+    fn eval(&self, input: &[T], weights: &[T], state: &mut [T]) {
+        self.mlp
+            .eval(input, weights, &mut state[..self.mlp.state_len()]);
+    }
+    fn input_len(&self) -> usize {
+        self.mlp.input_len()
+    }
+    fn output_len(&self) -> usize {
+        self.mlp.output_len()
+    }
+    fn output<'a>(&self, state: &'a [T]) -> &'a [T] {
+        self.mlp.output(&state[..self.mlp.state_len()])
+    }
+    fn output_mut<'a>(&self, state: &'a mut [T]) -> &'a mut [T] {
+        self.mlp.output_mut(&mut state[..self.mlp.state_len()])
+    }
+}
+impl<T: Float> Gradient<T> for DynamicsLoss<T> {
+    fn compute_gradient(&self, input: &[T], weights: &[T], state: &mut [T], gradient: &mut [T]) {
+        let (state, tmp) = state.split_at_mut(self.mlp.state_len());
+        let (back, tmp) = tmp.split_at_mut(self.mlp.min_back_front_len());
+        let (front, target) = tmp.split_at_mut(self.mlp.min_back_front_len());
+        back.iter_mut()
+            .zip(self.mlp.output(state).iter().zip(target.iter()))
+            .for_each(|(b, (&o, &t))| *b = o - t);
+        self.mlp
+            .back_prop(input, weights, state, front, back, gradient);
+    }
+    fn objective(&self, state: &[T]) -> T {
+        self.output(state)
+            .iter()
+            .zip(self.target(state).iter())
+            .fold(T::zero(), |acc, (&o, &t)| acc + (o - t) * (o - t))
+            / (T::one() + T::one())
+    }
+}
+
+/// Ensemble of dynamics models with the same architecture and independent weights. Each member is
+/// trained on its own batches sampled from a replay buffer, and the variance of their predictions
+/// measures how far a transition is from the collected data.
+pub struct DynamicsEnsemble<T: Float, O: Optimizer<T>> {
+    loss: DynamicsLoss<T>,
+    weights: Box<[Box<[T]>]>,
+    optimizers: Box<[O]>,
+    buffers: OptimizeBuffers<T>,
+    batch: Batch<T>,
+    states: Box<[T]>,
+    input: Box<[T]>,
+    prediction: Box<[T]>,
+    rng: StdRng,
+    optimize_options: OptimizeOptions<T>,
+    report: OptimizeReport,
+    stats: Box<[OptimizeStats<T>]>,
+}
+
+impl<T: Float, O: Optimizer<T>> DynamicsEnsemble<T, O> {
+    /// `optimizer(weights_len)` creates the optimizer of each member.
+    pub fn new(
+        loss: DynamicsLoss<T>,
+        members: usize,
+        optimizer: impl Fn(usize) -> O,
+        batch_size: usize,
+    ) -> Self {
+        debug_assert!(members > 0, "the ensemble must not be empty");
+        DynamicsEnsemble {
+            weights: (0..members).map(|_| loss.empty_weights()).collect(),
+            optimizers: (0..members)
+                .map(|_| optimizer(loss.weights_len()))
+                .collect(),
+            buffers: OptimizeBuffers::new(loss.weights_len(), batch_size),
+            batch: Batch::new(batch_size, loss.input_len(), loss.state_len()),
+            states: vec![T::zero(); members * loss.mlp().state_len()].into_boxed_slice(),
+            input: vec![T::zero(); loss.input_len()].into_boxed_slice(),
+            prediction: vec![T::zero(); loss.output_len()].into_boxed_slice(),
+            rng: StdRng::from_os_rng(),
+            optimize_options: OptimizeOptions::default(),
+            report: OptimizeReport::default(),
+            stats: vec![OptimizeStats::default(); members].into_boxed_slice(),
+            loss,
+        }
+    }
+    /// Seed the sampling of the batches and their shuffling, for reproducible runs
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.rng = StdRng::seed_from_u64(seed);
+        self
+    }
+    /// Gradient clipping of every update
+    pub fn with_optimize_options(mut self, options: OptimizeOptions<T>) -> Self {
+        self.optimize_options = options;
+        self
+    }
+    /// Steps, skipped minibatches and clipped gradients cumulated over all the updates
+    pub fn report(&self) -> OptimizeReport {
+        self.report
+    }
+    /// Loss and gradient norm of the last step of the member `member`
+    pub fn last_stats(&self, member: usize) -> OptimizeStats<T> {
+        self.stats[member]
+    }
+    pub fn loss(&self) -> &DynamicsLoss<T> {
+        &self.loss
+    }
+    pub fn members(&self) -> usize {
+        self.weights.len()
+    }
+    pub fn observation_len(&self) -> usize {
+        self.loss.observation_len()
+    }
+    pub fn action_len(&self) -> usize {
+        self.loss.action_len()
+    }
+    pub fn weights(&self, member: usize) -> &[T] {
+        &self.weights[member]
+    }
+    pub fn weights_mut(&mut self, member: usize) -> &mut [T] {
+        &mut self.weights[member]
+    }
+    /// Length of the buffer needed by `predict_all`
+    pub fn states_len(&self) -> usize {
+        self.states.len()
+    }
+
+    /// Do `steps` gradient steps for each member and return the mean loss of the last batches.
+    /// The replay buffer must store the actual next observation of terminal transitions as well.
+    pub fn train(&mut self, replay: &ReplayBuffer<T>, steps: usize) -> T {
+        debug_assert!(
+            replay.observation_len() == self.observation_len()
+                && replay.action_len() == self.action_len(),
+            "The replay buffer must store the observations and actions of the dynamics model"
+        );
+        if replay.len() < self.batch.len() {
+            return T::zero();
+        }
+        let DynamicsEnsemble {
+            loss,
+            weights,
+            optimizers,
+            buffers,
+            batch,
+            prediction: target,
+            rng,
+            optimize_options,
+            report,
+            stats,
+            ..
+        } = self;
+        let n = loss.observation_len();
+        let batch_size = batch.len();
+        let options = optimize_options
+            .with_minibatch_size(batch_size)
+            .with_direction(Direction::Descent);
+        let mut total = T::zero();
+        for ((weights, optimizer), stats) in weights
+            .iter_mut()
+            .zip(optimizers.iter_mut())
+            .zip(stats.iter_mut())
+        {
+            for step in 0..steps {
+                replay.sample(batch, rng, |transition, _, input, state| {
+                    input[..n].copy_from_slice(transition.observation);
+                    input[n..].copy_from_slice(transition.action);
+                    target[..n]
+                        .iter_mut()
+                        .zip(
+                            transition
+                                .next_observation
+                                .iter()
+                                .zip(transition.observation.iter()),
+                        )
+                        .for_each(|(t, (&next, &o))| *t = next - o);
+                    target[n] = transition.reward;
+                    loss.set_target(target, state);
+                });
+                let mut time_steps = batch.time_steps();
+                *stats = optimizer.optimize(loss, weights, &mut time_steps, buffers, options, rng);
+                *report += stats.total;
+                if step + 1 == steps {
+                    // NOTE: the loss is evaluated with the weights after the last step
+                    for i in 0..time_steps.len() {
+                        let TimeStep { input, state } = time_steps.get_mut(i);
+                        loss.eval(input, weights, state);
+                        total = total + loss.objective(state);
+                    }
+                }
+            }
+        }
+        total / T::from(batch_size * self.weights.len()).unwrap()
+    }
+
+    /// Prediction of the member `member`: write the next observation and return the reward.
+    /// `state` must be at least `loss().mlp().state_len()` long.
+    pub fn predict(
+        &self,
+        member: usize,
+        observation: &[T],
+        action: &[T],
+        input: &mut [T],
+        state: &mut [T],
+        next_observation: &mut [T],
+    ) -> T {
+        let mlp = self.loss.mlp();
+        let n = self.observation_len();
+        input[..n].copy_from_slice(observation);
+        input[n..].copy_from_slice(action);
+        let state = &mut state[..mlp.state_len()];
+        mlp.eval(input, &self.weights[member], state);
+        let output = mlp.output(state);
+        next_observation
+            .iter_mut()
+            .zip(observation.iter().zip(output.iter()))
+            .for_each(|(next, (&o, &d))| *next = o + d);
+        output[n]
+    }
+
+    /// Evaluate every member, where `states` is `states_len()` long, then write the mean next
+    /// observation and return the mean reward and the disagreement, which is the variance of the
+    /// predicted observations across the members averaged over the observation dimensions.
+    pub fn predict_all(
+        &self,
+        observation: &[T],
+        action: &[T],
+        input: &mut [T],
+        states: &mut [T],
+        next_observation: &mut [T],
+    ) -> (T, T) {
+        let mlp = self.loss.mlp();
+        let n = self.observation_len();
+        input[..n].copy_from_slice(observation);
+        input[n..].copy_from_slice(action);
+        let count = T::from(self.members()).unwrap();
+        next_observation.iter_mut().for_each(|o| *o = T::zero());
+        let mut reward = T::zero();
+        for (weights, state) in self
+            .weights
+            .iter()
+            .zip(states.chunks_exact_mut(mlp.state_len()))
+        {
+            mlp.eval(input, weights, state);
+            let output = mlp.output(state);
+            next_observation
+                .iter_mut()
+                .zip(output.iter())
+                .for_each(|(next, &d)| *next = *next + d / count);
+            reward = reward + output[n] / count;
+        }
+        let mut variance = T::zero();
+        for state in states.chunks_exact(mlp.state_len()).take(self.members()) {
+            variance = mlp
+                .output(state)
+                .iter()
+                .zip(next_observation.iter())
+                .fold(variance, |acc, (&d, &mean)| acc + (d - mean) * (d - mean));
+        }
+        next_observation
+            .iter_mut()
+            .zip(observation.iter())
+            .for_each(|(next, &o)| *next = *next + o);
+        (reward, variance / (count * T::from(n).unwrap()))
+    }
+
+    /// Same as `predict_all` with the buffers of the ensemble.
+    pub fn predict_mean(
+        &mut self,
+        observation: &[T],
+        action: &[T],
+        next_observation: &mut [T],
+    ) -> (T, T) {
+        let (mut input, mut states) = (
+            std::mem::take(&mut self.input),
+            std::mem::take(&mut self.states),
+        );
+        let prediction = self.predict_all(
+            observation,
+            action,
+            &mut input,
+            &mut states,
+            next_observation,
+        );
+        (self.input, self.states) = (input, states);
+        prediction
+    }
+
+    /// Model-predictive control: plan from `observation` with the mean prediction of the ensemble,
+    /// minus `penalty` times the disagreement, as reward.
+    pub fn plan<'c>(&mut self, cem: &'c mut Cem<T>, observation: &[T], penalty: T) -> &'c [T]
+    where
+        StandardNormal: Distribution<T>,
+    {
+        debug_assert!(cem.action_len() == self.action_len(), "action length");
+        let (mut input, mut states, mut next) = (
+            std::mem::take(&mut self.input),
+            std::mem::take(&mut self.states),
+            std::mem::take(&mut self.prediction),
+        );
+        let n = self.observation_len();
+        let action = cem.plan_rollout(observation, |state, action| {
+            let (reward, disagreement) =
+                self.predict_all(state, action, &mut input, &mut states, &mut next[..n]);
+            state.copy_from_slice(&next[..n]);
+            reward - penalty * disagreement
+        });
+        (self.input, self.states, self.prediction) = (input, states, next);
+        action
+    }
+}
+
+type Termination<'a, T> = Box<dyn Fn(&[T]) -> bool + 'a>;
+
+/// Imagined environment where the transitions are predicted by a `DynamicsEnsemble`, each step by
+/// a random member, and the episodes start from observations of a replay buffer, as in MBPO.
+/// The episodes are truncated after `horizon` steps.
+pub struct ModelEnv<'a, T: Float, O: Optimizer<T>> {
+    ensemble: &'a DynamicsEnsemble<T, O>,
+    replay: &'a ReplayBuffer<T>,
+    horizon: usize,
+    penalty: T,
+    termination: Option<Termination<'a, T>>,
+    steps: usize,
+    rng: StdRng,
+    observation: Box<[T]>,
+    next_observation: Box<[T]>,
+    input: Box<[T]>,
+    states: Box<[T]>,
+}
+
+impl<'a, T: Float, O: Optimizer<T>> ModelEnv<'a, T, O> {
+    pub fn new(
+        ensemble: &'a DynamicsEnsemble<T, O>,
+        replay: &'a ReplayBuffer<T>,
+        horizon: usize,
+    ) -> Self {
+        debug_assert!(
+            replay.observation_len() == ensemble.observation_len(),
+            "observation length"
+        );
+        ModelEnv {
+            ensemble,
+            replay,
+            horizon,
+            penalty: T::zero(),
+            termination: None,
+            steps: 0,
+            rng: StdRng::from_os_rng(),
+            observation: vec![T::zero(); ensemble.observation_len()].into_boxed_slice(),
+            next_observation: vec![T::zero(); ensemble.observation_len()].into_boxed_slice(),
+            input: vec![T::zero(); ensemble.loss().input_len()].into_boxed_slice(),
+            states: vec![T::zero(); ensemble.states_len()].into_boxed_slice(),
+        }
+    }
+    /// Subtract `penalty` times the disagreement of the ensemble from the reward, as in MOPO.
+    pub fn with_penalty(mut self, penalty: T) -> Self {
+        self.penalty = penalty;
+        self
+    }
+    /// Seed the starting observations and the members, for reproducible imagined rollouts
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.rng = StdRng::seed_from_u64(seed);
+        self
+    }
+    /// Known termination condition evaluated on the predicted observations
+    pub fn with_termination(mut self, termination: impl Fn(&[T]) -> bool + 'a) -> Self {
+        self.termination = Some(Box::new(termination));
+        self
+    }
+}
+
+impl<T: Float, O: Optimizer<T>> Env<T> for ModelEnv<'_, T, O> {
+    fn observation_len(&self) -> usize {
+        self.ensemble.observation_len()
+    }
+    fn action_len(&self) -> usize {
+        self.ensemble.action_len()
+    }
+    fn reset(&mut self, observation: &mut [T]) {
+        debug_assert!(
+            !self.replay.is_empty(),
+            "the episodes start from the replay buffer"
+        );
+        let index = self.rng.random_range(0..self.replay.len());
+        self.observation
+            .copy_from_slice(self.replay.get(index).observation);
+        observation.copy_from_slice(&self.observation);
+        self.steps = 0;
+    }
+    fn step(&mut self, action: &[T], observation: &mut [T]) -> Step<T> {
+        let (_, disagreement) = self.ensemble.predict_all(
+            &self.observation,
+            action,
+            &mut self.input,
+            &mut self.states,
+            &mut self.next_observation,
+        );
+        let member = self.rng.random_range(0..self.ensemble.members());
+        let reward = self.ensemble.predict(
+            member,
+            &self.observation,
+            action,
+            &mut self.input,
+            &mut self.states,
+            observation,
+        );
+        self.observation.copy_from_slice(observation);
+        self.steps += 1;
+        Step {
+            reward: reward - self.penalty * disagreement,
+            terminated: self.termination.as_ref().is_some_and(|t| t(observation)),
+            truncated: self.steps >= self.horizon,
+        }
+    }
+}
@@ -1,2 +1,6 @@
+pub mod add;
+pub mod concat;
 pub mod mul;
+pub mod parallel;
+pub mod sequential;
 pub mod sub;
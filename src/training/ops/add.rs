@@ -0,0 +1,120 @@
+use std::marker::PhantomData;
+
+use num::Float;
+
+use crate::training::{BackProp, Eval, Weights, split_gradient};
+
+/// Sum of the outputs of `a` and `b` evaluated on their own part of the input, which is stored
+/// after their states. As for `Sub`, an output of length 1 is broadcasted.
+pub struct Add<T: Float, A: BackProp<T>, B: BackProp<T>> {
+    a: A,
+    b: B,
+    _phantom: PhantomData<T>,
+}
+pub fn add<T: Float, A: BackProp<T>, B: BackProp<T>>(a: A, b: B) -> Add<T, A, B> {
+    debug_assert!(
+        a.output_len() == b.output_len() || a.output_len() == 1 || b.output_len() == 1,
+        "output len"
+    );
+    Add {
+        a,
+        b,
+        _phantom: PhantomData,
+    }
+}
+impl<T: Float, A: BackProp<T>, B: BackProp<T>> Add<T, A, B> {
+    /// Length of the part of `front` and `back` used as buffers by `a` and `b`
+    fn inner_len(&self) -> usize {
+        self.a.back_front_len().max(self.b.back_front_len())
+    }
+}
+/// Gradient with respect to an operand of length `back.len()` given the gradient `output` with
+/// respect to the sum, which is summed when the operand is broadcasted.
+fn reduce<T: Float>(output: &[T], back: &mut [T]) {
+    if back.len() == output.len() {
+        back.copy_from_slice(output);
+    } else {
+        back[0] = output.iter().fold(T::zero(), |acc, &o| acc + o);
+    }
+}
+
+impl<T: Float, A: BackProp<T>, B: BackProp<T>> Weights<T> for Add<T, A, B> {
+    fn weights_len(&self) -> usize {
+        self.a.weights_len() + self.b.weights_len()
+    }
+}
+impl<T: Float, A: BackProp<T>, B: BackProp<T>> Eval<T> for Add<T, A, B> {
+    fn state_len(&self) -> usize {
+        self.a.state_len() + self.b.state_len() + self.output_len()
+    }
+    fn eval(&self, input: &[T], weights: &[T], state: &mut [T]) {
+        let (input_a, input_b) = input.split_at(self.a.input_len());
+        let (weights_a, weights_b) = weights.split_at(self.a.weights_len());
+        let (state_a, state) = state.split_at_mut(self.a.state_len());
+        let (state_b, output) = state.split_at_mut(self.b.state_len());
+        self.a.eval(input_a, weights_a, state_a);
+        self.b.eval(input_b, weights_b, state_b);
+        let output_a = self.a.output(state_a);
+        let output_b = self.b.output(state_b);
+        let last_a = output_a.len() - 1;
+        let last_b = output_b.len() - 1;
+        output
+            .iter_mut()
+            .enumerate()
+            .for_each(|(i, o)| *o = output_a[i.min(last_a)] + output_b[i.min(last_b)]);
+    }
+    fn input_len(&self) -> usize {
+        self.a.input_len() + self.b.input_len()
+    }
+    fn output_len(&self) -> usize {
+        self.a.output_len().max(self.b.output_len())
+    }
+    fn output<'a>(&self, state: &'a [T]) -> &'a [T] {
+        &state[self.a.state_len() + self.b.state_len()..]
+    }
+    fn output_mut<'a>(&self, state: &'a mut [T]) -> &'a mut [T] {
+        &mut state[self.a.state_len() + self.b.state_len()..]
+    }
+}
+impl<T: Float, A: BackProp<T>, B: BackProp<T>> BackProp<T> for Add<T, A, B> {
+    fn back_prop(
+        &self,
+        input: &[T],
+        weights: &[T],
+        state: &[T],
+        front: &mut [T],
+        back: &mut [T],
+        gradient: &mut [T],
+    ) {
+        debug_assert!(front.len() >= self.back_front_len(), "Add front");
+        debug_assert!(back.len() >= self.back_front_len(), "Add back");
+        let (input_a, input_b) = input.split_at(self.a.input_len());
+        let (weights_a, weights_b) = weights.split_at(self.a.weights_len());
+        let (state_a, state) = state.split_at(self.a.state_len());
+        let state_b = &state[..self.b.state_len()];
+        let (gradient_a, gradient_b) = split_gradient(gradient, self.a.weights_len());
+        let (inputs_a, inputs_b) = (self.a.input_len(), self.b.input_len());
+        let outputs = self.output_len();
+        let inner = self.inner_len();
+
+        back.copy_within(0..outputs, inner);
+        {
+            let (back, back_output) = back.split_at_mut(inner);
+            let (front, front_input) = front.split_at_mut(inner);
+            reduce(&back_output[..outputs], &mut back[..self.a.output_len()]);
+            self.a
+                .back_prop(input_a, weights_a, state_a, front, back, gradient_a);
+            front_input[..inputs_a].copy_from_slice(&front[..inputs_a]);
+
+            reduce(&back_output[..outputs], &mut back[..self.b.output_len()]);
+            self.b
+                .back_prop(input_b, weights_b, state_b, front, back, gradient_b);
+            back[..inputs_a].copy_from_slice(&front_input[..inputs_a]);
+        }
+        front.copy_within(0..inputs_b, inputs_a);
+        front[..inputs_a].copy_from_slice(&back[..inputs_a]);
+    }
+    fn back_front_len(&self) -> usize {
+        self.inner_len() + self.a.input_len().max(self.output_len())
+    }
+}
@@ -0,0 +1,102 @@
+use std::marker::PhantomData;
+
+use num::Float;
+
+use crate::training::{BackProp, Eval, Weights, split_gradient};
+
+/// Evaluate `a` and `b` on their own part of the input. The output is the output of `a` followed
+/// by the output of `b`, which is stored after their states.
+pub struct Concat<T: Float, A: BackProp<T>, B: BackProp<T>> {
+    a: A,
+    b: B,
+    _phantom: PhantomData<T>,
+}
+pub fn concat<T: Float, A: BackProp<T>, B: BackProp<T>>(a: A, b: B) -> Concat<T, A, B> {
+    Concat {
+        a,
+        b,
+        _phantom: PhantomData,
+    }
+}
+impl<T: Float, A: BackProp<T>, B: BackProp<T>> Concat<T, A, B> {
+    /// Length of the part of `front` and `back` used as buffers by `a` and `b`
+    fn inner_len(&self) -> usize {
+        self.a.back_front_len().max(self.b.back_front_len())
+    }
+}
+
+impl<T: Float, A: BackProp<T>, B: BackProp<T>> Weights<T> for Concat<T, A, B> {
+    fn weights_len(&self) -> usize {
+        self.a.weights_len() + self.b.weights_len()
+    }
+}
+impl<T: Float, A: BackProp<T>, B: BackProp<T>> Eval<T> for Concat<T, A, B> {
+    fn state_len(&self) -> usize {
+        self.a.state_len() + self.b.state_len() + self.output_len()
+    }
+    fn eval(&self, input: &[T], weights: &[T], state: &mut [T]) {
+        let (input_a, input_b) = input.split_at(self.a.input_len());
+        let (weights_a, weights_b) = weights.split_at(self.a.weights_len());
+        let (state_a, state) = state.split_at_mut(self.a.state_len());
+        let (state_b, output) = state.split_at_mut(self.b.state_len());
+        self.a.eval(input_a, weights_a, state_a);
+        self.b.eval(input_b, weights_b, state_b);
+        let (output_a, output_b) = output.split_at_mut(self.a.output_len());
+        output_a.copy_from_slice(self.a.output(state_a));
+        output_b.copy_from_slice(self.b.output(state_b));
+    }
+    fn input_len(&self) -> usize {
+        self.a.input_len() + self.b.input_len()
+    }
+    fn output_len(&self) -> usize {
+        self.a.output_len() + self.b.output_len()
+    }
+    fn output<'a>(&self, state: &'a [T]) -> &'a [T] {
+        &state[self.a.state_len() + self.b.state_len()..]
+    }
+    fn output_mut<'a>(&self, state: &'a mut [T]) -> &'a mut [T] {
+        &mut state[self.a.state_len() + self.b.state_len()..]
+    }
+}
+impl<T: Float, A: BackProp<T>, B: BackProp<T>> BackProp<T> for Concat<T, A, B> {
+    fn back_prop(
+        &self,
+        input: &[T],
+        weights: &[T],
+        state: &[T],
+        front: &mut [T],
+        back: &mut [T],
+        gradient: &mut [T],
+    ) {
+        debug_assert!(front.len() >= self.back_front_len(), "Concat front");
+        debug_assert!(back.len() >= self.back_front_len(), "Concat back");
+        let (input_a, input_b) = input.split_at(self.a.input_len());
+        let (weights_a, weights_b) = weights.split_at(self.a.weights_len());
+        let (state_a, state) = state.split_at(self.a.state_len());
+        let state_b = &state[..self.b.state_len()];
+        let (gradient_a, gradient_b) = split_gradient(gradient, self.a.weights_len());
+        let (inputs_a, inputs_b) = (self.a.input_len(), self.b.input_len());
+        let outputs_a = self.a.output_len();
+        let inner = self.inner_len();
+
+        back.copy_within(0..self.output_len(), inner);
+        {
+            let (back, back_output) = back.split_at_mut(inner);
+            let (front, front_input) = front.split_at_mut(inner);
+            back[..outputs_a].copy_from_slice(&back_output[..outputs_a]);
+            self.a
+                .back_prop(input_a, weights_a, state_a, front, back, gradient_a);
+            front_input[..inputs_a].copy_from_slice(&front[..inputs_a]);
+
+            back[..self.b.output_len()].copy_from_slice(&back_output[outputs_a..self.output_len()]);
+            self.b
+                .back_prop(input_b, weights_b, state_b, front, back, gradient_b);
+            back[..inputs_a].copy_from_slice(&front_input[..inputs_a]);
+        }
+        front.copy_within(0..inputs_b, inputs_a);
+        front[..inputs_a].copy_from_slice(&back[..inputs_a]);
+    }
+    fn back_front_len(&self) -> usize {
+        self.inner_len() + self.a.input_len().max(self.output_len())
+    }
+}
@@ -0,0 +1,181 @@
+use std::marker::PhantomData;
+
+use num::Float;
+
+use crate::training::{BackProp, Eval, Gradient, Weights, split_gradient};
+
+/// Element-wise product of the outputs of `a` and `b` evaluated on their own part of the input,
+/// which is stored after their states. As for `Sub`, an output of length 1 is broadcasted. The
+/// objective is the sum of the product, which is the product itself for a single output. When `a`
+/// and `b` have the same number of weights, the weights are tied as for `Sub`. Otherwise the
+/// weights of `a` and `b` are independent.
+/// State: a state | b state | output | back | front, the last two being used by `compute_gradient`.
+pub struct Mul<T: Float, A: BackProp<T>, B: BackProp<T>> {
+    a: A,
+    b: B,
+    _phantom: PhantomData<T>,
+}
+pub fn mul<T: Float, A: BackProp<T>, B: BackProp<T>>(a: A, b: B) -> Mul<T, A, B> {
+    debug_assert!(
+        a.output_len() == b.output_len() || a.output_len() == 1 || b.output_len() == 1,
+        "output len"
+    );
+    Mul {
+        a,
+        b,
+        _phantom: PhantomData,
+    }
+}
+impl<T: Float, A: BackProp<T>, B: BackProp<T>> Mul<T, A, B> {
+    /// Length of the part of `front` and `back` used as buffers by `a` and `b`
+    fn inner_len(&self) -> usize {
+        self.a.back_front_len().max(self.b.back_front_len())
+    }
+    fn tied(&self) -> bool {
+        self.a.weights_len() == self.b.weights_len()
+    }
+}
+/// Gradient with respect to an operand of length `back.len()` given the gradient `output` with
+/// respect to the product and the output `other` of the other operand, by the product rule
+/// $d(ab) = b\,da + a\,db$ applied to every output. It is summed when the operand is broadcasted.
+fn product_rule<T: Float>(output: &[T], other: &[T], back: &mut [T]) {
+    let last = other.len() - 1;
+    if back.len() == output.len() {
+        back.iter_mut()
+            .zip(output.iter())
+            .enumerate()
+            .for_each(|(i, (b, &o))| *b = o * other[i.min(last)]);
+    } else {
+        back[0] = output
+            .iter()
+            .enumerate()
+            .fold(T::zero(), |acc, (i, &o)| acc + o * other[i.min(last)]);
+    }
+}
+
+impl<T: Float, A: BackProp<T>, B: BackProp<T>> Weights<T> for Mul<T, A, B> {
+    fn weights_len(&self) -> usize {
+        self.a.weights_len() + self.b.weights_len()
+    }
+}
+impl<T: Float, A: BackProp<T>, B: BackProp<T>> Eval<T> for Mul<T, A, B> {
+    fn state_len(&self) -> usize {
+        self.a.state_len() + self.b.state_len() + self.output_len() + 2 * self.back_front_len()
+    }
+    fn eval(&self, input: &[T], weights: &[T], state: &mut [T]) {
+        let (input_a, input_b) = input.split_at(self.a.input_len());
+        let (weights_a, weights_b) = weights.split_at(self.a.weights_len());
+        let (state_a, state) = state.split_at_mut(self.a.state_len());
+        let (state_b, state) = state.split_at_mut(self.b.state_len());
+        let output = &mut state[..self.output_len()];
+        self.a.eval(input_a, weights_a, state_a);
+        self.b.eval(input_b, weights_b, state_b);
+        let output_a = self.a.output(state_a);
+        let output_b = self.b.output(state_b);
+        if self.a.output_len() == self.b.output_len() {
+            output
+                .iter_mut()
+                .zip(output_a.iter().zip(output_b.iter()))
+                .for_each(|(o, (a, b))| *o = *a * *b);
+        } else if self.a.output_len() == 1 {
+            let a = output_a[0];
+            output
+                .iter_mut()
+                .zip(output_b.iter())
+                .for_each(|(o, b)| *o = a * *b);
+        } else {
+            let b = output_b[0];
+            output
+                .iter_mut()
+                .zip(output_a.iter())
+                .for_each(|(o, a)| *o = *a * b);
+        }
+    }
+    fn input_len(&self) -> usize {
+        self.a.input_len() + self.b.input_len()
+    }
+    fn output_len(&self) -> usize {
+        self.a.output_len().max(self.b.output_len())
+    }
+    fn output<'a>(&self, state: &'a [T]) -> &'a [T] {
+        &state[self.a.state_len() + self.b.state_len()..][..self.output_len()]
+    }
+    fn output_mut<'a>(&self, state: &'a mut [T]) -> &'a mut [T] {
+        let len = self.output_len();
+        &mut state[self.a.state_len() + self.b.state_len()..][..len]
+    }
+}
+impl<T: Float, A: BackProp<T>, B: BackProp<T>> BackProp<T> for Mul<T, A, B> {
+    fn back_prop(
+        &self,
+        input: &[T],
+        weights: &[T],
+        state: &[T],
+        front: &mut [T],
+        back: &mut [T],
+        gradient: &mut [T],
+    ) {
+        debug_assert!(front.len() >= self.back_front_len(), "Mul front");
+        debug_assert!(back.len() >= self.back_front_len(), "Mul back");
+        let (input_a, input_b) = input.split_at(self.a.input_len());
+        let (weights_a, weights_b) = weights.split_at(self.a.weights_len());
+        let (state_a, state) = state.split_at(self.a.state_len());
+        let state_b = &state[..self.b.state_len()];
+        let (output_a, output_b) = (self.a.output(state_a), self.b.output(state_b));
+        let (gradient_a, gradient_b) = split_gradient(gradient, self.a.weights_len());
+        let (inputs_a, inputs_b) = (self.a.input_len(), self.b.input_len());
+        let outputs = self.output_len();
+        let inner = self.inner_len();
+
+        back.copy_within(0..outputs, inner);
+        {
+            let (back, back_output) = back.split_at_mut(inner);
+            let (front, front_input) = front.split_at_mut(inner);
+            product_rule(
+                &back_output[..outputs],
+                output_b,
+                &mut back[..self.a.output_len()],
+            );
+            self.a
+                .back_prop(input_a, weights_a, state_a, front, back, gradient_a);
+            front_input[..inputs_a].copy_from_slice(&front[..inputs_a]);
+
+            product_rule(
+                &back_output[..outputs],
+                output_a,
+                &mut back[..self.b.output_len()],
+            );
+            self.b
+                .back_prop(input_b, weights_b, state_b, front, back, gradient_b);
+            back[..inputs_a].copy_from_slice(&front_input[..inputs_a]);
+        }
+        front.copy_within(0..inputs_b, inputs_a);
+        front[..inputs_a].copy_from_slice(&back[..inputs_a]);
+
+        if self.tied() {
+            gradient_a
+                .iter_mut()
+                .zip(gradient_b.iter_mut())
+                .for_each(|(a, b)| {
+                    *a = *a + *b;
+                    *b = *a
+                });
+        }
+    }
+    fn back_front_len(&self) -> usize {
+        self.inner_len() + self.a.input_len().max(self.output_len())
+    }
+}
+impl<T: Float, A: BackProp<T>, B: BackProp<T>> Gradient<T> for Mul<T, A, B> {
+    fn compute_gradient(&self, input: &[T], weights: &[T], state: &mut [T], gradient: &mut [T]) {
+        let len = self.back_front_len();
+        let (state, tmp) =
+            state.split_at_mut(self.a.state_len() + self.b.state_len() + self.output_len());
+        let (back, front) = tmp.split_at_mut(len);
+        back[..self.output_len()].fill(T::one());
+        self.back_prop(input, weights, state, front, back, gradient);
+    }
+    fn objective(&self, state: &[T]) -> T {
+        self.output(state).iter().fold(T::zero(), |acc, &o| acc + o)
+    }
+}
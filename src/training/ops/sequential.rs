@@ -0,0 +1,81 @@
+use std::marker::PhantomData;
+
+use num::Float;
+
+use crate::training::{BackProp, Eval, Weights, split_gradient};
+
+/// Feed the output of `a` as the input of `b`.
+pub struct Sequential<T: Float, A: BackProp<T>, B: BackProp<T>> {
+    a: A,
+    b: B,
+    _phantom: PhantomData<T>,
+}
+pub fn sequential<T: Float, A: BackProp<T>, B: BackProp<T>>(a: A, b: B) -> Sequential<T, A, B> {
+    debug_assert!(a.output_len() == b.input_len(), "output len");
+    Sequential {
+        a,
+        b,
+        _phantom: PhantomData,
+    }
+}
+
+impl<T: Float, A: BackProp<T>, B: BackProp<T>> Weights<T> for Sequential<T, A, B> {
+    fn weights_len(&self) -> usize {
+        self.a.weights_len() + self.b.weights_len()
+    }
+}
+impl<T: Float, A: BackProp<T>, B: BackProp<T>> Eval<T> for Sequential<T, A, B> {
+    fn state_len(&self) -> usize {
+        self.a.state_len() + self.b.state_len()
+    }
+    fn eval(&self, input: &[T], weights: &[T], state: &mut [T]) {
+        let (weights_a, weights_b) = weights.split_at(self.a.weights_len());
+        let (state_a, state_b) = state.split_at_mut(self.a.state_len());
+        self.a.eval(input, weights_a, state_a);
+        self.b.eval(self.a.output(state_a), weights_b, state_b);
+    }
+    fn input_len(&self) -> usize {
+        self.a.input_len()
+    }
+    fn output_len(&self) -> usize {
+        self.b.output_len()
+    }
+    fn output<'a>(&self, state: &'a [T]) -> &'a [T] {
+        self.b.output(&state[self.a.state_len()..])
+    }
+    fn output_mut<'a>(&self, state: &'a mut [T]) -> &'a mut [T] {
+        self.b.output_mut(&mut state[self.a.state_len()..])
+    }
+}
+impl<T: Float, A: BackProp<T>, B: BackProp<T>> BackProp<T> for Sequential<T, A, B> {
+    fn back_prop(
+        &self,
+        input: &[T],
+        weights: &[T],
+        state: &[T],
+        front: &mut [T],
+        back: &mut [T],
+        gradient: &mut [T],
+    ) {
+        debug_assert!(front.len() >= self.back_front_len(), "Sequential front");
+        debug_assert!(back.len() >= self.back_front_len(), "Sequential back");
+        let (weights_a, weights_b) = weights.split_at(self.a.weights_len());
+        let (state_a, state_b) = state.split_at(self.a.state_len());
+        let (gradient_a, gradient_b) = split_gradient(gradient, self.a.weights_len());
+        self.b.back_prop(
+            self.a.output(state_a),
+            weights_b,
+            state_b,
+            front,
+            back,
+            gradient_b,
+        );
+        let len = self.a.output_len();
+        back[..len].copy_from_slice(&front[..len]);
+        self.a
+            .back_prop(input, weights_a, state_a, front, back, gradient_a);
+    }
+    fn back_front_len(&self) -> usize {
+        self.a.back_front_len().max(self.b.back_front_len())
+    }
+}
@@ -1,2 +1,7 @@
 pub mod adam;
 pub mod adamax;
+pub mod adamw;
+pub mod es;
+pub mod lion;
+pub mod rmsprop;
+pub mod sgd;
@@ -1,6 +1,6 @@
 use num::Float;
 
-use crate::training::Optimizer;
+use crate::training::{Optimizer, checkpoint::OptimizerState, serialize::LoadError};
 
 pub struct Adam<T: Float> {
     alpha: T,
@@ -60,4 +60,45 @@ impl<T: Float + std::fmt::Display> Optimizer<T> for Adam<T> {
             .zip(self.moment_1.iter().zip(self.moment_2.iter()))
             .for_each(|(w, (&m1, &m2))| *w = *w - alpha_step * m1 / (m2.sqrt() + epsilon_step));
     }
+    fn learning_rate(&self) -> T {
+        self.alpha
+    }
+    fn set_learning_rate(&mut self, alpha: T) {
+        self.alpha = alpha;
+    }
+    fn state(&self) -> OptimizerState<T> {
+        OptimizerState {
+            name: "adam".to_string(),
+            scalars: vec![
+                self.alpha,
+                self.beta_1,
+                self.beta_2,
+                self.epsilon,
+                self.cumulated_beta_1,
+                self.cumulated_beta_2,
+            ],
+            counters: Vec::new(),
+            buffers: vec![self.moment_1.clone(), self.moment_2.clone()],
+        }
+    }
+    fn load_state(&mut self, state: &OptimizerState<T>) -> Result<(), LoadError> {
+        state.check("adam", 6, 2, self.moment_1.len())?;
+        let [
+            alpha,
+            beta_1,
+            beta_2,
+            epsilon,
+            cumulated_beta_1,
+            cumulated_beta_2,
+        ] = state.scalars[..].try_into().unwrap();
+        self.alpha = alpha;
+        self.beta_1 = beta_1;
+        self.beta_2 = beta_2;
+        self.epsilon = epsilon;
+        self.cumulated_beta_1 = cumulated_beta_1;
+        self.cumulated_beta_2 = cumulated_beta_2;
+        self.moment_1.copy_from_slice(&state.buffers[0]);
+        self.moment_2.copy_from_slice(&state.buffers[1]);
+        Ok(())
+    }
 }
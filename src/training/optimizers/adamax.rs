@@ -1,6 +1,6 @@
 use num::Float;
 
-use crate::training::Optimizer;
+use crate::training::{Optimizer, checkpoint::OptimizerState, serialize::LoadError};
 
 pub struct AdaMax<T: Float> {
     alpha: T,
@@ -53,4 +53,45 @@ impl<T: Float> Optimizer<T> for AdaMax<T> {
             .zip(self.moment_1.iter().zip(self.moment_inf.iter()))
             .for_each(|(w, (&m1, &minf))| *w = *w - alpha_step * m1 / (minf + self.epsilon));
     }
+    fn learning_rate(&self) -> T {
+        self.alpha
+    }
+    fn set_learning_rate(&mut self, alpha: T) {
+        self.alpha = alpha;
+    }
+    fn state(&self) -> OptimizerState<T> {
+        OptimizerState {
+            name: "adamax".to_string(),
+            scalars: vec![
+                self.alpha,
+                self.beta_1,
+                self.beta_2,
+                self.epsilon,
+                self.cumulated_beta_1,
+                self.cumulated_beta_2,
+            ],
+            counters: Vec::new(),
+            buffers: vec![self.moment_1.clone(), self.moment_inf.clone()],
+        }
+    }
+    fn load_state(&mut self, state: &OptimizerState<T>) -> Result<(), LoadError> {
+        state.check("adamax", 6, 2, self.moment_1.len())?;
+        let [
+            alpha,
+            beta_1,
+            beta_2,
+            epsilon,
+            cumulated_beta_1,
+            cumulated_beta_2,
+        ] = state.scalars[..].try_into().unwrap();
+        self.alpha = alpha;
+        self.beta_1 = beta_1;
+        self.beta_2 = beta_2;
+        self.epsilon = epsilon;
+        self.cumulated_beta_1 = cumulated_beta_1;
+        self.cumulated_beta_2 = cumulated_beta_2;
+        self.moment_1.copy_from_slice(&state.buffers[0]);
+        self.moment_inf.copy_from_slice(&state.buffers[1]);
+        Ok(())
+    }
 }
@@ -0,0 +1,118 @@
+use num::Float;
+
+use crate::training::{Optimizer, checkpoint::OptimizerState, serialize::LoadError};
+
+/// Adam with decoupled weight decay (Loshchilov & Hutter): the weights are shrunk by
+/// `alpha * weight_decay` at each step, independently of the moments. The biases are decayed too.
+pub struct AdamW<T: Float> {
+    alpha: T,
+    beta_1: T,
+    beta_2: T,
+    epsilon: T,
+    weight_decay: T,
+    cumulated_beta_1: T,
+    cumulated_beta_2: T,
+    moment_1: Box<[T]>,
+    moment_2: Box<[T]>,
+}
+
+impl<T: Float> AdamW<T> {
+    pub fn new(weights_len: usize) -> Self {
+        AdamW {
+            alpha: T::from(0.001).unwrap(),
+            beta_1: T::from(0.9).unwrap(),
+            beta_2: T::from(0.999).unwrap(),
+            epsilon: T::from(1e-8).unwrap(),
+            weight_decay: T::from(0.01).unwrap(),
+            cumulated_beta_1: T::one(),
+            cumulated_beta_2: T::one(),
+            moment_1: vec![T::zero(); weights_len].into_boxed_slice(),
+            moment_2: vec![T::zero(); weights_len].into_boxed_slice(),
+        }
+    }
+    pub fn with_alpha(mut self, alpha: T) -> Self {
+        self.alpha = alpha;
+        self
+    }
+    pub fn with_weight_decay(mut self, weight_decay: T) -> Self {
+        self.weight_decay = weight_decay;
+        self
+    }
+    pub fn with_parameters(mut self, alpha: T, beta_1: T, beta_2: T, epsilon: T) -> Self {
+        self.alpha = alpha;
+        self.beta_1 = beta_1;
+        self.beta_2 = beta_2;
+        self.epsilon = epsilon;
+        self
+    }
+}
+
+impl<T: Float> Optimizer<T> for AdamW<T> {
+    fn step(&mut self, weights: &mut [T], gradient: &mut [T]) {
+        self.cumulated_beta_1 = self.cumulated_beta_1 * self.beta_1;
+        self.cumulated_beta_2 = self.cumulated_beta_2 * self.beta_2;
+        self.moment_1
+            .iter_mut()
+            .zip(gradient.iter())
+            .for_each(|(m1, g)| *m1 = self.beta_1 * *m1 + (T::one() - self.beta_1) * *g);
+        self.moment_2
+            .iter_mut()
+            .zip(gradient.iter())
+            .for_each(|(m2, g)| *m2 = self.beta_2 * *m2 + (T::one() - self.beta_2) * (*g * *g));
+        let c1 = T::one() - self.cumulated_beta_1;
+        let c2 = (T::one() - self.cumulated_beta_2).sqrt();
+        let alpha_step = self.alpha * c2 / c1;
+        let epsilon_step = self.epsilon * c2;
+        let decay = T::one() - self.alpha * self.weight_decay;
+        weights
+            .iter_mut()
+            .zip(self.moment_1.iter().zip(self.moment_2.iter()))
+            .for_each(|(w, (&m1, &m2))| {
+                *w = *w * decay - alpha_step * m1 / (m2.sqrt() + epsilon_step)
+            });
+    }
+    fn learning_rate(&self) -> T {
+        self.alpha
+    }
+    fn set_learning_rate(&mut self, alpha: T) {
+        self.alpha = alpha;
+    }
+    fn state(&self) -> OptimizerState<T> {
+        OptimizerState {
+            name: "adamw".to_string(),
+            scalars: vec![
+                self.alpha,
+                self.beta_1,
+                self.beta_2,
+                self.epsilon,
+                self.weight_decay,
+                self.cumulated_beta_1,
+                self.cumulated_beta_2,
+            ],
+            counters: Vec::new(),
+            buffers: vec![self.moment_1.clone(), self.moment_2.clone()],
+        }
+    }
+    fn load_state(&mut self, state: &OptimizerState<T>) -> Result<(), LoadError> {
+        state.check("adamw", 7, 2, self.moment_1.len())?;
+        let [
+            alpha,
+            beta_1,
+            beta_2,
+            epsilon,
+            weight_decay,
+            cumulated_beta_1,
+            cumulated_beta_2,
+        ] = state.scalars[..].try_into().unwrap();
+        self.alpha = alpha;
+        self.beta_1 = beta_1;
+        self.beta_2 = beta_2;
+        self.epsilon = epsilon;
+        self.weight_decay = weight_decay;
+        self.cumulated_beta_1 = cumulated_beta_1;
+        self.cumulated_beta_2 = cumulated_beta_2;
+        self.moment_1.copy_from_slice(&state.buffers[0]);
+        self.moment_2.copy_from_slice(&state.buffers[1]);
+        Ok(())
+    }
+}
@@ -0,0 +1,352 @@
+use std::cmp::Ordering;
+
+use num::Float;
+use rand::{SeedableRng, rngs::StdRng};
+use rand_distr::{Distribution, StandardNormal};
+
+use crate::training::Optimizer;
+
+/// Orders the fitness with NaN below everything else, so that a diverged candidate ranks last
+/// instead of aborting the sort.
+fn fitness_cmp<T: Float>(a: T, b: T) -> Ordering {
+    match (a.is_nan(), b.is_nan()) {
+        (true, true) => Ordering::Equal,
+        (true, false) => Ordering::Less,
+        (false, true) => Ordering::Greater,
+        (false, false) => a.partial_cmp(&b).unwrap(),
+    }
+}
+
+/// Centered ranks in $[-0.5, 0.5]$, so that the update does not depend on the scale of the fitness.
+/// `order` is a buffer of the same length as `fitness`.
+pub fn rank_shaping<T: Float>(fitness: &[T], order: &mut [usize], shaped: &mut [T]) {
+    debug_assert!(fitness.len() == order.len() && fitness.len() == shaped.len());
+    let n = fitness.len();
+    order.iter_mut().enumerate().for_each(|(i, o)| *o = i);
+    order.sort_unstable_by(|&a, &b| fitness_cmp(fitness[a], fitness[b]));
+    let scale = T::from(n.max(2) - 1).unwrap().recip();
+    let half = T::from(0.5).unwrap();
+    order
+        .iter()
+        .enumerate()
+        .for_each(|(rank, &i)| shaped[i] = T::from(rank).unwrap() * scale - half);
+}
+
+/// OpenAI Evolution Strategies: the gradient of the Gaussian smoothed fitness
+/// $\nabla_\theta E_\epsilon[F(\theta + \sigma \epsilon)]$ is estimated with `pairs` antithetic
+/// perturbations $\pm\epsilon$, then given to any `Optimizer`. The fitness is maximized.
+pub struct Es<T: Float>
+where
+    StandardNormal: Distribution<T>,
+{
+    sigma: T,
+    rank_shaping: bool,
+    weight_decay: T,
+    noise: Box<[T]>,
+    fitness: Box<[T]>,
+    shaped: Box<[T]>,
+    order: Box<[usize]>,
+    candidate: Box<[T]>,
+    gradient: Box<[T]>,
+    rng: StdRng,
+}
+
+impl<T: Float> Es<T>
+where
+    StandardNormal: Distribution<T>,
+{
+    pub fn new(weights_len: usize, pairs: usize) -> Self {
+        debug_assert!(pairs > 0, "the population must not be empty");
+        Es {
+            sigma: T::from(0.02).unwrap(),
+            rank_shaping: true,
+            weight_decay: T::zero(),
+            noise: vec![T::zero(); pairs * weights_len].into_boxed_slice(),
+            fitness: vec![T::zero(); 2 * pairs].into_boxed_slice(),
+            shaped: vec![T::zero(); 2 * pairs].into_boxed_slice(),
+            order: vec![0; 2 * pairs].into_boxed_slice(),
+            candidate: vec![T::zero(); weights_len].into_boxed_slice(),
+            gradient: vec![T::zero(); weights_len].into_boxed_slice(),
+            rng: StdRng::from_os_rng(),
+        }
+    }
+    /// Standard deviation of the perturbations
+    pub fn with_sigma(mut self, sigma: T) -> Self {
+        self.sigma = sigma;
+        self
+    }
+    /// Use the raw fitness differences instead of the centered ranks, which gives an unbiased estimate
+    /// of the gradient of the smoothed fitness.
+    pub fn with_rank_shaping(mut self, rank_shaping: bool) -> Self {
+        self.rank_shaping = rank_shaping;
+        self
+    }
+    /// L2 penalty $\lambda \|\theta\|^2 / 2$ subtracted from the fitness
+    pub fn with_weight_decay(mut self, weight_decay: T) -> Self {
+        self.weight_decay = weight_decay;
+        self
+    }
+    /// Seed the perturbations, for reproducible runs
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.rng = StdRng::seed_from_u64(seed);
+        self
+    }
+    pub fn pairs(&self) -> usize {
+        self.fitness.len() / 2
+    }
+    /// Fitness of the last population, in the order $+\epsilon_0, -\epsilon_0, +\epsilon_1, \dots$
+    pub fn fitness(&self) -> &[T] {
+        &self.fitness
+    }
+
+    /// Evaluate the population with `fitness(weights)` and return the estimated ascent direction.
+    pub fn estimate_gradient(&mut self, weights: &[T], mut fitness: impl FnMut(&[T]) -> T) -> &[T] {
+        debug_assert!(weights.len() == self.candidate.len(), "weights length");
+        let n = weights.len();
+        for (k, eps) in self.noise.chunks_exact_mut(n).enumerate() {
+            eps.iter_mut()
+                .for_each(|e| *e = StandardNormal.sample(&mut self.rng));
+            for (s, sign) in [T::one(), -T::one()].into_iter().enumerate() {
+                self.candidate
+                    .iter_mut()
+                    .zip(weights.iter().zip(eps.iter()))
+                    .for_each(|(c, (&w, &e))| *c = w + sign * self.sigma * e);
+                self.fitness[2 * k + s] = fitness(&self.candidate);
+            }
+        }
+        if self.rank_shaping {
+            rank_shaping(&self.fitness, &mut self.order, &mut self.shaped);
+        } else {
+            // NOTE: a pair with a fitness which is not finite is left out of the estimate instead
+            // of turning the whole gradient into NaN
+            self.shaped
+                .chunks_exact_mut(2)
+                .zip(self.fitness.chunks_exact(2))
+                .for_each(|(shaped, fitness)| {
+                    if fitness.iter().all(|f| f.is_finite()) {
+                        shaped.copy_from_slice(fitness);
+                    } else {
+                        shaped.fill(T::zero());
+                    }
+                });
+        }
+
+        let pairs = T::from(self.pairs()).unwrap();
+        let normalization = (pairs * (T::one() + T::one()) * self.sigma).recip();
+        self.gradient
+            .iter_mut()
+            .zip(weights.iter())
+            .for_each(|(g, &w)| *g = -self.weight_decay * w);
+        for (eps, shaped) in self.noise.chunks_exact(n).zip(self.shaped.chunks_exact(2)) {
+            let scale = (shaped[0] - shaped[1]) * normalization;
+            self.gradient
+                .iter_mut()
+                .zip(eps.iter())
+                .for_each(|(g, &e)| *g = *g + scale * e);
+        }
+        &self.gradient
+    }
+
+    /// One generation: estimate the gradient and let `optimizer` step in the ascent direction.
+    /// Returns the mean of the finite fitness of the population, NaN if none is finite.
+    pub fn step<O: Optimizer<T>>(
+        &mut self,
+        optimizer: &mut O,
+        weights: &mut [T],
+        fitness: impl FnMut(&[T]) -> T,
+    ) -> T {
+        self.estimate_gradient(weights, fitness);
+        // NOTE: optimizers perform gradient descent
+        self.gradient.iter_mut().for_each(|g| *g = -*g);
+        optimizer.step(weights, &mut self.gradient);
+        let (sum, count) = self
+            .fitness
+            .iter()
+            .filter(|f| f.is_finite())
+            .fold((T::zero(), 0), |(sum, count), &f| (sum + f, count + 1));
+        sum / T::from(count).unwrap()
+    }
+}
+
+/// Separable CMA-ES (Ros & Hansen, 2008): CMA-ES restricted to a diagonal covariance, so that a
+/// generation is linear in the number of weights. The fitness is maximized and the mean of the
+/// search distribution is the current solution.
+pub struct SeparableCmaEs<T: Float>
+where
+    StandardNormal: Distribution<T>,
+{
+    mean: Box<[T]>,
+    sigma: T,
+    variances: Box<[T]>,
+    path_sigma: Box<[T]>,
+    path_c: Box<[T]>,
+    recombination: Box<[T]>,
+    mu_eff: T,
+    c_sigma: T,
+    d_sigma: T,
+    c_c: T,
+    c_1: T,
+    c_mu: T,
+    expected_norm: T,
+    generation: usize,
+    noise: Box<[T]>,
+    candidate: Box<[T]>,
+    fitness: Box<[T]>,
+    order: Box<[usize]>,
+    best: Box<[T]>,
+    best_fitness: T,
+    rng: StdRng,
+}
+
+impl<T: Float> SeparableCmaEs<T>
+where
+    StandardNormal: Distribution<T>,
+{
+    /// Start from `mean` with step size `sigma` and the default population size $4 + 3 \ln n$.
+    pub fn new(mean: &[T], sigma: T) -> Self {
+        let n = mean.len() as f64;
+        Self::with_population(mean, sigma, 4 + (3.0 * n.ln()).floor() as usize)
+    }
+    pub fn with_population(mean: &[T], sigma: T, population: usize) -> Self {
+        debug_assert!(
+            population >= 2,
+            "the population must hold at least two candidates"
+        );
+        let n = mean.len() as f64;
+        let mu = population / 2;
+        let raw: Vec<f64> = (1..=mu)
+            .map(|i| (mu as f64 + 0.5).ln() - (i as f64).ln())
+            .collect();
+        let sum: f64 = raw.iter().sum();
+        let mu_eff = sum * sum / raw.iter().map(|w| w * w).sum::<f64>();
+        let c_sigma = (mu_eff + 2.0) / (n + mu_eff + 5.0);
+        let d_sigma = 1.0 + 2.0 * (((mu_eff - 1.0) / (n + 1.0)).sqrt() - 1.0).max(0.0) + c_sigma;
+        let c_c = (4.0 + mu_eff / n) / (n + 4.0 + 2.0 * mu_eff / n);
+        // NOTE: the diagonal covariance can be learned faster than a full one by a factor (n + 2) / 3
+        let speed_up = (n + 2.0) / 3.0;
+        let c_1 = (speed_up * 2.0 / ((n + 1.3).powi(2) + mu_eff)).min(1.0);
+        let c_mu = (speed_up * 2.0 * (mu_eff - 2.0 + 1.0 / mu_eff) / ((n + 2.0).powi(2) + mu_eff))
+            .min(1.0 - c_1)
+            .max(0.0);
+        let expected_norm = n.sqrt() * (1.0 - 1.0 / (4.0 * n) + 1.0 / (21.0 * n * n));
+        let f = |x: f64| T::from(x).unwrap();
+        SeparableCmaEs {
+            mean: mean.into(),
+            sigma,
+            variances: vec![T::one(); mean.len()].into_boxed_slice(),
+            path_sigma: vec![T::zero(); mean.len()].into_boxed_slice(),
+            path_c: vec![T::zero(); mean.len()].into_boxed_slice(),
+            recombination: raw.iter().map(|&w| f(w / sum)).collect(),
+            mu_eff: f(mu_eff),
+            c_sigma: f(c_sigma),
+            d_sigma: f(d_sigma),
+            c_c: f(c_c),
+            c_1: f(c_1),
+            c_mu: f(c_mu),
+            expected_norm: f(expected_norm),
+            generation: 0,
+            noise: vec![T::zero(); population * mean.len()].into_boxed_slice(),
+            candidate: vec![T::zero(); mean.len()].into_boxed_slice(),
+            fitness: vec![T::zero(); population].into_boxed_slice(),
+            order: vec![0; population].into_boxed_slice(),
+            best: mean.into(),
+            best_fitness: T::neg_infinity(),
+            rng: StdRng::from_os_rng(),
+        }
+    }
+    /// Seed the sampling of the population, for reproducible runs
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.rng = StdRng::seed_from_u64(seed);
+        self
+    }
+    pub fn population(&self) -> usize {
+        self.fitness.len()
+    }
+    pub fn mean(&self) -> &[T] {
+        &self.mean
+    }
+    pub fn sigma(&self) -> T {
+        self.sigma
+    }
+    /// Diagonal of the covariance matrix, the actual variances being scaled by $\sigma^2$
+    pub fn variances(&self) -> &[T] {
+        &self.variances
+    }
+    /// Best candidate evaluated so far, with its fitness
+    pub fn best(&self) -> (&[T], T) {
+        (&self.best, self.best_fitness)
+    }
+
+    /// One generation: sample and evaluate the population with `fitness(weights)`, then update the
+    /// mean, the evolution paths, the covariance and the step size. Returns the best fitness of the
+    /// generation.
+    pub fn step(&mut self, mut fitness: impl FnMut(&[T]) -> T) -> T {
+        let n = self.mean.len();
+        for (z, f) in self.noise.chunks_exact_mut(n).zip(self.fitness.iter_mut()) {
+            z.iter_mut()
+                .for_each(|z| *z = StandardNormal.sample(&mut self.rng));
+            for (i, c) in self.candidate.iter_mut().enumerate() {
+                *c = self.mean[i] + self.sigma * self.variances[i].sqrt() * z[i];
+            }
+            *f = fitness(&self.candidate);
+            if *f > self.best_fitness {
+                self.best_fitness = *f;
+                self.best.copy_from_slice(&self.candidate);
+            }
+        }
+        let fitness = &self.fitness;
+        self.order.iter_mut().enumerate().for_each(|(i, o)| *o = i);
+        self.order
+            .sort_unstable_by(|&a, &b| fitness_cmp(fitness[b], fitness[a]));
+        self.generation += 1;
+
+        let one = T::one();
+        let two = one + one;
+        let elites = &self.order[..self.recombination.len()];
+        // NOTE: with a diagonal covariance $C^{-1/2} y_w$ is simply the weighted mean of the noise $z_w$
+        let c_sigma_norm = (self.c_sigma * (two - self.c_sigma) * self.mu_eff).sqrt();
+        let mut norm_sigma = T::zero();
+        for i in 0..n {
+            let z_w = elites
+                .iter()
+                .zip(self.recombination.iter())
+                .fold(T::zero(), |acc, (&k, &w)| acc + w * self.noise[k * n + i]);
+            self.mean[i] = self.mean[i] + self.sigma * self.variances[i].sqrt() * z_w;
+            self.path_sigma[i] = (one - self.c_sigma) * self.path_sigma[i] + c_sigma_norm * z_w;
+            norm_sigma = norm_sigma + self.path_sigma[i] * self.path_sigma[i];
+        }
+        let norm_sigma = norm_sigma.sqrt();
+        let correction = (one - (one - self.c_sigma).powi(2 * self.generation as i32)).sqrt();
+        let threshold = T::from(1.4 + 2.0 / (n as f64 + 1.0)).unwrap() * self.expected_norm;
+        let h_sigma = norm_sigma / correction < threshold;
+
+        let c_c_norm = (self.c_c * (two - self.c_c) * self.mu_eff).sqrt();
+        for i in 0..n {
+            let std = self.variances[i].sqrt();
+            let (y_w, rank_mu) = elites.iter().zip(self.recombination.iter()).fold(
+                (T::zero(), T::zero()),
+                |(y_w, rank_mu), (&k, &w)| {
+                    let y = std * self.noise[k * n + i];
+                    (y_w + w * y, rank_mu + w * y * y)
+                },
+            );
+            self.path_c[i] = (one - self.c_c) * self.path_c[i];
+            if h_sigma {
+                self.path_c[i] = self.path_c[i] + c_c_norm * y_w;
+            }
+            let rank_one = self.path_c[i] * self.path_c[i]
+                + if h_sigma {
+                    T::zero()
+                } else {
+                    self.c_c * (two - self.c_c) * self.variances[i]
+                };
+            self.variances[i] = (one - self.c_1 - self.c_mu) * self.variances[i]
+                + self.c_1 * rank_one
+                + self.c_mu * rank_mu;
+        }
+        self.sigma = self.sigma
+            * (self.c_sigma / self.d_sigma * (norm_sigma / self.expected_norm - one)).exp();
+
+        self.fitness[self.order[0]]
+    }
+}
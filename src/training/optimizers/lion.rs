@@ -0,0 +1,80 @@
+use num::Float;
+
+use crate::training::{Optimizer, checkpoint::OptimizerState, serialize::LoadError};
+
+/// EvoLved Sign Momentum (Chen et al.): every weight moves by exactly `alpha` in the direction of
+/// the sign of an interpolation between the momentum and the gradient. As the steps are larger
+/// than the ones of Adam, `alpha` is typically 3 to 10 times smaller.
+pub struct Lion<T: Float> {
+    alpha: T,
+    beta_1: T,
+    beta_2: T,
+    weight_decay: T,
+    momentum: Box<[T]>,
+}
+
+impl<T: Float> Lion<T> {
+    pub fn new(weights_len: usize) -> Self {
+        Lion {
+            alpha: T::from(0.0001).unwrap(),
+            beta_1: T::from(0.9).unwrap(),
+            beta_2: T::from(0.99).unwrap(),
+            weight_decay: T::zero(),
+            momentum: vec![T::zero(); weights_len].into_boxed_slice(),
+        }
+    }
+    pub fn with_alpha(mut self, alpha: T) -> Self {
+        self.alpha = alpha;
+        self
+    }
+    pub fn with_weight_decay(mut self, weight_decay: T) -> Self {
+        self.weight_decay = weight_decay;
+        self
+    }
+    /// `beta_1` interpolates the update direction and `beta_2` the momentum
+    pub fn with_parameters(mut self, alpha: T, beta_1: T, beta_2: T) -> Self {
+        self.alpha = alpha;
+        self.beta_1 = beta_1;
+        self.beta_2 = beta_2;
+        self
+    }
+}
+
+impl<T: Float> Optimizer<T> for Lion<T> {
+    fn step(&mut self, weights: &mut [T], gradient: &mut [T]) {
+        weights
+            .iter_mut()
+            .zip(self.momentum.iter_mut().zip(gradient.iter()))
+            .for_each(|(w, (m, &g))| {
+                let c = self.beta_1 * *m + (T::one() - self.beta_1) * g;
+                // NOTE: `signum` of zero is one, a zero direction must not move the weight
+                let sign = if c == T::zero() { c } else { c.signum() };
+                *w = *w - self.alpha * (sign + self.weight_decay * *w);
+                *m = self.beta_2 * *m + (T::one() - self.beta_2) * g;
+            });
+    }
+    fn learning_rate(&self) -> T {
+        self.alpha
+    }
+    fn set_learning_rate(&mut self, alpha: T) {
+        self.alpha = alpha;
+    }
+    fn state(&self) -> OptimizerState<T> {
+        OptimizerState {
+            name: "lion".to_string(),
+            scalars: vec![self.alpha, self.beta_1, self.beta_2, self.weight_decay],
+            counters: Vec::new(),
+            buffers: vec![self.momentum.clone()],
+        }
+    }
+    fn load_state(&mut self, state: &OptimizerState<T>) -> Result<(), LoadError> {
+        state.check("lion", 4, 1, self.momentum.len())?;
+        let [alpha, beta_1, beta_2, weight_decay] = state.scalars[..].try_into().unwrap();
+        self.alpha = alpha;
+        self.beta_1 = beta_1;
+        self.beta_2 = beta_2;
+        self.weight_decay = weight_decay;
+        self.momentum.copy_from_slice(&state.buffers[0]);
+        Ok(())
+    }
+}
@@ -0,0 +1,68 @@
+use num::Float;
+
+use crate::training::{Optimizer, checkpoint::OptimizerState, serialize::LoadError};
+
+/// Gradient scaled by the square root of a moving average of its square.
+pub struct RmsProp<T: Float> {
+    alpha: T,
+    rho: T,
+    epsilon: T,
+    mean_square: Box<[T]>,
+}
+
+impl<T: Float> RmsProp<T> {
+    pub fn new(weights_len: usize) -> Self {
+        RmsProp {
+            alpha: T::from(0.001).unwrap(),
+            rho: T::from(0.9).unwrap(),
+            epsilon: T::from(1e-8).unwrap(),
+            mean_square: vec![T::zero(); weights_len].into_boxed_slice(),
+        }
+    }
+    pub fn with_alpha(mut self, alpha: T) -> Self {
+        self.alpha = alpha;
+        self
+    }
+    /// `rho` is the decay of the moving average of the squared gradient
+    pub fn with_parameters(mut self, alpha: T, rho: T, epsilon: T) -> Self {
+        self.alpha = alpha;
+        self.rho = rho;
+        self.epsilon = epsilon;
+        self
+    }
+}
+
+impl<T: Float> Optimizer<T> for RmsProp<T> {
+    fn step(&mut self, weights: &mut [T], gradient: &mut [T]) {
+        weights
+            .iter_mut()
+            .zip(self.mean_square.iter_mut().zip(gradient.iter()))
+            .for_each(|(w, (s, &g))| {
+                *s = self.rho * *s + (T::one() - self.rho) * g * g;
+                *w = *w - self.alpha * g / (s.sqrt() + self.epsilon);
+            });
+    }
+    fn learning_rate(&self) -> T {
+        self.alpha
+    }
+    fn set_learning_rate(&mut self, alpha: T) {
+        self.alpha = alpha;
+    }
+    fn state(&self) -> OptimizerState<T> {
+        OptimizerState {
+            name: "rmsprop".to_string(),
+            scalars: vec![self.alpha, self.rho, self.epsilon],
+            counters: Vec::new(),
+            buffers: vec![self.mean_square.clone()],
+        }
+    }
+    fn load_state(&mut self, state: &OptimizerState<T>) -> Result<(), LoadError> {
+        state.check("rmsprop", 3, 1, self.mean_square.len())?;
+        let [alpha, rho, epsilon] = state.scalars[..].try_into().unwrap();
+        self.alpha = alpha;
+        self.rho = rho;
+        self.epsilon = epsilon;
+        self.mean_square.copy_from_slice(&state.buffers[0]);
+        Ok(())
+    }
+}
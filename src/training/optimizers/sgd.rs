@@ -0,0 +1,76 @@
+use num::Float;
+
+use crate::training::{Optimizer, checkpoint::OptimizerState, serialize::LoadError};
+
+/// Stochastic gradient descent with optional heavy-ball or Nesterov momentum.
+pub struct Sgd<T: Float> {
+    alpha: T,
+    momentum: T,
+    nesterov: bool,
+    velocity: Box<[T]>,
+}
+
+impl<T: Float> Sgd<T> {
+    pub fn new(weights_len: usize) -> Self {
+        Sgd {
+            alpha: T::from(0.01).unwrap(),
+            momentum: T::zero(),
+            nesterov: false,
+            velocity: vec![T::zero(); weights_len].into_boxed_slice(),
+        }
+    }
+    pub fn with_alpha(mut self, alpha: T) -> Self {
+        self.alpha = alpha;
+        self
+    }
+    pub fn with_parameters(mut self, alpha: T, momentum: T, nesterov: bool) -> Self {
+        self.alpha = alpha;
+        self.momentum = momentum;
+        self.nesterov = nesterov;
+        self
+    }
+}
+
+impl<T: Float> Optimizer<T> for Sgd<T> {
+    fn step(&mut self, weights: &mut [T], gradient: &mut [T]) {
+        weights
+            .iter_mut()
+            .zip(self.velocity.iter_mut().zip(gradient.iter()))
+            .for_each(|(w, (v, &g))| {
+                *v = self.momentum * *v + g;
+                let direction = if self.nesterov {
+                    g + self.momentum * *v
+                } else {
+                    *v
+                };
+                *w = *w - self.alpha * direction;
+            });
+    }
+    fn learning_rate(&self) -> T {
+        self.alpha
+    }
+    fn set_learning_rate(&mut self, alpha: T) {
+        self.alpha = alpha;
+    }
+    fn state(&self) -> OptimizerState<T> {
+        OptimizerState {
+            name: "sgd".to_string(),
+            scalars: vec![
+                self.alpha,
+                self.momentum,
+                if self.nesterov { T::one() } else { T::zero() },
+            ],
+            counters: Vec::new(),
+            buffers: vec![self.velocity.clone()],
+        }
+    }
+    fn load_state(&mut self, state: &OptimizerState<T>) -> Result<(), LoadError> {
+        state.check("sgd", 3, 1, self.velocity.len())?;
+        let [alpha, momentum, nesterov] = state.scalars[..].try_into().unwrap();
+        self.alpha = alpha;
+        self.momentum = momentum;
+        self.nesterov = nesterov != T::zero();
+        self.velocity.copy_from_slice(&state.buffers[0]);
+        Ok(())
+    }
+}
@@ -0,0 +1,183 @@
+use num::Float;
+use rand::{SeedableRng, rngs::StdRng};
+use rand_distr::{Distribution, StandardNormal};
+
+/// Cross-entropy method over action sequences of `horizon` steps for model-predictive control.
+/// Candidates are sampled from a diagonal Gaussian which is refitted to the elites at each
+/// iteration. After planning, the solution is shifted by one step to warm-start the next call.
+pub struct Cem<T: Float>
+where
+    StandardNormal: Distribution<T>,
+{
+    horizon: usize,
+    action_len: usize,
+    elites: usize,
+    iterations: usize,
+    initial_std: T,
+    min_std: T,
+    smoothing: T,
+    low: T,
+    high: T,
+    mean: Box<[T]>,
+    std: Box<[T]>,
+    samples: Box<[T]>,
+    scores: Box<[T]>,
+    order: Box<[usize]>,
+    action: Box<[T]>,
+    rollout_state: Vec<T>,
+    rng: StdRng,
+}
+
+impl<T: Float> Cem<T>
+where
+    StandardNormal: Distribution<T>,
+{
+    pub fn new(horizon: usize, action_len: usize, population: usize, elites: usize) -> Self {
+        debug_assert!(
+            0 < elites && elites <= population,
+            "the elites must be a non empty part of the population"
+        );
+        let len = horizon * action_len;
+        Cem {
+            horizon,
+            action_len,
+            elites,
+            iterations: 5,
+            initial_std: T::one(),
+            min_std: T::from(1e-3).unwrap(),
+            smoothing: T::zero(),
+            low: T::neg_infinity(),
+            high: T::infinity(),
+            mean: vec![T::zero(); len].into_boxed_slice(),
+            std: vec![T::one(); len].into_boxed_slice(),
+            samples: vec![T::zero(); population * len].into_boxed_slice(),
+            scores: vec![T::zero(); population].into_boxed_slice(),
+            order: vec![0; population].into_boxed_slice(),
+            action: vec![T::zero(); action_len].into_boxed_slice(),
+            rollout_state: vec![],
+            rng: StdRng::from_os_rng(),
+        }
+    }
+    pub fn with_iterations(mut self, iterations: usize) -> Self {
+        self.iterations = iterations;
+        self
+    }
+    /// Standard deviation of the sampling distribution at the start of each call to `plan`
+    pub fn with_initial_std(mut self, std: T) -> Self {
+        self.initial_std = std;
+        self
+    }
+    /// Lower bound of the standard deviation, which prevents a premature collapse
+    pub fn with_min_std(mut self, min_std: T) -> Self {
+        self.min_std = min_std;
+        self
+    }
+    /// The refitted distribution is `smoothing * old + (1 - smoothing) * elites`
+    pub fn with_smoothing(mut self, smoothing: T) -> Self {
+        self.smoothing = smoothing;
+        self
+    }
+    /// Every action is clamped to `[low, high]`
+    pub fn with_bounds(mut self, low: T, high: T) -> Self {
+        self.low = low;
+        self.high = high;
+        self.mean.iter_mut().for_each(|m| *m = m.max(low).min(high));
+        self
+    }
+    /// Seed the sampling of the candidates, for reproducible plans
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.rng = StdRng::seed_from_u64(seed);
+        self
+    }
+    pub fn horizon(&self) -> usize {
+        self.horizon
+    }
+    pub fn action_len(&self) -> usize {
+        self.action_len
+    }
+    pub fn population(&self) -> usize {
+        self.scores.len()
+    }
+    /// Current action sequence, `horizon` actions of `action_len` values
+    pub fn mean(&self) -> &[T] {
+        &self.mean
+    }
+    /// Forget the previous solution, for instance at the start of an episode.
+    pub fn reset(&mut self) {
+        let zero = T::zero().max(self.low).min(self.high);
+        self.mean.iter_mut().for_each(|m| *m = zero);
+    }
+
+    /// Optimize the action sequence where `score(actions)` returns the return of the sequence, then
+    /// return the first action and shift the solution for the next call.
+    pub fn plan(&mut self, mut score: impl FnMut(&[T]) -> T) -> &[T] {
+        let len = self.mean.len();
+        self.std.iter_mut().for_each(|s| *s = self.initial_std);
+        for _ in 0..self.iterations {
+            for (sample, s) in self
+                .samples
+                .chunks_exact_mut(len)
+                .zip(self.scores.iter_mut())
+            {
+                for (a, (&m, &std)) in sample.iter_mut().zip(self.mean.iter().zip(self.std.iter()))
+                {
+                    let xi: T = StandardNormal.sample(&mut self.rng);
+                    *a = (m + std * xi).max(self.low).min(self.high);
+                }
+                let value = score(sample);
+                // NOTE: a diverged rollout is the worst candidate rather than a panic in the sort
+                *s = if value.is_nan() {
+                    T::neg_infinity()
+                } else {
+                    value
+                };
+            }
+            let scores = &self.scores;
+            self.order.iter_mut().enumerate().for_each(|(i, o)| *o = i);
+            self.order
+                .sort_unstable_by(|&a, &b| scores[b].partial_cmp(&scores[a]).unwrap());
+
+            let elites = &self.order[..self.elites];
+            let count = T::from(self.elites).unwrap();
+            for i in 0..len {
+                let mean = elites
+                    .iter()
+                    .fold(T::zero(), |acc, &k| acc + self.samples[k * len + i])
+                    / count;
+                let variance = elites.iter().fold(T::zero(), |acc, &k| {
+                    acc + (self.samples[k * len + i] - mean).powi(2)
+                }) / count;
+                self.mean[i] = self.smoothing * self.mean[i] + (T::one() - self.smoothing) * mean;
+                self.std[i] = (self.smoothing * self.std[i]
+                    + (T::one() - self.smoothing) * variance.sqrt())
+                .max(self.min_std);
+            }
+        }
+
+        // NOTE: warm start, the last action stays in place so it is repeated
+        let a = self.action_len;
+        self.action.copy_from_slice(&self.mean[..a]);
+        self.mean.copy_within(a.., 0);
+        &self.action
+    }
+
+    /// `plan` where the score is the sum of the rewards returned by `step(state, action)`, which
+    /// updates `state` in place, starting from `initial_state`.
+    pub fn plan_rollout(
+        &mut self,
+        initial_state: &[T],
+        mut step: impl FnMut(&mut [T], &[T]) -> T,
+    ) -> &[T] {
+        let mut state = std::mem::take(&mut self.rollout_state);
+        let a = self.action_len;
+        self.plan(|actions| {
+            state.clear();
+            state.extend_from_slice(initial_state);
+            actions
+                .chunks_exact(a)
+                .fold(T::zero(), |acc, action| acc + step(&mut state, action))
+        });
+        self.rollout_state = state;
+        &self.action
+    }
+}
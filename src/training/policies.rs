@@ -1 +1,16 @@
+use num::Float;
+
+pub mod beta_policy;
+pub mod categorical_policy;
 pub mod normal_policy;
+pub mod squashed_normal_policy;
+
+/// $\ln(1 + e^x)$ computed without overflow
+fn softplus<T: Float>(x: T) -> T {
+    x.max(T::zero()) + (-x.abs()).exp().ln_1p()
+}
+
+/// Derivative of `softplus`
+fn sigmoid<T: Float>(x: T) -> T {
+    T::one() / (T::one() + (-x).exp())
+}
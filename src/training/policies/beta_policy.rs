@@ -0,0 +1,259 @@
+use num::Float;
+use rand::Rng;
+use rand_distr::Distribution;
+
+use crate::training::{BackProp, Eval, Gradient, StochasticPolicy, Weights, mlp::MLP};
+
+use super::{sigmoid, softplus};
+
+/// Policy sampling each action dimension from a Beta distribution rescaled to the bounds, so that
+/// no probability mass lies outside of them. The MLP outputs $z_\alpha$ for every dimension
+/// followed by $z_\beta$, and $\alpha = 1 + \mathrm{softplus}(z_\alpha)$, $\beta = 1 + \mathrm{softplus}(z_\beta)$,
+/// which keeps the distributions unimodal. The bounds are the `output_range` of the MLP when it
+/// is bounded on both sides and $[0, 1]$ otherwise, see `with_bounds`.
+/// NOTE: the output layer should be unbounded, e.g. `Id`, so that the distributions can concentrate.
+pub struct BetaPolicy<T: Float> {
+    mlp: MLP<T>,
+    low: T,
+    high: T,
+}
+
+impl<T: Float> BetaPolicy<T> {
+    pub fn new(mlp: MLP<T>) -> Self {
+        debug_assert!(
+            mlp.output_len() % 2 == 0,
+            "The MLP must output alpha and beta for each action dimension"
+        );
+        let (low, high) = match mlp.output_range() {
+            (Some(low), Some(high)) => (low, high),
+            _ => (T::zero(), T::one()),
+        };
+        BetaPolicy { mlp, low, high }
+    }
+    /// Actuator limits of the actions
+    pub fn with_bounds(mut self, low: T, high: T) -> Self {
+        debug_assert!(low < high, "low must be smaller than high");
+        self.low = low;
+        self.high = high;
+        self
+    }
+    pub fn mlp(&self) -> &MLP<T> {
+        &self.mlp
+    }
+    pub fn output_range(&self) -> (Option<T>, Option<T>) {
+        (Some(self.low), Some(self.high))
+    }
+    pub fn alphas<'a>(&self, state: &'a [T]) -> &'a [T] {
+        &state[self.alphas_offset()..][..self.output_len()]
+    }
+    pub fn betas<'a>(&self, state: &'a [T]) -> &'a [T] {
+        &state[self.alphas_offset() + self.output_len()..][..self.output_len()]
+    }
+    pub fn action<'a>(&self, state: &'a [T]) -> &'a [T] {
+        &state[self.state_len() - self.output_len()..]
+    }
+    pub fn log_probability(&self, state: &[T]) -> T {
+        let log_width = (self.high - self.low).ln();
+        self.alphas(state)
+            .iter()
+            .zip(self.betas(state).iter())
+            .zip(self.action(state).iter())
+            .fold(T::zero(), |acc, ((&alpha, &beta), &a)| {
+                let x = self.unit(a);
+                acc + (alpha - T::one()) * x.ln() + (beta - T::one()) * (-x).ln_1p()
+                    - log_beta(alpha, beta)
+                    - log_width
+            })
+    }
+    fn alphas_offset(&self) -> usize {
+        self.mlp.state_len() + 2 * self.mlp.min_back_front_len()
+    }
+    /// Position of the action in $[0, 1]$
+    fn unit(&self, a: T) -> T {
+        (a - self.low) / (self.high - self.low)
+    }
+}
+
+impl<T: Float> Weights<T> for BetaPolicy<T> {
+    fn weights_len(&self) -> usize {
+        self.mlp.weights_len()
+    }
+}
+impl<T: Float> Eval<T> for BetaPolicy<T> {
+    fn state_len(&self) -> usize {
+        self.alphas_offset() + 4 * self.output_len()
+    }
+    /// The output is the mean of the distribution
+    fn eval(&self, input: &[T], weights: &[T], state: &mut [T]) {
+        let n = self.output_len();
+        let (state, tmp) = state.split_at_mut(self.mlp.state_len());
+        self.mlp.eval(input, weights, state);
+        let z = self.mlp.output(state);
+        let (_back_front, tmp) = tmp.split_at_mut(2 * self.mlp.min_back_front_len());
+        let (alphas, tmp) = tmp.split_at_mut(n);
+        let (betas, tmp) = tmp.split_at_mut(n);
+        let means = &mut tmp[..n];
+        for i in 0..n {
+            alphas[i] = T::one() + softplus(z[i]);
+            betas[i] = T::one() + softplus(z[n + i]);
+            means[i] = self.low + (self.high - self.low) * alphas[i] / (alphas[i] + betas[i]);
+        }
+    }
+    fn input_len(&self) -> usize {
+        self.mlp.input_len()
+    }
+    fn output_len(&self) -> usize {
+        self.mlp.output_len() / 2
+    }
+    fn output<'a>(&self, state: &'a [T]) -> &'a [T] {
+        &state[self.alphas_offset() + 2 * self.output_len()..][..self.output_len()]
+    }
+    fn output_mut<'a>(&self, state: &'a mut [T]) -> &'a mut [T] {
+        let n = self.output_len();
+        &mut state[self.alphas_offset() + 2 * n..][..n]
+    }
+}
+impl<T: Float> Gradient<T> for BetaPolicy<T> {
+    fn compute_gradient(&self, input: &[T], weights: &[T], state: &mut [T], gradient: &mut [T]) {
+        self.compute_scaled_gradient(input, weights, state, T::one(), T::zero(), gradient);
+    }
+    fn objective(&self, state: &[T]) -> T {
+        self.probability(state)
+    }
+}
+impl<T: Float> StochasticPolicy<T> for BetaPolicy<T> {
+    fn probability(&self, state: &[T]) -> T {
+        self.log_probability(state).exp()
+    }
+    fn entropy(&self, state: &[T]) -> T {
+        let two = T::one() + T::one();
+        let log_width = (self.high - self.low).ln();
+        self.alphas(state)
+            .iter()
+            .zip(self.betas(state).iter())
+            .fold(T::zero(), |acc, (&alpha, &beta)| {
+                acc + log_beta(alpha, beta)
+                    - (alpha - T::one()) * digamma(alpha)
+                    - (beta - T::one()) * digamma(beta)
+                    + (alpha + beta - two) * digamma(alpha + beta)
+                    + log_width
+            })
+    }
+    fn stochastic_eval(&self, input: &[T], weights: &[T], state: &mut [T], rng: &mut impl Rng) {
+        self.eval(input, weights, state);
+        let n = self.output_len();
+        let offset = self.state_len() - n;
+        for i in 0..n {
+            let alpha = self.alphas(state)[i].to_f64().unwrap();
+            let beta = self.betas(state)[i].to_f64().unwrap();
+            let x = rand_distr::Beta::new(alpha, beta).unwrap().sample(rng);
+            // NOTE: keep away from the bounds where the log probability is infinite
+            let x = T::from(x)
+                .unwrap()
+                .max(T::epsilon())
+                .min(T::one() - T::epsilon());
+            state[offset + i] = self.low + (self.high - self.low) * x;
+        }
+    }
+    fn stochastic_output<'a>(&self, state: &'a [T]) -> &'a [T] {
+        self.action(state)
+    }
+    fn compute_scaled_gradient(
+        &self,
+        input: &[T],
+        weights: &[T],
+        state: &mut [T],
+        probability_scale: T,
+        entropy_scale: T,
+        gradient: &mut [T],
+    ) {
+        let n = self.output_len();
+        let two = T::one() + T::one();
+        let scale = self.probability(state) * probability_scale;
+        let (state, tmp) = state.split_at_mut(self.mlp.state_len());
+        let (back, tmp) = tmp.split_at_mut(self.mlp.min_back_front_len());
+        let (front, tmp) = tmp.split_at_mut(self.mlp.min_back_front_len());
+        let (alphas, tmp) = tmp.split_at(n);
+        let (betas, tmp) = tmp.split_at(n);
+        let actions = &tmp[n..];
+        let z = self.mlp.output(state);
+        for i in 0..n {
+            let (alpha, beta, x) = (alphas[i], betas[i], self.unit(actions[i]));
+            let psi_sum = digamma(alpha + beta);
+            let trigamma_sum = trigamma(alpha + beta);
+            // NOTE: dp/dalpha = p (ln x - psi(alpha) + psi(alpha + beta)) and
+            // dH/dalpha = (alpha + beta - 2) psi'(alpha + beta) - (alpha - 1) psi'(alpha)
+            let d_alpha = scale * (x.ln() - digamma(alpha) + psi_sum)
+                + entropy_scale
+                    * ((alpha + beta - two) * trigamma_sum - (alpha - T::one()) * trigamma(alpha));
+            let d_beta = scale * ((-x).ln_1p() - digamma(beta) + psi_sum)
+                + entropy_scale
+                    * ((alpha + beta - two) * trigamma_sum - (beta - T::one()) * trigamma(beta));
+            back[i] = d_alpha * sigmoid(z[i]);
+            back[n + i] = d_beta * sigmoid(z[n + i]);
+        }
+        self.mlp
+            .back_prop(input, weights, state, front, back, gradient);
+    }
+}
+
+/// $\ln B(\alpha, \beta) = \ln\Gamma(\alpha) + \ln\Gamma(\beta) - \ln\Gamma(\alpha + \beta)$
+fn log_beta<T: Float>(alpha: T, beta: T) -> T {
+    log_gamma(alpha) + log_gamma(beta) - log_gamma(alpha + beta)
+}
+
+/// Lanczos approximation for positive arguments
+fn log_gamma<T: Float>(x: T) -> T {
+    const G: f64 = 7.0;
+    const COEFFICIENTS: [f64; 9] = [
+        0.999_999_999_999_809_9,
+        676.520_368_121_885_1,
+        -1_259.139_216_722_402_8,
+        771.323_428_777_653_1,
+        -176.615_029_162_140_6,
+        12.507_343_278_686_905,
+        -0.138_571_095_265_720_12,
+        9.984_369_578_019_572e-6,
+        1.505_632_735_149_311_6e-7,
+    ];
+    let x = x.to_f64().unwrap() - 1.0;
+    let sum = COEFFICIENTS[1..]
+        .iter()
+        .enumerate()
+        .fold(COEFFICIENTS[0], |acc, (i, c)| {
+            acc + c / (x + i as f64 + 1.0)
+        });
+    let t = x + G + 0.5;
+    T::from(0.5 * (2.0 * std::f64::consts::PI).ln() + (x + 0.5) * t.ln() - t + sum.ln()).unwrap()
+}
+
+/// Recurrence up to 6 followed by the asymptotic expansion, for positive arguments
+fn digamma<T: Float>(x: T) -> T {
+    let mut x = x.to_f64().unwrap();
+    let mut result = 0.0;
+    while x < 6.0 {
+        result -= 1.0 / x;
+        x += 1.0;
+    }
+    let inv2 = 1.0 / (x * x);
+    result += x.ln()
+        - 0.5 / x
+        - inv2 * (1.0 / 12.0 - inv2 * (1.0 / 120.0 - inv2 * (1.0 / 252.0 - inv2 / 240.0)));
+    T::from(result).unwrap()
+}
+
+/// Derivative of `digamma`, same method
+fn trigamma<T: Float>(x: T) -> T {
+    let mut x = x.to_f64().unwrap();
+    let mut result = 0.0;
+    while x < 6.0 {
+        result += 1.0 / (x * x);
+        x += 1.0;
+    }
+    let inv = 1.0 / x;
+    let inv2 = inv * inv;
+    result += inv
+        + inv2 / 2.0
+        + inv * inv2 * (1.0 / 6.0 - inv2 * (1.0 / 30.0 - inv2 * (1.0 / 42.0 - inv2 / 30.0)));
+    T::from(result).unwrap()
+}
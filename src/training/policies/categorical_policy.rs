@@ -0,0 +1,170 @@
+use num::Float;
+use rand::Rng;
+
+use crate::training::{BackProp, Eval, Gradient, StochasticPolicy, Weights, mlp::MLP};
+
+/// Softmax policy over `output_len` discrete actions, where the MLP outputs the logits.
+/// The probabilities are the softmax of the logits divided by the temperature, so a high
+/// temperature explores more (Boltzmann exploration).
+pub struct CategoricalPolicy<T: Float> {
+    mlp: MLP<T>,
+    temperature: T,
+}
+
+impl<T: Float> CategoricalPolicy<T> {
+    pub fn new(mlp: MLP<T>) -> Self {
+        CategoricalPolicy {
+            mlp,
+            temperature: T::one(),
+        }
+    }
+    pub fn with_temperature(mut self, temperature: T) -> Self {
+        self.set_temperature(temperature);
+        self
+    }
+    pub fn temperature(&self) -> T {
+        self.temperature
+    }
+    pub fn set_temperature(&mut self, temperature: T) {
+        debug_assert!(temperature > T::zero(), "temperature must be positive");
+        self.temperature = temperature;
+    }
+    pub fn mlp(&self) -> &MLP<T> {
+        &self.mlp
+    }
+    pub fn probabilities<'a>(&self, state: &'a [T]) -> &'a [T] {
+        self.output(state)
+    }
+    /// Index of the action sampled by `stochastic_eval`
+    pub fn action(&self, state: &[T]) -> usize {
+        state[self.state_len() - 1].to_usize().unwrap()
+    }
+    pub fn log_probability(&self, state: &[T]) -> T {
+        self.probability(state).ln()
+    }
+    /// Index of the most probable action
+    pub fn greedy_action(&self, state: &[T]) -> usize {
+        self.probabilities(state)
+            .iter()
+            .enumerate()
+            .fold((0, T::neg_infinity()), |(best, max), (i, &p)| {
+                if p > max { (i, p) } else { (best, max) }
+            })
+            .0
+    }
+    fn probabilities_offset(&self) -> usize {
+        self.mlp.state_len() + 2 * self.mlp.min_back_front_len()
+    }
+}
+
+impl<T: Float> Weights<T> for CategoricalPolicy<T> {
+    fn weights_len(&self) -> usize {
+        self.mlp.weights_len()
+    }
+}
+impl<T: Float> Eval<T> for CategoricalPolicy<T> {
+    fn state_len(&self) -> usize {
+        self.probabilities_offset() + self.output_len() + 1
+    }
+    fn eval(&self, input: &[T], weights: &[T], state: &mut [T]) {
+        let (state, tmp) = state.split_at_mut(self.mlp.state_len());
+        self.mlp.eval(input, weights, state);
+        let logits = self.mlp.output(state);
+        let (_back_front, tmp) = tmp.split_at_mut(2 * self.mlp.min_back_front_len());
+        let probabilities = &mut tmp[..logits.len()];
+        let max = logits.iter().fold(T::neg_infinity(), |acc, &l| acc.max(l));
+        let mut sum = T::zero();
+        probabilities
+            .iter_mut()
+            .zip(logits.iter())
+            .for_each(|(p, &l)| {
+                *p = ((l - max) / self.temperature).exp();
+                sum = sum + *p;
+            });
+        probabilities.iter_mut().for_each(|p| *p = *p / sum);
+    }
+    fn input_len(&self) -> usize {
+        self.mlp.input_len()
+    }
+    fn output_len(&self) -> usize {
+        self.mlp.output_len()
+    }
+    fn output<'a>(&self, state: &'a [T]) -> &'a [T] {
+        &state[self.probabilities_offset()..self.state_len() - 1]
+    }
+    fn output_mut<'a>(&self, state: &'a mut [T]) -> &'a mut [T] {
+        let len = self.state_len();
+        &mut state[self.probabilities_offset()..len - 1]
+    }
+}
+impl<T: Float> Gradient<T> for CategoricalPolicy<T> {
+    fn compute_gradient(&self, input: &[T], weights: &[T], state: &mut [T], gradient: &mut [T]) {
+        self.compute_scaled_gradient(input, weights, state, T::one(), T::zero(), gradient);
+    }
+    fn objective(&self, state: &[T]) -> T {
+        self.probability(state)
+    }
+}
+impl<T: Float> StochasticPolicy<T> for CategoricalPolicy<T> {
+    fn probability(&self, state: &[T]) -> T {
+        self.probabilities(state)[self.action(state)]
+    }
+    fn entropy(&self, state: &[T]) -> T {
+        -self
+            .probabilities(state)
+            .iter()
+            .filter(|&&p| p > T::zero())
+            .fold(T::zero(), |acc, &p| acc + p * p.ln())
+    }
+    fn stochastic_eval(&self, input: &[T], weights: &[T], state: &mut [T], rng: &mut impl Rng) {
+        self.eval(input, weights, state);
+        let mut u = T::from(rng.random::<f64>()).unwrap();
+        let probabilities = self.probabilities(state);
+        let action = probabilities
+            .iter()
+            .position(|&p| {
+                u = u - p;
+                u < T::zero()
+            })
+            .unwrap_or(probabilities.len() - 1);
+        let len = self.state_len();
+        state[len - 1] = T::from(action).unwrap();
+    }
+    fn stochastic_output<'a>(&self, state: &'a [T]) -> &'a [T] {
+        &state[self.state_len() - 1..]
+    }
+    fn compute_scaled_gradient(
+        &self,
+        input: &[T],
+        weights: &[T],
+        state: &mut [T],
+        probability_scale: T,
+        entropy_scale: T,
+        gradient: &mut [T],
+    ) {
+        let action = self.action(state);
+        let probability = self.probability(state);
+        let entropy = self.entropy(state);
+        let (state, tmp) = state.split_at_mut(self.mlp.state_len());
+        let (back, tmp) = tmp.split_at_mut(self.mlp.min_back_front_len());
+        let (front, tmp) = tmp.split_at_mut(self.mlp.min_back_front_len());
+        let probabilities = &tmp[..self.mlp.output_len()];
+        // NOTE: with z the logits, dp_a/dz_j = p_a (δ_aj - p_j) / T and dH/dz_j = -p_j (ln p_j + H) / T
+        back.iter_mut()
+            .zip(probabilities.iter())
+            .enumerate()
+            .for_each(|(j, (b, &p))| {
+                let delta = if j == action { T::one() } else { T::zero() };
+                let d_probability = probability * (delta - p);
+                let d_entropy = if p > T::zero() {
+                    -p * (p.ln() + entropy)
+                } else {
+                    T::zero()
+                };
+                *b = (probability_scale * d_probability + entropy_scale * d_entropy)
+                    / self.temperature;
+            });
+        self.mlp
+            .back_prop(input, weights, state, front, back, gradient);
+    }
+}
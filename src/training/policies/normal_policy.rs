@@ -1,14 +1,26 @@
 use num::{Float, traits::FloatConst};
+use rand::Rng;
 use rand_distr::{Distribution, StandardNormal};
 
 use crate::training::{BackProp, Eval, Gradient, StochasticPolicy, Weights, mlp::MLP};
 
+/// How the standard deviation of each action dimension is obtained.
+#[derive(Clone, Copy, Debug)]
+pub enum StandardDeviation<T: Float> {
+    /// Same fixed sigma for every dimension
+    Fixed(T),
+    /// Trainable log standard deviation of each dimension stored after the weights of the MLP
+    Global,
+    /// The MLP outputs the means followed by the log standard deviations
+    StateDependent,
+}
+
 pub struct NormalPolicy<T: Float + FloatConst>
 where
     StandardNormal: Distribution<T>,
 {
     mlp: MLP<T>,
-    sigma: T,
+    standard_deviation: StandardDeviation<T>,
 }
 
 impl<T: Float + FloatConst> NormalPolicy<T>
@@ -16,17 +28,105 @@ where
     StandardNormal: Distribution<T>,
 {
     pub fn new(mlp: MLP<T>, sigma: T) -> Self {
-        NormalPolicy { mlp, sigma }
+        NormalPolicy {
+            mlp,
+            standard_deviation: StandardDeviation::Fixed(sigma),
+        }
+    }
+    /// Learn one log standard deviation per action dimension, see `init_log_std`.
+    pub fn with_global_log_std(mut self) -> Self {
+        self.standard_deviation = StandardDeviation::Global;
+        self
+    }
+    /// Learn the log standard deviations as the second half of the outputs of the MLP.
+    pub fn with_state_dependent_log_std(mut self) -> Self {
+        debug_assert!(
+            self.mlp.output_len() % 2 == 0,
+            "The MLP must output the means and the log standard deviations"
+        );
+        self.standard_deviation = StandardDeviation::StateDependent;
+        self
+    }
+    pub fn standard_deviation(&self) -> StandardDeviation<T> {
+        self.standard_deviation
+    }
+    /// Change the fixed sigma, which has no effect for learned standard deviations
+    pub fn set_sigma(&mut self, sigma: T) {
+        if let StandardDeviation::Fixed(s) = &mut self.standard_deviation {
+            *s = sigma;
+        }
+    }
+    /// Set the global log standard deviations in the weights
+    pub fn init_log_std(&self, weights: &mut [T], log_std: T) {
+        if let StandardDeviation::Global = self.standard_deviation {
+            weights[self.mlp.weights_len()..]
+                .iter_mut()
+                .for_each(|w| *w = log_std);
+        }
+    }
+    pub fn mlp(&self) -> &MLP<T> {
+        &self.mlp
     }
     pub fn input_len(&self) -> usize {
         self.mlp.input_len()
     }
     pub fn output_len(&self) -> usize {
-        self.mlp.output_len()
+        match self.standard_deviation {
+            StandardDeviation::StateDependent => self.mlp.output_len() / 2,
+            _ => self.mlp.output_len(),
+        }
     }
     pub fn action<'a>(&self, state: &'a [T]) -> &'a [T] {
         &state[self.state_len() - self.output_len()..]
     }
+    pub fn action_mut<'a>(&self, state: &'a mut [T]) -> &'a mut [T] {
+        let len = self.state_len();
+        &mut state[len - self.output_len()..]
+    }
+    /// Standard deviation of each action dimension computed by `eval`
+    pub fn sigmas<'a>(&self, state: &'a [T]) -> &'a [T] {
+        &state[self.sigmas_offset()..self.state_len() - self.output_len()]
+    }
+    fn sigmas_offset(&self) -> usize {
+        self.mlp.state_len() + 2 * self.mlp.min_back_front_len()
+    }
+    /// Overwrite the gradient with the back propagation of the derivatives with respect to the
+    /// mean and the log standard deviation of each action dimension, which are given by
+    /// `derivatives(dimension, mean, sigma, action)`.
+    pub(crate) fn back_prop_mean_log_std(
+        &self,
+        input: &[T],
+        weights: &[T],
+        state: &mut [T],
+        gradient: &mut [T],
+        derivatives: impl Fn(usize, T, T, T) -> (T, T),
+    ) {
+        let n = self.output_len();
+        let mlp_weights_len = self.mlp.weights_len();
+        let (state, tmp) = state.split_at_mut(self.mlp.state_len());
+        let (back, tmp) = tmp.split_at_mut(self.mlp.min_back_front_len());
+        let (front, tmp) = tmp.split_at_mut(self.mlp.min_back_front_len());
+        let (sigmas, actions) = tmp.split_at(n);
+        let (mlp_gradient, log_std_gradient) = gradient.split_at_mut(mlp_weights_len);
+        let means = &self.mlp.output(state)[..n];
+        for i in 0..n {
+            let (d_mean, d_log_std) = derivatives(i, means[i], sigmas[i], actions[i]);
+            back[i] = d_mean;
+            match self.standard_deviation {
+                StandardDeviation::Fixed(_) => {}
+                StandardDeviation::Global => log_std_gradient[i] = d_log_std,
+                StandardDeviation::StateDependent => back[n + i] = d_log_std,
+            }
+        }
+        self.mlp.back_prop(
+            input,
+            &weights[..mlp_weights_len],
+            state,
+            front,
+            back,
+            mlp_gradient,
+        );
+    }
 }
 
 impl<T: Float + FloatConst> Weights<T> for NormalPolicy<T>
@@ -34,7 +134,10 @@ where
     StandardNormal: Distribution<T>,
 {
     fn weights_len(&self) -> usize {
-        self.mlp.weights_len()
+        match self.standard_deviation {
+            StandardDeviation::Global => self.mlp.weights_len() + self.output_len(),
+            _ => self.mlp.weights_len(),
+        }
     }
 }
 impl<T: Float + FloatConst> Eval<T> for NormalPolicy<T>
@@ -42,24 +145,39 @@ where
     StandardNormal: Distribution<T>,
 {
     fn state_len(&self) -> usize {
-        self.mlp.state_len() + 2 * self.mlp.min_back_front_len() + self.output_len()
+        self.sigmas_offset() + 2 * self.output_len()
     }
 
     fn eval(&self, input: &[T], weights: &[T], state: &mut [T]) {
-        self.mlp
-            .eval(input, weights, &mut state[..self.mlp.state_len()]);
+        let n = self.output_len();
+        let (weights, log_stds) = weights.split_at(self.mlp.weights_len());
+        let (state, tmp) = state.split_at_mut(self.mlp.state_len());
+        self.mlp.eval(input, weights, state);
+        let sigmas = &mut tmp[2 * self.mlp.min_back_front_len()..][..n];
+        match self.standard_deviation {
+            StandardDeviation::Fixed(sigma) => sigmas.iter_mut().for_each(|s| *s = sigma),
+            StandardDeviation::Global => sigmas
+                .iter_mut()
+                .zip(log_stds.iter())
+                .for_each(|(s, l)| *s = l.exp()),
+            StandardDeviation::StateDependent => sigmas
+                .iter_mut()
+                .zip(self.mlp.output(state)[n..].iter())
+                .for_each(|(s, l)| *s = l.exp()),
+        }
     }
     fn input_len(&self) -> usize {
         self.mlp.input_len()
     }
     fn output_len(&self) -> usize {
-        self.mlp.output_len()
+        NormalPolicy::output_len(self)
     }
     fn output<'a>(&self, state: &'a [T]) -> &'a [T] {
-        self.mlp.output(&state[..self.mlp.state_len()])
+        &self.mlp.output(&state[..self.mlp.state_len()])[..self.output_len()]
     }
     fn output_mut<'a>(&self, state: &'a mut [T]) -> &'a mut [T] {
-        self.mlp.output_mut(&mut state[..self.mlp.state_len()])
+        let n = self.output_len();
+        &mut self.mlp.output_mut(&mut state[..self.mlp.state_len()])[..n]
     }
 }
 impl<T: Float + FloatConst> Gradient<T> for NormalPolicy<T>
@@ -67,16 +185,10 @@ where
     StandardNormal: Distribution<T>,
 {
     fn compute_gradient(&self, input: &[T], weights: &[T], state: &mut [T], gradient: &mut [T]) {
-        let probability = self.probability(state);
-        let (state, tmp) = state.split_at_mut(self.mlp.state_len());
-        let (back, tmp) = tmp.split_at_mut(self.mlp.min_back_front_len());
-        let (front, action_state) = tmp.split_at_mut(self.mlp.min_back_front_len());
-        back.iter_mut()
-            .zip(self.mlp.output(state).iter())
-            .zip(action_state.iter())
-            .for_each(|((b, &m), &a)| *b = (a - m) / self.sigma.powi(2) * probability);
-        self.mlp
-            .back_prop(input, weights, state, front, back, gradient);
+        self.compute_scaled_gradient(input, weights, state, T::one(), T::zero(), gradient);
+    }
+    fn objective(&self, state: &[T]) -> T {
+        self.probability(state)
     }
 }
 impl<T: Float + FloatConst> StochasticPolicy<T> for NormalPolicy<T>
@@ -86,30 +198,59 @@ where
     fn probability(&self, state: &[T]) -> T {
         self.output(state)
             .iter()
+            .zip(self.sigmas(state).iter())
             .zip(self.action(state).iter())
-            .fold(T::one(), |acc, (m, a)| acc * gaussian(*a, *m, self.sigma))
+            .fold(T::one(), |acc, ((m, s), a)| acc * gaussian(*a, *m, *s))
     }
-    fn stochastic_eval(&self, input: &[T], weights: &[T], state: &mut [T]) {
+    fn entropy(&self, state: &[T]) -> T {
+        let two = T::one() + T::one();
+        let half_log_two_pi_e = (two * T::PI() * T::E()).ln() / two;
+        self.sigmas(state)
+            .iter()
+            .fold(T::zero(), |acc, s| acc + half_log_two_pi_e + s.ln())
+    }
+    fn stochastic_eval(&self, input: &[T], weights: &[T], state: &mut [T], rng: &mut impl Rng) {
         self.eval(input, weights, state);
+        let n = self.output_len();
         let (state, tmp) = state.split_at_mut(self.mlp.state_len());
-        let (_back_front, action_state) = tmp.split_at_mut(2 * self.mlp.min_back_front_len());
-        let means = self.mlp.output(state);
+        let (_back_front, tmp) = tmp.split_at_mut(2 * self.mlp.min_back_front_len());
+        let (sigmas, action_state) = tmp.split_at_mut(n);
+        let means = &self.mlp.output(state)[..n];
         let (min, max) = self.mlp.output_range();
-        let limit = T::from(10.0).unwrap() * self.sigma; // NOTE: use to clamp to 10 sigma to avoid infinities
         action_state
             .iter_mut()
-            .zip(means.iter())
-            .for_each(|(a, &m)| {
+            .zip(means.iter().zip(sigmas.iter()))
+            .for_each(|(a, (&m, &sigma))| {
+                let limit = T::from(10.0).unwrap() * sigma; // NOTE: use to clamp to 10 sigma to avoid infinities
                 let min = min.unwrap_or(T::neg_infinity()).max(m - limit);
                 let max = max.unwrap_or(T::infinity()).min(m + limit);
-                *a = rand_distr::Normal::new(m, self.sigma)
+                *a = rand_distr::Normal::new(m, sigma)
                     .unwrap()
-                    .sample(&mut rand::rng())
+                    .sample(rng)
                     .clamp(min, max);
             });
     }
     fn stochastic_output<'a>(&self, state: &'a [T]) -> &'a [T] {
-        &state[self.mlp.state_len()..]
+        self.action(state)
+    }
+    fn compute_scaled_gradient(
+        &self,
+        input: &[T],
+        weights: &[T],
+        state: &mut [T],
+        probability_scale: T,
+        entropy_scale: T,
+        gradient: &mut [T],
+    ) {
+        let scale = self.probability(state) * probability_scale;
+        // NOTE: dp/dm = p (a - m) / sigma^2, dp/dlog(sigma) = p ((a - m)^2 / sigma^2 - 1) and dH/dlog(sigma) = 1
+        self.back_prop_mean_log_std(input, weights, state, gradient, |_, m, sigma, a| {
+            let z = (a - m) / sigma;
+            (
+                scale * z / sigma,
+                scale * (z * z - T::one()) + entropy_scale,
+            )
+        });
     }
 }
 fn gaussian<T: Float + FloatConst>(x: T, mean: T, sigma: T) -> T {
@@ -0,0 +1,189 @@
+use num::{Float, traits::FloatConst};
+use rand::Rng;
+use rand_distr::{Distribution, StandardNormal};
+
+use crate::training::{Eval, Gradient, StochasticPolicy, Weights};
+
+use super::{normal_policy::NormalPolicy, softplus};
+
+/// Gaussian policy whose samples $u$ are drawn without clamping and squashed into the bounds as
+/// $a = c + h \tanh(u)$, where $c$ is the center and $h$ the half width of the bounds.
+/// The density of the action is $p(a) = p(u) / \prod_i h (1 - \tanh^2 u_i)$.
+pub struct SquashedNormalPolicy<T: Float + FloatConst>
+where
+    StandardNormal: Distribution<T>,
+{
+    policy: NormalPolicy<T>,
+    low: T,
+    high: T,
+}
+
+impl<T: Float + FloatConst> SquashedNormalPolicy<T>
+where
+    StandardNormal: Distribution<T>,
+{
+    /// The last activation of the MLP of `policy` should be unbounded, the bounds are applied by the squashing.
+    pub fn new(policy: NormalPolicy<T>, low: T, high: T) -> Self {
+        debug_assert!(low < high, "low must be smaller than high");
+        SquashedNormalPolicy { policy, low, high }
+    }
+    pub fn policy(&self) -> &NormalPolicy<T> {
+        &self.policy
+    }
+    pub fn policy_mut(&mut self) -> &mut NormalPolicy<T> {
+        &mut self.policy
+    }
+    pub fn output_range(&self) -> (Option<T>, Option<T>) {
+        (Some(self.low), Some(self.high))
+    }
+    /// Unsquashed sample drawn by `stochastic_eval`
+    pub fn unsquashed_action<'a>(&self, state: &'a [T]) -> &'a [T] {
+        self.policy.action(&state[..self.policy.state_len()])
+    }
+    pub fn action<'a>(&self, state: &'a [T]) -> &'a [T] {
+        &state[self.state_len() - self.output_len()..]
+    }
+    pub fn action_mut<'a>(&self, state: &'a mut [T]) -> &'a mut [T] {
+        let len = self.state_len();
+        &mut state[len - self.output_len()..]
+    }
+    /// $\ln p(a) = \ln p(u) - \sum_i \ln(h (1 - \tanh^2 u_i))$
+    pub fn log_probability(&self, state: &[T]) -> T {
+        let policy_state = &state[..self.policy.state_len()];
+        let two = T::one() + T::one();
+        let half_log_two_pi = (two * T::PI()).ln() / two;
+        let log_probability = self
+            .policy
+            .output(policy_state)
+            .iter()
+            .zip(self.policy.sigmas(policy_state).iter())
+            .zip(self.policy.action(policy_state).iter())
+            .fold(T::zero(), |acc, ((&m, &sigma), &u)| {
+                let z = (u - m) / sigma;
+                acc - z * z / two - sigma.ln() - half_log_two_pi
+            });
+        log_probability - self.log_jacobian(state)
+    }
+    /// $\sum_i \ln(h (1 - \tanh^2 u_i))$ computed as $\ln h + 2 (\ln 2 - u - \mathrm{softplus}(-2u))$ for stability
+    fn log_jacobian(&self, state: &[T]) -> T {
+        let two = T::one() + T::one();
+        let log_half_width = self.half_width().ln();
+        self.unsquashed_action(state)
+            .iter()
+            .fold(T::zero(), |acc, &u| {
+                acc + log_half_width + two * (two.ln() - u - softplus(-two * u))
+            })
+    }
+    fn half_width(&self) -> T {
+        (self.high - self.low) / (T::one() + T::one())
+    }
+    /// $c + h \tanh(u)$
+    pub fn squash(&self, u: T) -> T {
+        (self.low + self.high) / (T::one() + T::one()) + self.half_width() * u.tanh()
+    }
+    /// Derivative of `squash`, $h (1 - \tanh^2 u)$
+    pub fn squash_derivative(&self, u: T) -> T {
+        self.half_width() * (T::one() - u.tanh().powi(2))
+    }
+}
+
+impl<T: Float + FloatConst> Weights<T> for SquashedNormalPolicy<T>
+where
+    StandardNormal: Distribution<T>,
+{
+    fn weights_len(&self) -> usize {
+        self.policy.weights_len()
+    }
+}
+impl<T: Float + FloatConst> Eval<T> for SquashedNormalPolicy<T>
+where
+    StandardNormal: Distribution<T>,
+{
+    fn state_len(&self) -> usize {
+        self.policy.state_len() + 2 * self.output_len()
+    }
+    /// The output is the squashed mean
+    fn eval(&self, input: &[T], weights: &[T], state: &mut [T]) {
+        let (policy_state, tmp) = state.split_at_mut(self.policy.state_len());
+        self.policy.eval(input, weights, policy_state);
+        tmp[..self.output_len()]
+            .iter_mut()
+            .zip(self.policy.output(policy_state).iter())
+            .for_each(|(o, &m)| *o = self.squash(m));
+    }
+    fn input_len(&self) -> usize {
+        self.policy.input_len()
+    }
+    fn output_len(&self) -> usize {
+        self.policy.output_len()
+    }
+    fn output<'a>(&self, state: &'a [T]) -> &'a [T] {
+        &state[self.policy.state_len()..][..self.output_len()]
+    }
+    fn output_mut<'a>(&self, state: &'a mut [T]) -> &'a mut [T] {
+        let n = self.output_len();
+        &mut state[self.policy.state_len()..][..n]
+    }
+}
+impl<T: Float + FloatConst> Gradient<T> for SquashedNormalPolicy<T>
+where
+    StandardNormal: Distribution<T>,
+{
+    fn compute_gradient(&self, input: &[T], weights: &[T], state: &mut [T], gradient: &mut [T]) {
+        self.compute_scaled_gradient(input, weights, state, T::one(), T::zero(), gradient);
+    }
+    fn objective(&self, state: &[T]) -> T {
+        self.probability(state)
+    }
+}
+impl<T: Float + FloatConst> StochasticPolicy<T> for SquashedNormalPolicy<T>
+where
+    StandardNormal: Distribution<T>,
+{
+    fn probability(&self, state: &[T]) -> T {
+        self.log_probability(state).exp()
+    }
+    /// One sample estimate $H(u) + \sum_i \ln(h (1 - \tanh^2 u_i))$ of the entropy, as it has no closed form
+    fn entropy(&self, state: &[T]) -> T {
+        self.policy.entropy(&state[..self.policy.state_len()]) + self.log_jacobian(state)
+    }
+    fn stochastic_eval(&self, input: &[T], weights: &[T], state: &mut [T], rng: &mut impl Rng) {
+        self.eval(input, weights, state);
+        let n = self.output_len();
+        let (policy_state, tmp) = state.split_at_mut(self.policy.state_len());
+        for i in 0..n {
+            let m = self.policy.output(policy_state)[i];
+            let sigma = self.policy.sigmas(policy_state)[i];
+            let xi: T = StandardNormal.sample(rng);
+            let u = m + sigma * xi;
+            self.policy.action_mut(policy_state)[i] = u;
+            tmp[n + i] = self.squash(u);
+        }
+    }
+    fn stochastic_output<'a>(&self, state: &'a [T]) -> &'a [T] {
+        self.action(state)
+    }
+    fn compute_scaled_gradient(
+        &self,
+        input: &[T],
+        weights: &[T],
+        state: &mut [T],
+        probability_scale: T,
+        entropy_scale: T,
+        gradient: &mut [T],
+    ) {
+        // NOTE: the Jacobian J only depends on the sampled u, so the gradient of the probability is
+        // the one of the unsquashed probability divided by J and the entropy estimate has the
+        // gradient of the unsquashed entropy
+        let inverse_jacobian = (-self.log_jacobian(state)).exp();
+        let policy_state = &mut state[..self.policy.state_len()];
+        self.policy.compute_scaled_gradient(
+            input,
+            weights,
+            policy_state,
+            probability_scale * inverse_jacobian,
+            entropy_scale,
+            gradient,
+        );
+    }
+}
@@ -0,0 +1,450 @@
+use num::Float;
+use rand::{SeedableRng, rngs::StdRng};
+
+use super::{
+    Direction, EpochStats, Eval, Gradient, OptimizeBuffers, OptimizeOptions, OptimizeReport,
+    OptimizeStats, Optimizer, StochasticPolicy, TimeStepChunks, TimeSteps, Value, Weights,
+    advantage::{self, Gae},
+    env::{Env, RolloutBuffer, Step},
+};
+
+/// Clipped surrogate objective of PPO. Its state is the state of the policy followed by the
+/// probability of the action under the policy that collected it and by the advantage of the action.
+pub struct ClippedSurrogate<T: Float, P: StochasticPolicy<T>> {
+    policy: P,
+    clip: T,
+    entropy_coefficient: T,
+}
+impl<T: Float, P: StochasticPolicy<T>> ClippedSurrogate<T, P> {
+    pub fn new(policy: P) -> Self {
+        ClippedSurrogate {
+            policy,
+            clip: T::from(0.2).unwrap(),
+            entropy_coefficient: T::zero(),
+        }
+    }
+    pub fn with_clip(mut self, clip: T) -> Self {
+        self.clip = clip;
+        self
+    }
+    pub fn with_entropy_coefficient(mut self, entropy_coefficient: T) -> Self {
+        self.entropy_coefficient = entropy_coefficient;
+        self
+    }
+    pub fn policy(&self) -> &P {
+        &self.policy
+    }
+    pub fn policy_mut(&mut self) -> &mut P {
+        &mut self.policy
+    }
+    pub fn policy_state<'a>(&self, state: &'a [T]) -> &'a [T] {
+        &state[..self.policy.state_len()]
+    }
+    pub fn policy_state_mut<'a>(&self, state: &'a mut [T]) -> &'a mut [T] {
+        &mut state[..self.policy.state_len()]
+    }
+    pub fn old_probability(&self, state: &[T]) -> T {
+        state[self.policy.state_len()]
+    }
+    pub fn set_old_probability(&self, old_probability: T, state: &mut [T]) {
+        state[self.policy.state_len()] = old_probability;
+    }
+    pub fn advantage(&self, state: &[T]) -> T {
+        state[self.policy.state_len() + 1]
+    }
+    pub fn set_advantage(&self, advantage: T, state: &mut [T]) {
+        state[self.policy.state_len() + 1] = advantage;
+    }
+    pub fn ratio(&self, state: &[T]) -> T {
+        self.policy.probability(self.policy_state(state)) / self.old_probability(state)
+    }
+    /// Estimator `(r - 1) - ln(r)` of KL(old || new) averaged over the states, with `r` the
+    /// probability ratio as of the last evaluation of each state.
+    pub fn approx_kl<S: TimeSteps<T> + ?Sized>(&self, time_steps: &S) -> T {
+        let sum = (0..time_steps.len()).fold(T::zero(), |acc, i| {
+            let r = self.ratio(time_steps.get(i).1);
+            acc + (r - T::one()) - r.ln()
+        });
+        sum / T::from(time_steps.len().max(1)).unwrap()
+    }
+}
+
+impl<T: Float, P: StochasticPolicy<T>> Weights<T> for ClippedSurrogate<T, P> {
+    fn weights_len(&self) -> usize {
+        self.policy.weights_len()
+    }
+}
+impl<T: Float, P: StochasticPolicy<T>> Eval<T> for ClippedSurrogate<T, P> {
+    fn state_len(&self) -> usize {
+        self.policy.state_len() + 2
+    }
+    fn eval(&self, input: &[T], weights: &[T], state: &mut [T]) {
+        self.policy
+            .eval(input, weights, self.policy_state_mut(state));
+    }
+    fn input_len(&self) -> usize {
+        self.policy.input_len()
+    }
+    fn output_len(&self) -> usize {
+        self.policy.output_len()
+    }
+    fn output<'a>(&self, state: &'a [T]) -> &'a [T] {
+        self.policy.output(self.policy_state(state))
+    }
+    fn output_mut<'a>(&self, state: &'a mut [T]) -> &'a mut [T] {
+        self.policy.output_mut(self.policy_state_mut(state))
+    }
+}
+impl<T: Float, P: StochasticPolicy<T>> Gradient<T> for ClippedSurrogate<T, P> {
+    fn compute_gradient(&self, input: &[T], weights: &[T], state: &mut [T], gradient: &mut [T]) {
+        let ratio = self.ratio(state);
+        let advantage = self.advantage(state);
+        // NOTE: the gradient of min(ratio * advantage, clamp(ratio, 1 - clip, 1 + clip) * advantage) vanishes when the clamped term is the minimum
+        let clipped = (advantage > T::zero() && ratio > T::one() + self.clip)
+            || (advantage < T::zero() && ratio < T::one() - self.clip);
+        let probability_scale = if clipped {
+            T::zero()
+        } else {
+            advantage / self.old_probability(state)
+        };
+        self.policy.compute_scaled_gradient(
+            input,
+            weights,
+            self.policy_state_mut(state),
+            probability_scale,
+            self.entropy_coefficient,
+            gradient,
+        );
+    }
+    fn objective(&self, state: &[T]) -> T {
+        let ratio = self.ratio(state);
+        let advantage = self.advantage(state);
+        let clipped_ratio = ratio.max(T::one() - self.clip).min(T::one() + self.clip);
+        let surrogate = (ratio * advantage).min(clipped_ratio * advantage);
+        surrogate + self.entropy_coefficient * self.policy.entropy(self.policy_state(state))
+    }
+}
+
+/// Proximal Policy Optimization with a separate value network used to compute the GAE(λ) advantages.
+/// The advantages are normalized over all the collected time steps.
+pub struct Ppo<T: Float, P: StochasticPolicy<T>, V: Value<T>, O: Optimizer<T>> {
+    surrogate: ClippedSurrogate<T, P>,
+    value: V,
+    policy_optimizer: O,
+    value_optimizer: O,
+    policy_weights: Box<[T]>,
+    value_weights: Box<[T]>,
+    policy_buffers: OptimizeBuffers<T>,
+    value_buffers: OptimizeBuffers<T>,
+    epochs: usize,
+    minibatch_size: usize,
+    gae: Gae<T>,
+    /// Inputs, states of the surrogate, rewards and dones of the collected episodes
+    rollout: RolloutBuffer<T>,
+    value_states: Box<[T]>,
+    advantages: Box<[T]>,
+    bootstrap_values: Box<[T]>,
+    bootstrap_state: Box<[T]>,
+    rng: StdRng,
+    optimize_options: OptimizeOptions<T>,
+    report: OptimizeReport,
+    policy_stats: OptimizeStats<T>,
+    value_stats: OptimizeStats<T>,
+    approx_kl: T,
+    target_kl: Option<T>,
+}
+
+impl<T: Float, P: StochasticPolicy<T>, V: Value<T>, O: Optimizer<T>> Ppo<T, P, V, O> {
+    /// The rollout storage is allocated once for `ctx_count` parallel episodes of at most `horizon` steps.
+    pub fn new(
+        surrogate: ClippedSurrogate<T, P>,
+        value: V,
+        policy_optimizer: O,
+        value_optimizer: O,
+        ctx_count: usize,
+        horizon: usize,
+    ) -> Self {
+        debug_assert!(
+            surrogate.input_len() == value.input_len(),
+            "The policy and the value must have the same input"
+        );
+        let steps = ctx_count * horizon;
+        Ppo {
+            policy_weights: surrogate.empty_weights(),
+            value_weights: value.empty_weights(),
+            policy_buffers: OptimizeBuffers::new(surrogate.weights_len(), steps),
+            value_buffers: OptimizeBuffers::new(value.weights_len(), steps),
+            epochs: 10,
+            minibatch_size: 64,
+            gae: Gae::new(T::from(0.99).unwrap(), T::from(0.95).unwrap()),
+            rollout: RolloutBuffer::new(
+                ctx_count,
+                horizon,
+                surrogate.input_len(),
+                surrogate.policy().output_len(),
+                surrogate.state_len(),
+            ),
+            value_states: vec![T::zero(); steps * value.state_len()].into_boxed_slice(),
+            advantages: vec![T::zero(); steps].into_boxed_slice(),
+            bootstrap_values: vec![T::zero(); ctx_count].into_boxed_slice(),
+            bootstrap_state: value.empty_state(),
+            rng: StdRng::from_os_rng(),
+            optimize_options: OptimizeOptions::default(),
+            report: OptimizeReport::default(),
+            policy_stats: OptimizeStats::default(),
+            value_stats: OptimizeStats::default(),
+            approx_kl: T::zero(),
+            target_kl: None,
+            surrogate,
+            value,
+            policy_optimizer,
+            value_optimizer,
+        }
+    }
+    pub fn with_epochs(mut self, epochs: usize) -> Self {
+        self.epochs = epochs;
+        self
+    }
+    pub fn with_minibatch_size(mut self, minibatch_size: usize) -> Self {
+        self.minibatch_size = minibatch_size;
+        self
+    }
+    pub fn with_discount(mut self, gamma: T, lambda: T) -> Self {
+        self.gae = Gae::new(gamma, lambda);
+        self
+    }
+    /// Seed the sampling of the actions and the shuffling of the minibatches, for reproducible runs
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.rng = StdRng::seed_from_u64(seed);
+        self
+    }
+    /// Gradient clipping of every update
+    pub fn with_optimize_options(mut self, options: OptimizeOptions<T>) -> Self {
+        self.optimize_options = options;
+        self
+    }
+    /// Stop the policy epochs of an update once the approximate KL divergence between the policy
+    /// that collected the rollouts and the optimized one exceeds `1.5 * target_kl`
+    pub fn with_target_kl(mut self, target_kl: T) -> Self {
+        self.target_kl = Some(target_kl);
+        self
+    }
+    /// Steps, skipped minibatches and clipped gradients cumulated over all the updates
+    pub fn report(&self) -> OptimizeReport {
+        self.report
+    }
+    /// Surrogate objective and gradient norm of the last update, `stopped_early` tells whether
+    /// `with_target_kl` ended it.
+    pub fn last_policy_stats(&self) -> OptimizeStats<T> {
+        self.policy_stats
+    }
+    pub fn last_value_stats(&self) -> OptimizeStats<T> {
+        self.value_stats
+    }
+    /// Approximate KL divergence after the last policy epoch of the last update, which is the one
+    /// that triggered the early stop if any.
+    pub fn last_approx_kl(&self) -> T {
+        self.approx_kl
+    }
+    pub fn policy(&self) -> &P {
+        self.surrogate.policy()
+    }
+    /// For instance to anneal the exploration with `NormalPolicy::set_sigma`
+    pub fn policy_mut(&mut self) -> &mut P {
+        self.surrogate.policy_mut()
+    }
+    pub fn value(&self) -> &V {
+        &self.value
+    }
+    pub fn policy_weights(&self) -> &[T] {
+        &self.policy_weights
+    }
+    pub fn policy_weights_mut(&mut self) -> &mut [T] {
+        &mut self.policy_weights
+    }
+    pub fn policy_optimizer(&self) -> &O {
+        &self.policy_optimizer
+    }
+    pub fn policy_optimizer_mut(&mut self) -> &mut O {
+        &mut self.policy_optimizer
+    }
+    pub fn value_optimizer(&self) -> &O {
+        &self.value_optimizer
+    }
+    pub fn value_optimizer_mut(&mut self) -> &mut O {
+        &mut self.value_optimizer
+    }
+    pub fn value_weights(&self) -> &[T] {
+        &self.value_weights
+    }
+    pub fn value_weights_mut(&mut self) -> &mut [T] {
+        &mut self.value_weights
+    }
+    /// Number of time steps recorded by the last call to `collect`
+    pub fn collected_steps(&self) -> usize {
+        self.rollout.recorded_steps()
+    }
+    /// Mean reward per time step recorded by the last call to `collect`
+    pub fn mean_reward(&self) -> T {
+        self.rollout.mean_reward()
+    }
+    pub fn rollout(&self) -> &RolloutBuffer<T> {
+        &self.rollout
+    }
+
+    /// Run one episode of at most `horizon` steps per ctx with the stochastic policy and compute the normalized advantages.
+    /// `physics_reward` must return the reward of the current step only and whether the episode is done.
+    pub fn collect<C>(
+        &mut self,
+        ctx_list: &mut [C],
+        ctx_to_input: impl Fn(&C, &mut [T]),
+        physics_reward: impl Fn(&mut C, &[T]) -> (T, bool),
+    ) {
+        debug_assert!(ctx_list.len() == self.rollout.env_count(), "ctx count");
+        let (surrogate, policy_weights, rng) =
+            (&self.surrogate, &self.policy_weights, &mut self.rng);
+        let policy = surrogate.policy();
+        for (e, ctx) in ctx_list.iter_mut().enumerate() {
+            let mut env = CtxEnv {
+                ctx,
+                ctx_to_input: &ctx_to_input,
+                physics_reward: &physics_reward,
+                observation_len: surrogate.input_len(),
+                action_len: policy.output_len(),
+            };
+            self.rollout
+                .collect_episode(e, &mut env, |input, state, action| {
+                    let policy_state = surrogate.policy_state_mut(state);
+                    policy.stochastic_eval(input, policy_weights, policy_state, rng);
+                    let probability = policy.probability(policy_state);
+                    action.copy_from_slice(policy.stochastic_output(policy_state));
+                    surrogate.set_old_probability(probability, state);
+                });
+            let steps = self.rollout.env_steps(e);
+            self.bootstrap_values[e] = if self.rollout.dones()[steps.end - 1] {
+                T::zero()
+            } else {
+                self.value.eval(
+                    self.rollout.last_observation(e),
+                    &self.value_weights,
+                    &mut self.bootstrap_state,
+                );
+                self.value.value(&self.bootstrap_state)
+            };
+        }
+        let (input_len, value_state_len) = (self.value.input_len(), self.value.state_len());
+        for e in 0..self.rollout.env_count() {
+            for i in self.rollout.env_steps(e) {
+                self.value.eval(
+                    &self.rollout.inputs()[i * input_len..][..input_len],
+                    &self.value_weights,
+                    &mut self.value_states[i * value_state_len..][..value_state_len],
+                );
+            }
+        }
+        self.compute_advantages();
+    }
+    fn compute_advantages(&mut self) {
+        let value_state_len = self.value.state_len();
+        let mut offset = 0;
+        for e in 0..self.rollout.env_count() {
+            let steps = self.rollout.env_steps(e);
+            let len = steps.len();
+            self.gae.estimate(
+                &mut self.value,
+                &mut self.value_states[steps.start * value_state_len..steps.end * value_state_len],
+                &self.rollout.rewards()[steps.clone()],
+                &self.rollout.dones()[steps.clone()],
+                self.bootstrap_values[e],
+                &mut self.advantages[offset..offset + len],
+            );
+            offset += len;
+        }
+        let advantages = &mut self.advantages[..offset];
+        advantage::normalize(advantages);
+        let mut advantages = advantages.iter();
+        let state_len = self.surrogate.state_len();
+        for e in 0..self.rollout.env_count() {
+            let steps = self.rollout.env_steps(e);
+            self.rollout.states_mut()[steps.start * state_len..steps.end * state_len]
+                .chunks_mut(state_len)
+                .zip(&mut advantages)
+                .for_each(|(state, &a)| self.surrogate.set_advantage(a, state));
+        }
+    }
+
+    /// Optimize the policy and the value on the rollouts recorded by the last call to `collect`.
+    /// NOTE: the time steps that do not fill a complete minibatch are dropped unless
+    /// `OptimizeOptions::with_remainder` is set.
+    pub fn update(&mut self) {
+        let options = self
+            .optimize_options
+            .with_epochs(self.epochs)
+            .with_minibatch_size(self.minibatch_size);
+        let max_kl = self.target_kl.map(|kl| kl * T::from(1.5).unwrap());
+        let mut approx_kl = T::zero();
+        let early_stop = |surrogate: &ClippedSurrogate<T, P>,
+                          _: &EpochStats<T>,
+                          time_steps: &TimeStepChunks<T>| {
+            approx_kl = surrogate.approx_kl(time_steps);
+            max_kl.is_some_and(|max_kl| approx_kl > max_kl)
+        };
+        let mut time_steps = self.rollout.time_steps();
+        self.policy_stats = self.policy_optimizer.optimize(
+            &mut self.surrogate,
+            &mut self.policy_weights,
+            &mut time_steps,
+            &mut self.policy_buffers,
+            options
+                .with_direction(Direction::Ascent)
+                .with_early_stop(early_stop),
+            &mut self.rng,
+        );
+        self.approx_kl = approx_kl;
+
+        let mut time_steps = self
+            .rollout
+            .time_steps_with(&mut self.value_states, self.value.state_len());
+        self.value_stats = self.value_optimizer.optimize(
+            &mut self.value,
+            &mut self.value_weights,
+            &mut time_steps,
+            &mut self.value_buffers,
+            options.with_direction(Direction::Descent),
+            &mut self.rng,
+        );
+        self.report += self.policy_stats.total;
+        self.report += self.value_stats.total;
+    }
+}
+
+/// `Env` view of a ctx of `Ppo::collect`, whose episode lasts until `physics_reward` says it is done.
+struct CtxEnv<'a, C, I, R> {
+    ctx: &'a mut C,
+    ctx_to_input: &'a I,
+    physics_reward: &'a R,
+    observation_len: usize,
+    action_len: usize,
+}
+impl<T: Float, C, I: Fn(&C, &mut [T]), R: Fn(&mut C, &[T]) -> (T, bool)> Env<T>
+    for CtxEnv<'_, C, I, R>
+{
+    fn observation_len(&self) -> usize {
+        self.observation_len
+    }
+    fn action_len(&self) -> usize {
+        self.action_len
+    }
+    fn reset(&mut self, observation: &mut [T]) {
+        (self.ctx_to_input)(self.ctx, observation);
+    }
+    fn step(&mut self, action: &[T], observation: &mut [T]) -> Step<T> {
+        let (reward, done) = (self.physics_reward)(self.ctx, action);
+        (self.ctx_to_input)(self.ctx, observation);
+        Step {
+            reward,
+            terminated: done,
+            truncated: false,
+        }
+    }
+}
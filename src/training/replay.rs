@@ -0,0 +1,312 @@
+use num::Float;
+use rand::Rng;
+
+use super::TimeStepChunks;
+
+/// Binary tree where each node is the sum of its children, used to sample leaves proportionally to their values.
+pub struct SumTree<T: Float> {
+    leaves: usize,
+    nodes: Box<[T]>,
+}
+
+impl<T: Float> SumTree<T> {
+    pub fn new(capacity: usize) -> Self {
+        let leaves = capacity.next_power_of_two();
+        SumTree {
+            leaves,
+            nodes: vec![T::zero(); 2 * leaves].into_boxed_slice(),
+        }
+    }
+    pub fn total(&self) -> T {
+        self.nodes[1]
+    }
+    pub fn get(&self, index: usize) -> T {
+        self.nodes[self.leaves + index]
+    }
+    pub fn set(&mut self, index: usize, value: T) {
+        debug_assert!(value >= T::zero(), "SumTree values must be non negative");
+        let mut node = self.leaves + index;
+        self.nodes[node] = value;
+        while node > 1 {
+            node /= 2;
+            self.nodes[node] = self.nodes[2 * node] + self.nodes[2 * node + 1];
+        }
+    }
+    /// Index of the leaf where the cumulative sum of the values reaches `mass`, which must be in `[0, total)`.
+    pub fn find(&self, mut mass: T) -> usize {
+        let mut node = 1;
+        while node < self.leaves {
+            let left = self.nodes[2 * node];
+            // NOTE: never go down in an empty subtree because of rounding errors
+            if mass < left || self.nodes[2 * node + 1] <= T::zero() {
+                node *= 2;
+            } else {
+                mass = mass - left;
+                node = 2 * node + 1;
+            }
+        }
+        node - self.leaves
+    }
+}
+
+/// One transition stored in a `ReplayBuffer`.
+pub struct Transition<'a, T: Float> {
+    pub observation: &'a [T],
+    pub action: &'a [T],
+    pub reward: T,
+    pub next_observation: &'a [T],
+    pub done: bool,
+}
+
+struct Prioritization<T: Float> {
+    tree: SumTree<T>,
+    alpha: T,
+    beta: T,
+    epsilon: T,
+    max_priority: T,
+}
+
+/// Fixed capacity ring buffer of transitions, which overwrites the oldest ones once full.
+/// Sampling is uniform unless `with_prioritization` is used.
+pub struct ReplayBuffer<T: Float> {
+    capacity: usize,
+    observation_len: usize,
+    action_len: usize,
+    observations: Box<[T]>,
+    actions: Box<[T]>,
+    rewards: Box<[T]>,
+    next_observations: Box<[T]>,
+    dones: Box<[bool]>,
+    len: usize,
+    next: usize,
+    prioritization: Option<Prioritization<T>>,
+}
+
+impl<T: Float> ReplayBuffer<T> {
+    pub fn new(capacity: usize, observation_len: usize, action_len: usize) -> Self {
+        debug_assert!(capacity > 0, "capacity must be positive");
+        ReplayBuffer {
+            capacity,
+            observation_len,
+            action_len,
+            observations: vec![T::zero(); capacity * observation_len].into_boxed_slice(),
+            actions: vec![T::zero(); capacity * action_len].into_boxed_slice(),
+            rewards: vec![T::zero(); capacity].into_boxed_slice(),
+            next_observations: vec![T::zero(); capacity * observation_len].into_boxed_slice(),
+            dones: vec![false; capacity].into_boxed_slice(),
+            len: 0,
+            next: 0,
+            prioritization: None,
+        }
+    }
+    /// Proportional prioritized sampling: the transition `i` is sampled with probability
+    /// $P(i) = p_i^\alpha / \sum_k p_k^\alpha$ where $p_i = |\delta_i| + \epsilon$ and $\delta_i$ is its TD error.
+    /// The importance sampling weights are $(N P(i))^{-\beta}$ divided by their maximum in the batch.
+    pub fn with_prioritization(mut self, alpha: T, beta: T) -> Self {
+        self.prioritization = Some(Prioritization {
+            tree: SumTree::new(self.capacity),
+            alpha,
+            beta,
+            epsilon: T::from(1e-6).unwrap(),
+            max_priority: T::one(),
+        });
+        self
+    }
+    /// Exponent of the importance sampling weights, usually annealed to 1 during training
+    pub fn set_beta(&mut self, beta: T) {
+        if let Some(p) = &mut self.prioritization {
+            p.beta = beta;
+        }
+    }
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+    pub fn len(&self) -> usize {
+        self.len
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+    pub fn observation_len(&self) -> usize {
+        self.observation_len
+    }
+    pub fn action_len(&self) -> usize {
+        self.action_len
+    }
+    pub fn clear(&mut self) {
+        self.len = 0;
+        self.next = 0;
+        if let Some(p) = &mut self.prioritization {
+            (0..self.capacity).for_each(|i| p.tree.set(i, T::zero()));
+            p.max_priority = T::one();
+        }
+    }
+
+    /// Store a transition with the largest priority seen so far, so that it is sampled at least once.
+    pub fn push(
+        &mut self,
+        observation: &[T],
+        action: &[T],
+        reward: T,
+        next_observation: &[T],
+        done: bool,
+    ) {
+        debug_assert!(
+            observation.len() == self.observation_len
+                && next_observation.len() == self.observation_len
+                && action.len() == self.action_len,
+            "transition dimensions"
+        );
+        let (i, o, a) = (self.next, self.observation_len, self.action_len);
+        self.observations[i * o..][..o].copy_from_slice(observation);
+        self.actions[i * a..][..a].copy_from_slice(action);
+        self.rewards[i] = reward;
+        self.next_observations[i * o..][..o].copy_from_slice(next_observation);
+        self.dones[i] = done;
+        if let Some(p) = &mut self.prioritization {
+            p.tree.set(i, p.max_priority.powf(p.alpha));
+        }
+        self.next = (self.next + 1) % self.capacity;
+        self.len = (self.len + 1).min(self.capacity);
+    }
+    pub fn get(&self, index: usize) -> Transition<'_, T> {
+        debug_assert!(index < self.len, "transition index");
+        let (o, a) = (self.observation_len, self.action_len);
+        Transition {
+            observation: &self.observations[index * o..][..o],
+            action: &self.actions[index * a..][..a],
+            reward: self.rewards[index],
+            next_observation: &self.next_observations[index * o..][..o],
+            done: self.dones[index],
+        }
+    }
+
+    /// Sample `batch.len()` transitions, then `fill(transition, weight, input, state)` must write the
+    /// input and the state of the corresponding time step, where `weight` is the importance sampling
+    /// weight (always one for uniform sampling).
+    pub fn sample(
+        &self,
+        batch: &mut Batch<T>,
+        rng: &mut impl Rng,
+        fill: impl FnMut(&Transition<T>, T, &mut [T], &mut [T]),
+    ) {
+        self.sample_indices(batch, rng);
+        self.fill(batch, fill);
+    }
+    /// First half of `sample`, which only draws the indices and the weights of the batch.
+    pub fn sample_indices(&self, batch: &mut Batch<T>, rng: &mut impl Rng) {
+        debug_assert!(!self.is_empty(), "cannot sample an empty replay buffer");
+        let batch_size = batch.len();
+        match &self.prioritization {
+            None => {
+                batch.indices.iter_mut().for_each(|i| {
+                    *i = rng.random_range(0..self.len);
+                });
+                batch.weights.iter_mut().for_each(|w| *w = T::one());
+            }
+            Some(p) => {
+                // NOTE: stratified sampling, one transition in each of batch_size equal segments of the total priority
+                let total = p.tree.total();
+                let segment = total / T::from(batch_size).unwrap();
+                let n = T::from(self.len).unwrap();
+                for (k, (i, w)) in batch
+                    .indices
+                    .iter_mut()
+                    .zip(batch.weights.iter_mut())
+                    .enumerate()
+                {
+                    let u = T::from(rng.random::<f64>()).unwrap();
+                    let mass = (T::from(k).unwrap() + u) * segment;
+                    *i = p.tree.find(mass.min(total * (T::one() - T::epsilon())));
+                    let probability = p.tree.get(*i) / total;
+                    *w = (n * probability).powf(-p.beta);
+                }
+                let max = batch.weights.iter().fold(T::zero(), |acc, &w| acc.max(w));
+                batch.weights.iter_mut().for_each(|w| *w = *w / max);
+            }
+        }
+    }
+    /// Second half of `sample`, which calls `fill` on the transitions of the sampled indices.
+    pub fn fill(
+        &self,
+        batch: &mut Batch<T>,
+        mut fill: impl FnMut(&Transition<T>, T, &mut [T], &mut [T]),
+    ) {
+        let (input_len, state_len) = (batch.input_len, batch.state_len);
+        for (k, &i) in batch.indices.iter().enumerate() {
+            fill(
+                &self.get(i),
+                batch.weights[k],
+                &mut batch.inputs[k * input_len..][..input_len],
+                &mut batch.states[k * state_len..][..state_len],
+            );
+        }
+    }
+
+    /// Set the priorities of the sampled transitions from their new TD errors.
+    pub fn update_priorities(&mut self, indices: &[usize], td_errors: &[T]) {
+        debug_assert!(indices.len() == td_errors.len());
+        if let Some(p) = &mut self.prioritization {
+            for (&i, &delta) in indices.iter().zip(td_errors.iter()) {
+                let priority = delta.abs() + p.epsilon;
+                p.max_priority = p.max_priority.max(priority);
+                p.tree.set(i, priority.powf(p.alpha));
+            }
+        }
+    }
+}
+
+/// Preallocated storage of the time steps of a sampled batch.
+pub struct Batch<T: Float> {
+    input_len: usize,
+    state_len: usize,
+    inputs: Box<[T]>,
+    states: Box<[T]>,
+    indices: Box<[usize]>,
+    weights: Box<[T]>,
+}
+
+impl<T: Float> Batch<T> {
+    pub fn new(batch_size: usize, input_len: usize, state_len: usize) -> Self {
+        Batch {
+            input_len,
+            state_len,
+            inputs: vec![T::zero(); batch_size * input_len].into_boxed_slice(),
+            states: vec![T::zero(); batch_size * state_len].into_boxed_slice(),
+            indices: vec![0; batch_size].into_boxed_slice(),
+            weights: vec![T::one(); batch_size].into_boxed_slice(),
+        }
+    }
+    pub fn len(&self) -> usize {
+        self.indices.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.indices.is_empty()
+    }
+    /// Indices of the sampled transitions in the replay buffer
+    pub fn indices(&self) -> &[usize] {
+        &self.indices
+    }
+    /// Importance sampling weights of the sampled transitions
+    pub fn weights(&self) -> &[T] {
+        &self.weights
+    }
+    pub fn inputs(&self) -> &[T] {
+        &self.inputs
+    }
+    pub fn states(&self) -> &[T] {
+        &self.states
+    }
+    pub fn states_mut(&mut self) -> &mut [T] {
+        &mut self.states
+    }
+    /// Time steps ready to be given to `Optimizer::optimize`, without allocation.
+    pub fn time_steps(&mut self) -> TimeStepChunks<'_, T> {
+        TimeStepChunks::new(
+            &self.inputs,
+            self.input_len,
+            &mut self.states,
+            self.state_len,
+        )
+    }
+}
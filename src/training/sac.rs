@@ -0,0 +1,507 @@
+use num::{Float, traits::FloatConst};
+use rand::{Rng, SeedableRng, rngs::StdRng};
+use rand_distr::{Distribution, StandardNormal};
+
+use super::{
+    BackProp, Direction, Eval, Gradient, OptimizeBuffers, OptimizeOptions, OptimizeReport,
+    OptimizeStats, Optimizer, StochasticPolicy, TimeSteps, Weights,
+    least_squar_value::LeastSquareValue,
+    policies::squashed_normal_policy::SquashedNormalPolicy,
+    replay::{Batch, ReplayBuffer},
+};
+
+/// $\min_j Q_j(s, a) - \alpha \ln \pi(a|s)$ as a function of the policy weights, where the action
+/// $a = c + h \tanh(m + \sigma \xi)$ is reparameterized by the noise $\xi$ stored in the state,
+/// see `sample_noise`. The critic weights are copies set with `set_critic_weights`.
+/// State: policy state | noise | critic input | critic states | critic back | critic front | dQ/du
+pub struct SoftActorObjective<T: Float + FloatConst>
+where
+    StandardNormal: Distribution<T>,
+{
+    policy: SquashedNormalPolicy<T>,
+    critics: [LeastSquareValue<T>; 2],
+    critic_weights: [Box<[T]>; 2],
+    alpha: T,
+}
+
+impl<T: Float + FloatConst> SoftActorObjective<T>
+where
+    StandardNormal: Distribution<T>,
+{
+    /// Both critics must have the same architecture, with the observation followed by the action as input.
+    pub fn new(policy: SquashedNormalPolicy<T>, critics: [LeastSquareValue<T>; 2]) -> Self {
+        debug_assert!(
+            critics.iter().all(
+                |c| c.input_len() == policy.input_len() + policy.output_len()
+                    && c.weights_len() == critics[0].weights_len()
+                    && c.state_len() == critics[0].state_len()
+            ),
+            "The critics must have the same architecture and take the observation followed by the action"
+        );
+        SoftActorObjective {
+            critic_weights: [critics[0].empty_weights(), critics[1].empty_weights()],
+            policy,
+            critics,
+            alpha: T::one(),
+        }
+    }
+    pub fn policy(&self) -> &SquashedNormalPolicy<T> {
+        &self.policy
+    }
+    pub fn critics(&self) -> &[LeastSquareValue<T>; 2] {
+        &self.critics
+    }
+    pub fn set_critic_weights(&mut self, critic: usize, weights: &[T]) {
+        self.critic_weights[critic].copy_from_slice(weights);
+    }
+    pub fn alpha(&self) -> T {
+        self.alpha
+    }
+    pub fn set_alpha(&mut self, alpha: T) {
+        self.alpha = alpha;
+    }
+    /// Draw the standard normal noise that reparameterizes the action of this time step
+    pub fn sample_noise(&self, state: &mut [T], rng: &mut impl Rng) {
+        state[self.policy.state_len()..][..self.output_len()]
+            .iter_mut()
+            .for_each(|xi| *xi = StandardNormal.sample(rng));
+    }
+    pub fn log_probability(&self, state: &[T]) -> T {
+        self.policy
+            .log_probability(&state[..self.policy.state_len()])
+    }
+    /// Smallest Q-value of the critics and the index of the corresponding critic
+    pub fn q_value(&self, state: &[T]) -> (T, usize) {
+        let critic_state_len = self.critic_state_len();
+        let offset = self.policy.state_len() + self.output_len() + self.critics[0].input_len();
+        (0..2)
+            .map(|j| {
+                let critic_state = &state[offset + j * critic_state_len..][..critic_state_len];
+                (self.critics[j].mlp().output(critic_state)[0], j)
+            })
+            .fold(
+                (T::infinity(), 0),
+                |acc, q| if q.0 < acc.0 { q } else { acc },
+            )
+    }
+    fn critic_state_len(&self) -> usize {
+        self.critics[0].mlp().state_len()
+    }
+}
+
+impl<T: Float + FloatConst> Weights<T> for SoftActorObjective<T>
+where
+    StandardNormal: Distribution<T>,
+{
+    fn weights_len(&self) -> usize {
+        self.policy.weights_len()
+    }
+}
+impl<T: Float + FloatConst> Eval<T> for SoftActorObjective<T>
+where
+    StandardNormal: Distribution<T>,
+{
+    fn state_len(&self) -> usize {
+        let critic = self.critics[0].mlp();
+        self.policy.state_len()
+            + 2 * self.output_len()
+            + critic.input_len()
+            + 2 * critic.state_len()
+            + 2 * critic.min_back_front_len()
+    }
+    fn eval(&self, input: &[T], weights: &[T], state: &mut [T]) {
+        let normal = self.policy.policy();
+        let (policy_state, tmp) = state.split_at_mut(self.policy.state_len());
+        let (noise, tmp) = tmp.split_at_mut(self.output_len());
+        let (critic_input, tmp) = tmp.split_at_mut(self.critics[0].input_len());
+        let (critic_state_1, tmp) = tmp.split_at_mut(self.critic_state_len());
+        let critic_state_2 = &mut tmp[..self.critic_state_len()];
+        self.policy.eval(input, weights, policy_state);
+        for (i, &xi) in noise.iter().enumerate() {
+            let normal_state = &mut policy_state[..normal.state_len()];
+            let u = normal.output(normal_state)[i] + normal.sigmas(normal_state)[i] * xi;
+            normal.action_mut(normal_state)[i] = u;
+            self.policy.action_mut(policy_state)[i] = self.policy.squash(u);
+        }
+        let (observation, action) = critic_input.split_at_mut(input.len());
+        observation.copy_from_slice(input);
+        action.copy_from_slice(self.policy.action(policy_state));
+        for (j, critic_state) in [critic_state_1, critic_state_2].into_iter().enumerate() {
+            self.critics[j]
+                .mlp()
+                .eval(critic_input, &self.critic_weights[j], critic_state);
+        }
+    }
+    fn input_len(&self) -> usize {
+        self.policy.input_len()
+    }
+    fn output_len(&self) -> usize {
+        self.policy.output_len()
+    }
+    /// The output is the reparameterized action
+    fn output<'a>(&self, state: &'a [T]) -> &'a [T] {
+        self.policy.action(&state[..self.policy.state_len()])
+    }
+    fn output_mut<'a>(&self, state: &'a mut [T]) -> &'a mut [T] {
+        self.policy
+            .action_mut(&mut state[..self.policy.state_len()])
+    }
+}
+impl<T: Float + FloatConst> Gradient<T> for SoftActorObjective<T>
+where
+    StandardNormal: Distribution<T>,
+{
+    fn compute_gradient(&self, input: &[T], weights: &[T], state: &mut [T], gradient: &mut [T]) {
+        let n = self.output_len();
+        let (_, j) = self.q_value(state);
+        let critic = self.critics[j].mlp();
+        let normal = self.policy.policy();
+        let (policy_state, tmp) = state.split_at_mut(self.policy.state_len());
+        let (_noise, tmp) = tmp.split_at_mut(n);
+        let (critic_input, tmp) = tmp.split_at_mut(critic.input_len());
+        let (critic_states, tmp) = tmp.split_at_mut(2 * critic.state_len());
+        let (critic_back, tmp) = tmp.split_at_mut(critic.min_back_front_len());
+        let (critic_front, tmp) = tmp.split_at_mut(critic.min_back_front_len());
+        let d_q_u = &mut tmp[..n];
+        critic_back[0] = T::one();
+        let d_critic_input = critic.input_gradient(
+            critic_input,
+            &self.critic_weights[j],
+            &critic_states[j * critic.state_len()..][..critic.state_len()],
+            critic_front,
+            critic_back,
+        );
+        let normal_state = &mut policy_state[..normal.state_len()];
+        d_q_u
+            .iter_mut()
+            .zip(d_critic_input[input.len()..].iter())
+            .zip(normal.action(normal_state).iter())
+            .for_each(|((d, &d_q_a), &u)| *d = d_q_a * self.policy.squash_derivative(u));
+        let two = T::one() + T::one();
+        let alpha = self.alpha;
+        // NOTE: with u = m + sigma xi, du/dm = 1 and du/dlog(sigma) = u - m, while the noise xi
+        // is fixed so that d ln(pi)/du = 2 tanh(u), from the Jacobian, and d ln(pi)/dlog(sigma) = -1 at fixed u
+        normal.back_prop_mean_log_std(input, weights, normal_state, gradient, |i, m, _, u| {
+            let d_u = d_q_u[i] - alpha * two * u.tanh();
+            (d_u, d_u * (u - m) + alpha)
+        });
+    }
+    fn objective(&self, state: &[T]) -> T {
+        self.q_value(state).0 - self.alpha * self.log_probability(state)
+    }
+}
+
+/// Soft Actor-Critic with twin critics, Polyak averaged target critics and an entropy temperature
+/// $\alpha$ tuned toward a target entropy. Each parameter group has its own optimizer.
+pub struct Sac<T: Float + FloatConst, O: Optimizer<T>>
+where
+    StandardNormal: Distribution<T>,
+{
+    objective: SoftActorObjective<T>,
+    policy_optimizer: O,
+    critic_optimizers: [O; 2],
+    alpha_optimizer: O,
+    policy_weights: Box<[T]>,
+    critic_weights: [Box<[T]>; 2],
+    critic_target_weights: [Box<[T]>; 2],
+    log_alpha: Box<[T]>,
+    policy_buffers: OptimizeBuffers<T>,
+    critic_buffers: OptimizeBuffers<T>,
+    alpha_gradient: Box<[T]>,
+    replay: ReplayBuffer<T>,
+    critic_batch: Batch<T>,
+    policy_batch: Batch<T>,
+    policy_state: Box<[T]>,
+    critic_input: Box<[T]>,
+    critic_state: Box<[T]>,
+    gamma: T,
+    tau: T,
+    target_entropy: T,
+    tune_alpha: bool,
+    rng: StdRng,
+    optimize_options: OptimizeOptions<T>,
+    report: OptimizeReport,
+    policy_stats: OptimizeStats<T>,
+    critic_stats: [OptimizeStats<T>; 2],
+}
+
+impl<T: Float + FloatConst, O: Optimizer<T>> Sac<T, O>
+where
+    StandardNormal: Distribution<T>,
+{
+    /// `optimizer(weights_len)` creates the optimizer of each parameter group: the policy, each
+    /// critic and the log temperature.
+    pub fn new(
+        objective: SoftActorObjective<T>,
+        optimizer: impl Fn(usize) -> O,
+        replay: ReplayBuffer<T>,
+        batch_size: usize,
+    ) -> Self {
+        let critic = &objective.critics()[0];
+        let policy = objective.policy();
+        debug_assert!(
+            replay.observation_len() == objective.input_len()
+                && replay.action_len() == objective.output_len(),
+            "replay dimensions"
+        );
+        Sac {
+            policy_optimizer: optimizer(objective.weights_len()),
+            critic_optimizers: [
+                optimizer(critic.weights_len()),
+                optimizer(critic.weights_len()),
+            ],
+            alpha_optimizer: optimizer(1),
+            policy_weights: objective.empty_weights(),
+            critic_weights: [critic.empty_weights(), critic.empty_weights()],
+            critic_target_weights: [critic.empty_weights(), critic.empty_weights()],
+            log_alpha: vec![T::zero(); 1].into_boxed_slice(),
+            policy_buffers: OptimizeBuffers::new(objective.weights_len(), batch_size),
+            critic_buffers: OptimizeBuffers::new(critic.weights_len(), batch_size),
+            alpha_gradient: vec![T::zero(); 1].into_boxed_slice(),
+            critic_batch: Batch::new(batch_size, critic.input_len(), critic.state_len()),
+            policy_batch: Batch::new(batch_size, objective.input_len(), objective.state_len()),
+            policy_state: policy.empty_state(),
+            critic_input: vec![T::zero(); critic.input_len()].into_boxed_slice(),
+            critic_state: critic.mlp().empty_state(),
+            gamma: T::from(0.99).unwrap(),
+            tau: T::from(0.005).unwrap(),
+            target_entropy: -T::from(objective.output_len()).unwrap(),
+            tune_alpha: true,
+            rng: StdRng::from_os_rng(),
+            optimize_options: OptimizeOptions::default(),
+            report: OptimizeReport::default(),
+            policy_stats: OptimizeStats::default(),
+            critic_stats: [OptimizeStats::default(); 2],
+            objective,
+            replay,
+        }
+    }
+    pub fn with_gamma(mut self, gamma: T) -> Self {
+        self.gamma = gamma;
+        self
+    }
+    /// Polyak averaging coefficient of the target critics
+    pub fn with_tau(mut self, tau: T) -> Self {
+        self.tau = tau;
+        self
+    }
+    /// Initial temperature, 1 by default
+    pub fn with_alpha(mut self, alpha: T) -> Self {
+        self.log_alpha[0] = alpha.ln();
+        self
+    }
+    /// Keep the temperature fixed instead of tuning it
+    pub fn with_fixed_alpha(mut self, alpha: T) -> Self {
+        self.tune_alpha = false;
+        self.with_alpha(alpha)
+    }
+    /// Entropy toward which the temperature is tuned, minus the action dimension by default
+    pub fn with_target_entropy(mut self, target_entropy: T) -> Self {
+        self.target_entropy = target_entropy;
+        self
+    }
+    /// Seed the sampling of the actions, of the batches and of the noise, for reproducible runs
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.rng = StdRng::seed_from_u64(seed);
+        self
+    }
+    /// Gradient clipping of every update
+    pub fn with_optimize_options(mut self, options: OptimizeOptions<T>) -> Self {
+        self.optimize_options = options;
+        self
+    }
+    /// Steps, skipped minibatches and clipped gradients cumulated over all the updates, the
+    /// temperature updates included
+    pub fn report(&self) -> OptimizeReport {
+        self.report
+    }
+    /// Soft Q-value and gradient norm of the last policy update
+    pub fn last_policy_stats(&self) -> OptimizeStats<T> {
+        self.policy_stats
+    }
+    /// TD loss and gradient norm of the last update of the critic `0` or `1`
+    pub fn last_critic_stats(&self, critic: usize) -> OptimizeStats<T> {
+        self.critic_stats[critic]
+    }
+    pub fn policy(&self) -> &SquashedNormalPolicy<T> {
+        self.objective.policy()
+    }
+    pub fn alpha(&self) -> T {
+        self.log_alpha[0].exp()
+    }
+    pub fn policy_weights(&self) -> &[T] {
+        &self.policy_weights
+    }
+    pub fn policy_weights_mut(&mut self) -> &mut [T] {
+        &mut self.policy_weights
+    }
+    /// Call `sync_targets` after modifying the weights directly, for instance at initialization.
+    pub fn critic_weights_mut(&mut self, critic: usize) -> &mut [T] {
+        &mut self.critic_weights[critic]
+    }
+    pub fn sync_targets(&mut self) {
+        for (target, weights) in self
+            .critic_target_weights
+            .iter_mut()
+            .zip(self.critic_weights.iter())
+        {
+            target.copy_from_slice(weights);
+        }
+    }
+
+    /// Squashed mean action of the policy
+    pub fn deterministic_action(&mut self, observation: &[T], action: &mut [T]) {
+        let policy = self.objective.policy();
+        policy.eval(observation, &self.policy_weights, &mut self.policy_state);
+        action.copy_from_slice(policy.output(&self.policy_state));
+    }
+    /// Action sampled from the policy
+    pub fn act(&mut self, observation: &[T], action: &mut [T]) {
+        let policy = self.objective.policy();
+        policy.stochastic_eval(
+            observation,
+            &self.policy_weights,
+            &mut self.policy_state,
+            &mut self.rng,
+        );
+        action.copy_from_slice(policy.stochastic_output(&self.policy_state));
+    }
+    pub fn store(
+        &mut self,
+        observation: &[T],
+        action: &[T],
+        reward: T,
+        next_observation: &[T],
+        done: bool,
+    ) {
+        self.replay
+            .push(observation, action, reward, next_observation, done);
+    }
+
+    /// Update the critics, the policy and the temperature on batches sampled from the replay
+    /// buffer, then the target critics. Nothing is done until the replay buffer holds a batch.
+    pub fn update(&mut self) {
+        if self.replay.len() < self.critic_batch.len() {
+            return;
+        }
+        self.update_critics();
+        self.update_policy();
+        let tau = self.tau;
+        for (target, weights) in self
+            .critic_target_weights
+            .iter_mut()
+            .zip(self.critic_weights.iter())
+        {
+            target
+                .iter_mut()
+                .zip(weights.iter())
+                .for_each(|(t, &w)| *t = tau * w + (T::one() - tau) * *t);
+        }
+    }
+    fn update_critics(&mut self) {
+        let alpha = self.alpha();
+        let Sac {
+            objective,
+            policy_weights,
+            critic_target_weights,
+            replay,
+            critic_batch,
+            policy_state,
+            critic_input,
+            critic_state,
+            gamma,
+            rng,
+            ..
+        } = self;
+        let (policy, critic) = (objective.policy(), objective.critics()[0].mlp());
+        let observation_len = policy.input_len();
+        replay.sample_indices(critic_batch, rng);
+        replay.fill(critic_batch, |transition, _weight, input, state| {
+            let next_value = if transition.done {
+                T::zero()
+            } else {
+                policy.stochastic_eval(
+                    transition.next_observation,
+                    policy_weights,
+                    policy_state,
+                    rng,
+                );
+                let (observation, action) = critic_input.split_at_mut(observation_len);
+                observation.copy_from_slice(transition.next_observation);
+                action.copy_from_slice(policy.stochastic_output(policy_state));
+                let q = critic_target_weights
+                    .iter()
+                    .fold(T::infinity(), |acc, weights| {
+                        critic.eval(critic_input, weights, critic_state);
+                        acc.min(critic.output(critic_state)[0])
+                    });
+                q - alpha * policy.log_probability(policy_state)
+            };
+            let (observation, action) = input.split_at_mut(observation_len);
+            observation.copy_from_slice(transition.observation);
+            action.copy_from_slice(transition.action);
+            // NOTE: both critics have the same state layout, so the target is shared
+            *objective.critics()[0].target_mut(state) = transition.reward + *gamma * next_value;
+        });
+        let options = self
+            .optimize_options
+            .with_minibatch_size(self.critic_batch.len())
+            .with_direction(Direction::Descent);
+        let mut time_steps = self.critic_batch.time_steps();
+        for (j, optimizer) in self.critic_optimizers.iter_mut().enumerate() {
+            self.critic_stats[j] = optimizer.optimize(
+                &mut self.objective.critics[j],
+                &mut self.critic_weights[j],
+                &mut time_steps,
+                &mut self.critic_buffers,
+                options,
+                &mut self.rng,
+            );
+            self.report += self.critic_stats[j].total;
+        }
+    }
+    fn update_policy(&mut self) {
+        for j in 0..2 {
+            self.objective
+                .set_critic_weights(j, &self.critic_weights[j]);
+        }
+        self.objective.set_alpha(self.alpha());
+        let (objective, rng) = (&self.objective, &mut self.rng);
+        self.replay.sample_indices(&mut self.policy_batch, rng);
+        self.replay.fill(
+            &mut self.policy_batch,
+            |transition, _weight, input, state| {
+                input.copy_from_slice(transition.observation);
+                objective.sample_noise(state, rng);
+            },
+        );
+        let batch_size = self.policy_batch.len();
+        let options = self
+            .optimize_options
+            .with_minibatch_size(batch_size)
+            .with_direction(Direction::Ascent);
+        let mut time_steps = self.policy_batch.time_steps();
+        self.policy_stats = self.policy_optimizer.optimize(
+            &mut self.objective,
+            &mut self.policy_weights,
+            &mut time_steps,
+            &mut self.policy_buffers,
+            options,
+            &mut self.rng,
+        );
+        self.report += self.policy_stats.total;
+        if self.tune_alpha {
+            // NOTE: minimize -alpha (ln(pi) + target entropy) with respect to ln(alpha), where
+            // ln(pi) is averaged over the batch as evaluated during the policy update
+            let mean_log_probability = (0..time_steps.len()).fold(T::zero(), |acc, i| {
+                acc + self.objective.log_probability(time_steps.get(i).1)
+            }) / T::from(batch_size).unwrap();
+            self.alpha_gradient[0] = -self.alpha() * (mean_log_probability + self.target_entropy);
+            self.report += self.alpha_optimizer.guarded_step(
+                &mut self.log_alpha,
+                &mut self.alpha_gradient,
+                &self.optimize_options,
+            );
+        }
+    }
+}
@@ -0,0 +1,148 @@
+use num::{Float, traits::FloatConst};
+
+use super::{Optimizer, checkpoint::OptimizerState, serialize::LoadError};
+
+/// Value of a hyperparameter as a function of the progress `t`, which counts whatever unit the
+/// caller chooses: optimizer steps, epochs, collected rollouts, ...
+#[derive(Clone, Debug)]
+pub enum Schedule<T: Float> {
+    Constant(T),
+    /// From `start` to `end` in `steps`, then `end`
+    Linear {
+        start: T,
+        end: T,
+        steps: usize,
+    },
+    /// Half a cosine from `start` to `end` in `steps`, then `end`
+    Cosine {
+        start: T,
+        end: T,
+        steps: usize,
+    },
+    /// Linear from `start` to the initial value of `then` in `steps`, then `then` shifted by `steps`
+    Warmup {
+        start: T,
+        steps: usize,
+        then: Box<Schedule<T>>,
+    },
+    /// `start` multiplied by `factor` every `every` steps
+    Step {
+        start: T,
+        factor: T,
+        every: usize,
+    },
+    /// `start * rate^t`
+    Exponential {
+        start: T,
+        rate: T,
+    },
+}
+
+impl<T: Float + FloatConst> Schedule<T> {
+    pub fn value(&self, t: usize) -> T {
+        let progress =
+            |steps: usize| T::from(t.min(steps)).unwrap() / T::from(steps.max(1)).unwrap();
+        match self {
+            &Schedule::Constant(value) => value,
+            &Schedule::Linear { start, end, steps } => start + (end - start) * progress(steps),
+            &Schedule::Cosine { start, end, steps } => {
+                let cosine = (T::PI() * progress(steps)).cos();
+                end + (start - end) * (T::one() + cosine) / T::from(2).unwrap()
+            }
+            Schedule::Warmup { start, steps, then } => {
+                if t < *steps {
+                    *start + (then.value(0) - *start) * progress(*steps)
+                } else {
+                    then.value(t - steps)
+                }
+            }
+            &Schedule::Step {
+                start,
+                factor,
+                every,
+            } => start * factor.powi((t / every.max(1)) as i32),
+            &Schedule::Exponential { start, rate } => start * rate.powf(T::from(t).unwrap()),
+        }
+    }
+}
+
+/// Optimizer whose learning rate follows `schedule`, evaluated at the number of steps done, times
+/// a scale changed by `set_learning_rate`.
+pub struct Scheduled<T: Float, O: Optimizer<T>> {
+    optimizer: O,
+    schedule: Schedule<T>,
+    scale: T,
+    steps: usize,
+}
+
+impl<T: Float + FloatConst, O: Optimizer<T>> Scheduled<T, O> {
+    pub fn new(optimizer: O, schedule: Schedule<T>) -> Self {
+        Scheduled {
+            optimizer,
+            schedule,
+            scale: T::one(),
+            steps: 0,
+        }
+    }
+    pub fn optimizer(&self) -> &O {
+        &self.optimizer
+    }
+    pub fn steps(&self) -> usize {
+        self.steps
+    }
+}
+
+impl<T: Float + FloatConst, O: Optimizer<T>> Optimizer<T> for Scheduled<T, O> {
+    fn step(&mut self, weights: &mut [T], gradient: &mut [T]) {
+        self.optimizer.set_learning_rate(self.learning_rate());
+        self.optimizer.step(weights, gradient);
+        self.steps += 1;
+    }
+    fn learning_rate(&self) -> T {
+        self.scale * self.schedule.value(self.steps)
+    }
+    /// Rescale the rest of the schedule so that the learning rate is now `alpha`. A schedule which
+    /// is at zero cannot be rescaled, it is replaced by the constant `alpha`.
+    fn set_learning_rate(&mut self, alpha: T) {
+        let value = self.schedule.value(self.steps);
+        if value == T::zero() {
+            self.schedule = Schedule::Constant(alpha);
+            self.scale = T::one();
+        } else {
+            self.scale = alpha / value;
+        }
+    }
+    /// The state of the wrapped optimizer followed by the scale in `scalars` and the number of
+    /// steps in `counters`
+    fn state(&self) -> OptimizerState<T> {
+        let mut state = self.optimizer.state();
+        state.name = format!("scheduled {}", state.name);
+        state.scalars.push(self.scale);
+        state.counters.push(self.steps as u64);
+        state
+    }
+    fn load_state(&mut self, state: &OptimizerState<T>) -> Result<(), LoadError> {
+        let wrong = || LoadError::WrongOptimizer {
+            expected: self.state().name,
+            found: state.name.clone(),
+        };
+        let (name, scale, steps) = match (
+            state.name.strip_prefix("scheduled "),
+            state.scalars.last(),
+            state.counters.last(),
+        ) {
+            (Some(name), Some(&scale), Some(&steps)) => {
+                (name, scale, usize::try_from(steps).map_err(|_| wrong())?)
+            }
+            _ => return Err(wrong()),
+        };
+        let mut inner = state.clone();
+        inner.name = name.to_string();
+        inner.scalars.pop();
+        inner.counters.pop();
+        self.optimizer.load_state(&inner)?;
+        self.scale = scale;
+        self.steps = steps;
+        Ok(())
+    }
+}
@@ -0,0 +1,446 @@
+use std::{collections::HashMap, fmt::Display, path::Path};
+
+use num::Float;
+
+use super::{
+    Activation, Eval, Weights,
+    activations::{id::Id, relu::ReLu, tanh::Tanh},
+    mlp::MLP,
+};
+
+/// Version written in the files, loading any other version fails.
+pub const VERSION: u32 = 1;
+const MLP_MAGIC: [u8; 4] = *b"RMLP";
+const TEXT_HEADER: &str = "mlp";
+
+/// Build an activation of `inputs` values.
+pub type ActivationConstructor<T> = fn(usize) -> Box<dyn Activation<T>>;
+
+/// Known activations, by `Activation::name`, used to rebuild an `MLP` when loading.
+pub struct ActivationRegistry<T: Float> {
+    constructors: HashMap<&'static str, ActivationConstructor<T>>,
+}
+
+impl<T: Float> ActivationRegistry<T> {
+    /// Registry without any activation, see `default` for the ones of this crate.
+    pub fn empty() -> Self {
+        ActivationRegistry {
+            constructors: HashMap::new(),
+        }
+    }
+    pub fn with(mut self, name: &'static str, constructor: ActivationConstructor<T>) -> Self {
+        self.register(name, constructor);
+        self
+    }
+    pub fn register(&mut self, name: &'static str, constructor: ActivationConstructor<T>) {
+        self.constructors.insert(name, constructor);
+    }
+    pub fn build(&self, name: &str, inputs: usize) -> Result<Box<dyn Activation<T>>, LoadError> {
+        self.constructors
+            .get(name)
+            .map(|c| c(inputs))
+            .ok_or_else(|| LoadError::UnknownActivation(name.to_string()))
+    }
+}
+
+impl<T: Float> Default for ActivationRegistry<T> {
+    fn default() -> Self {
+        ActivationRegistry::empty()
+            .with("id", |n| Id::layer(n).1)
+            .with("relu", |n| ReLu::layer(n).1)
+            .with("tanh", |n| Tanh::layer(n).1)
+    }
+}
+
+#[derive(Debug)]
+pub enum LoadError {
+    Io(std::io::Error),
+    /// The file does not start with the expected magic bytes or text header
+    NotAModel,
+    UnsupportedVersion(u32),
+    Checksum {
+        expected: u32,
+        found: u32,
+    },
+    Truncated,
+    UnknownActivation(String),
+    /// The number of stored weights does not match the `weights_len` of the architecture
+    WeightsLen {
+        expected: usize,
+        found: usize,
+    },
+    /// A line of the text encoding could not be read
+    Parse {
+        line: usize,
+        message: String,
+    },
+    /// The architecture has no layer, a size of zero, or more weights than a `usize` can count
+    InvalidArchitecture(&'static str),
+    /// Bytes remain after the content described by the binary encoding
+    TrailingBytes(usize),
+    /// An `OptimizerState` does not have the number of scalars or buffers of its optimizer
+    StateLen {
+        what: &'static str,
+        expected: usize,
+        found: usize,
+    },
+    /// An `OptimizerState` is loaded into another kind of optimizer
+    WrongOptimizer {
+        expected: String,
+        found: String,
+    },
+}
+
+impl Display for LoadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LoadError::Io(e) => write!(f, "io error: {e}"),
+            LoadError::NotAModel => write!(f, "not a model file"),
+            LoadError::UnsupportedVersion(v) => {
+                write!(f, "unsupported version {v}, expected {VERSION}")
+            }
+            LoadError::Checksum { expected, found } => {
+                write!(f, "checksum {found:08x} does not match {expected:08x}")
+            }
+            LoadError::Truncated => write!(f, "unexpected end of file"),
+            LoadError::UnknownActivation(name) => write!(f, "unknown activation \"{name}\""),
+            LoadError::WeightsLen { expected, found } => {
+                write!(
+                    f,
+                    "{found} weights for an architecture of {expected} weights"
+                )
+            }
+            LoadError::Parse { line, message } => write!(f, "line {line}: {message}"),
+            LoadError::InvalidArchitecture(reason) => write!(f, "invalid architecture: {reason}"),
+            LoadError::TrailingBytes(len) => write!(f, "{len} unexpected trailing bytes"),
+            LoadError::StateLen {
+                what,
+                expected,
+                found,
+            } => write!(f, "optimizer state of {found} {what} instead of {expected}"),
+            LoadError::WrongOptimizer { expected, found } => {
+                write!(
+                    f,
+                    "state of the optimizer \"{found}\" loaded into \"{expected}\""
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for LoadError {}
+
+impl From<std::io::Error> for LoadError {
+    fn from(e: std::io::Error) -> Self {
+        LoadError::Io(e)
+    }
+}
+
+/// Binary encoding: the magic bytes `RMLP`, the version, the architecture, the weights stored as
+/// little-endian `f64` whatever `T` is, and a CRC-32 of all the previous bytes.
+pub fn to_bytes<T: Float>(mlp: &MLP<T>, weights: &[T]) -> Vec<u8> {
+    let mut encoder = Encoder::new(MLP_MAGIC);
+    encoder.mlp(mlp, weights);
+    encoder.finish()
+}
+
+pub fn from_bytes<T: Float>(
+    bytes: &[u8],
+    registry: &ActivationRegistry<T>,
+) -> Result<(MLP<T>, Box<[T]>), LoadError> {
+    let mut decoder = Decoder::new(MLP_MAGIC, bytes)?;
+    let model = decoder.mlp(registry)?;
+    decoder.finish()?;
+    Ok(model)
+}
+
+/// Human-readable encoding, one entry per line:
+/// ```text
+/// mlp 1
+/// inputs 2
+/// layer 16 tanh
+/// layer 1 id
+/// weights 65
+/// 0.123
+/// ...
+/// ```
+pub fn to_text<T: Float>(mlp: &MLP<T>, weights: &[T]) -> String {
+    debug_assert!(weights.len() == mlp.weights_len(), "MLP weights");
+    let mut text = format!("{TEXT_HEADER} {VERSION}\ninputs {}\n", mlp.input_len());
+    for (outputs, activation) in mlp.layers() {
+        text += &format!("layer {outputs} {}\n", activation.name());
+    }
+    text += &format!("weights {}\n", weights.len());
+    // NOTE: the `Display` of `f64` is the shortest representation that parses back exactly
+    for w in weights {
+        text += &format!("{}\n", w.to_f64().unwrap());
+    }
+    text
+}
+
+pub fn from_text<T: Float>(
+    text: &str,
+    registry: &ActivationRegistry<T>,
+) -> Result<(MLP<T>, Box<[T]>), LoadError> {
+    let mut lines = text
+        .lines()
+        .enumerate()
+        .map(|(i, l)| (i + 1, l.split_whitespace().collect::<Vec<_>>()))
+        .filter(|(_, words)| !words.is_empty());
+    let mut next = || lines.next().ok_or(LoadError::Truncated);
+
+    match next()? {
+        (line, words) if words.len() == 2 && words[0] == TEXT_HEADER => {
+            let version = parse(line, words[1])?;
+            if version != VERSION {
+                return Err(LoadError::UnsupportedVersion(version));
+            }
+        }
+        _ => return Err(LoadError::NotAModel),
+    }
+    let inputs = match next()? {
+        (line, words) if words.len() == 2 && words[0] == "inputs" => parse(line, words[1])?,
+        (line, _) => return Err(expected(line, "inputs <len>")),
+    };
+    let mut layers = vec![];
+    let weights_len = loop {
+        match next()? {
+            (line, words) if words.len() == 3 && words[0] == "layer" => {
+                let outputs = parse(line, words[1])?;
+                layers.push((outputs, registry.build(words[2], outputs)?));
+            }
+            (line, words) if words.len() == 2 && words[0] == "weights" => {
+                break parse(line, words[1])?;
+            }
+            (line, _) => return Err(expected(line, "layer <outputs> <activation>")),
+        }
+    };
+    check_architecture(inputs, &layers, weights_len)?;
+    let mlp = MLP::new(inputs, layers);
+    let weights = (0..weights_len)
+        .map(|_| match next()? {
+            (line, words) if words.len() == 1 => {
+                parse(line, words[0]).map(|w: f64| T::from(w).unwrap())
+            }
+            (line, _) => Err(expected(line, "one weight")),
+        })
+        .collect::<Result<Box<[T]>, _>>()?;
+    match next() {
+        Ok((line, _)) => Err(expected(line, "the end of the file")),
+        Err(_) => Ok((mlp, weights)),
+    }
+}
+
+/// Write the binary encoding, see `to_bytes`.
+pub fn save<T: Float>(path: impl AsRef<Path>, mlp: &MLP<T>, weights: &[T]) -> std::io::Result<()> {
+    std::fs::write(path, to_bytes(mlp, weights))
+}
+
+/// Read a file written by `save` or containing the text encoding.
+pub fn load<T: Float>(
+    path: impl AsRef<Path>,
+    registry: &ActivationRegistry<T>,
+) -> Result<(MLP<T>, Box<[T]>), LoadError> {
+    let bytes = std::fs::read(path)?;
+    if bytes.starts_with(&MLP_MAGIC) {
+        from_bytes(&bytes, registry)
+    } else {
+        let text = std::str::from_utf8(&bytes).map_err(|_| LoadError::NotAModel)?;
+        from_text(text, registry)
+    }
+}
+
+fn parse<V: std::str::FromStr>(line: usize, word: &str) -> Result<V, LoadError>
+where
+    V::Err: Display,
+{
+    word.parse().map_err(|e| LoadError::Parse {
+        line,
+        message: format!("\"{word}\": {e}"),
+    })
+}
+
+fn expected(line: usize, what: &str) -> LoadError {
+    LoadError::Parse {
+        line,
+        message: format!("expected {what}"),
+    }
+}
+
+/// Verify the architecture before building the `MLP`, whose methods assume at least one layer and
+/// no size of zero, and that it has `found` weights.
+fn check_architecture<T: Float>(
+    inputs: usize,
+    layers: &[(usize, Box<dyn Activation<T>>)],
+    found: usize,
+) -> Result<(), LoadError> {
+    if layers.is_empty() {
+        return Err(LoadError::InvalidArchitecture("no layer"));
+    }
+    if inputs == 0 || layers.iter().any(|&(outputs, _)| outputs == 0) {
+        return Err(LoadError::InvalidArchitecture("a size is zero"));
+    }
+    let expected = layers
+        .iter()
+        .try_fold((inputs, 0usize), |(inputs, len), (outputs, activation)| {
+            let layer = (inputs.checked_add(1)?)
+                .checked_mul(*outputs)?
+                .checked_add(activation.weights_len())?;
+            Some((*outputs, len.checked_add(layer)?))
+        })
+        .ok_or(LoadError::InvalidArchitecture("too many weights"))?
+        .1;
+    if expected == found {
+        Ok(())
+    } else {
+        Err(LoadError::WeightsLen { expected, found })
+    }
+}
+
+/// CRC-32 (IEEE), bitwise as the files are small compared to a training run.
+pub(crate) fn crc32(bytes: &[u8]) -> u32 {
+    !bytes.iter().fold(!0u32, |crc, &b| {
+        (0..8).fold(crc ^ b as u32, |crc, _| {
+            (crc >> 1) ^ (0xEDB8_8320 & (crc & 1).wrapping_neg())
+        })
+    })
+}
+
+/// Little-endian writer of the binary files: magic bytes, version, then the content.
+pub(crate) struct Encoder {
+    bytes: Vec<u8>,
+}
+
+impl Encoder {
+    pub(crate) fn new(magic: [u8; 4]) -> Self {
+        let mut encoder = Encoder {
+            bytes: magic.to_vec(),
+        };
+        encoder.u32(VERSION);
+        encoder
+    }
+    pub(crate) fn u32(&mut self, v: u32) {
+        self.bytes.extend_from_slice(&v.to_le_bytes());
+    }
+    pub(crate) fn u64(&mut self, v: u64) {
+        self.bytes.extend_from_slice(&v.to_le_bytes());
+    }
+    pub(crate) fn float<T: Float>(&mut self, v: T) {
+        self.bytes
+            .extend_from_slice(&v.to_f64().unwrap().to_le_bytes());
+    }
+    pub(crate) fn floats<T: Float>(&mut self, vs: &[T]) {
+        self.u64(vs.len() as u64);
+        vs.iter().for_each(|&v| self.float(v));
+    }
+    pub(crate) fn str(&mut self, s: &str) {
+        self.u64(s.len() as u64);
+        self.bytes.extend_from_slice(s.as_bytes());
+    }
+    pub(crate) fn mlp<T: Float>(&mut self, mlp: &MLP<T>, weights: &[T]) {
+        debug_assert!(weights.len() == mlp.weights_len(), "MLP weights");
+        self.u64(mlp.input_len() as u64);
+        self.u64(mlp.layers().count() as u64);
+        for (outputs, activation) in mlp.layers() {
+            self.u64(outputs as u64);
+            self.str(activation.name());
+        }
+        self.floats(weights);
+    }
+    /// Append the checksum and return the bytes.
+    pub(crate) fn finish(mut self) -> Vec<u8> {
+        let crc = crc32(&self.bytes);
+        self.u32(crc);
+        self.bytes
+    }
+}
+
+/// Reader of the files written by `Encoder`, the magic bytes, version and checksum are verified
+/// by `new`.
+pub(crate) struct Decoder<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Decoder<'a> {
+    pub(crate) fn new(magic: [u8; 4], bytes: &'a [u8]) -> Result<Self, LoadError> {
+        if !bytes.starts_with(&magic) {
+            return Err(LoadError::NotAModel);
+        }
+        if bytes.len() < magic.len() + 8 {
+            return Err(LoadError::Truncated);
+        }
+        let (content, crc) = bytes.split_at(bytes.len() - 4);
+        let mut decoder = Decoder {
+            bytes: &content[magic.len()..],
+        };
+        let version = decoder.u32()?;
+        if version != VERSION {
+            return Err(LoadError::UnsupportedVersion(version));
+        }
+        let expected = u32::from_le_bytes(crc.try_into().unwrap());
+        let found = crc32(content);
+        if expected != found {
+            return Err(LoadError::Checksum { expected, found });
+        }
+        Ok(decoder)
+    }
+    fn take(&mut self, len: usize) -> Result<&'a [u8], LoadError> {
+        if self.bytes.len() < len {
+            return Err(LoadError::Truncated);
+        }
+        let (taken, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Ok(taken)
+    }
+    pub(crate) fn u32(&mut self) -> Result<u32, LoadError> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+    pub(crate) fn u64(&mut self) -> Result<u64, LoadError> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+    pub(crate) fn count(&mut self) -> Result<usize, LoadError> {
+        let len = self.u64()? as usize;
+        // NOTE: a corrupted length cannot make us allocate more than the file
+        if len > self.bytes.len() {
+            return Err(LoadError::Truncated);
+        }
+        Ok(len)
+    }
+    pub(crate) fn float<T: Float>(&mut self) -> Result<T, LoadError> {
+        let v = f64::from_le_bytes(self.take(8)?.try_into().unwrap());
+        Ok(T::from(v).unwrap())
+    }
+    pub(crate) fn floats<T: Float>(&mut self) -> Result<Box<[T]>, LoadError> {
+        let len = self.count()?;
+        (0..len).map(|_| self.float()).collect()
+    }
+    pub(crate) fn str(&mut self) -> Result<&'a str, LoadError> {
+        let len = self.count()?;
+        let bytes = self.take(len)?;
+        std::str::from_utf8(bytes)
+            .map_err(|_| LoadError::UnknownActivation(String::from_utf8_lossy(bytes).into()))
+    }
+    pub(crate) fn mlp<T: Float>(
+        &mut self,
+        registry: &ActivationRegistry<T>,
+    ) -> Result<(MLP<T>, Box<[T]>), LoadError> {
+        let inputs = self.u64()? as usize;
+        let layers = (0..self.count()?)
+            .map(|_| {
+                let outputs = self.u64()? as usize;
+                Ok((outputs, registry.build(self.str()?, outputs)?))
+            })
+            .collect::<Result<Vec<_>, LoadError>>()?;
+        let weights = self.floats()?;
+        check_architecture(inputs, &layers, weights.len())?;
+        Ok((MLP::new(inputs, layers), weights))
+    }
+    /// Fail if there are bytes left, which means that the content does not match its description.
+    pub(crate) fn finish(self) -> Result<(), LoadError> {
+        if self.bytes.is_empty() {
+            Ok(())
+        } else {
+            Err(LoadError::TrailingBytes(self.bytes.len()))
+        }
+    }
+}
@@ -0,0 +1,475 @@
+use num::Float;
+use rand::{SeedableRng, rngs::StdRng};
+use rand_distr::{Distribution, StandardNormal};
+
+use super::{
+    BackProp, Direction, Eval, Gradient, OptimizeBuffers, OptimizeOptions, OptimizeReport,
+    OptimizeStats, Optimizer, Weights,
+    least_squar_value::LeastSquareValue,
+    mlp::MLP,
+    replay::{Batch, ReplayBuffer},
+};
+
+/// $Q(s, \mu(s))$ as a function of the weights of the deterministic actor $\mu$, for a copy of
+/// the critic weights set with `set_critic_weights`. The critic input is the observation followed
+/// by the action.
+/// State: actor state | critic input | critic state | actor back | actor front | critic back |
+/// critic front
+pub struct ActorObjective<T: Float> {
+    actor: MLP<T>,
+    critic: LeastSquareValue<T>,
+    critic_weights: Box<[T]>,
+}
+
+impl<T: Float> ActorObjective<T> {
+    pub fn new(actor: MLP<T>, critic: LeastSquareValue<T>) -> Self {
+        debug_assert!(
+            critic.input_len() == actor.input_len() + actor.output_len(),
+            "The critic input must be the observation followed by the action"
+        );
+        ActorObjective {
+            critic_weights: critic.empty_weights(),
+            actor,
+            critic,
+        }
+    }
+    pub fn actor(&self) -> &MLP<T> {
+        &self.actor
+    }
+    pub fn critic(&self) -> &LeastSquareValue<T> {
+        &self.critic
+    }
+    pub fn set_critic_weights(&mut self, weights: &[T]) {
+        self.critic_weights.copy_from_slice(weights);
+    }
+    pub fn q_value(&self, state: &[T]) -> T {
+        self.critic.mlp().output(self.critic_state(state))[0]
+    }
+    fn critic_state<'a>(&self, state: &'a [T]) -> &'a [T] {
+        &state[self.actor.state_len() + self.critic.input_len()..][..self.critic.mlp().state_len()]
+    }
+}
+
+impl<T: Float> Weights<T> for ActorObjective<T> {
+    fn weights_len(&self) -> usize {
+        self.actor.weights_len()
+    }
+}
+impl<T: Float> Eval<T> for ActorObjective<T> {
+    fn state_len(&self) -> usize {
+        let critic = self.critic.mlp();
+        self.actor.state_len()
+            + self.critic.input_len()
+            + critic.state_len()
+            + 2 * self.actor.min_back_front_len()
+            + 2 * critic.min_back_front_len()
+    }
+    fn eval(&self, input: &[T], weights: &[T], state: &mut [T]) {
+        let (actor_state, tmp) = state.split_at_mut(self.actor.state_len());
+        let (critic_input, tmp) = tmp.split_at_mut(self.critic.input_len());
+        let critic_state = &mut tmp[..self.critic.mlp().state_len()];
+        self.actor.eval(input, weights, actor_state);
+        let (observation, action) = critic_input.split_at_mut(input.len());
+        observation.copy_from_slice(input);
+        action.copy_from_slice(self.actor.output(actor_state));
+        self.critic
+            .mlp()
+            .eval(critic_input, &self.critic_weights, critic_state);
+    }
+    fn input_len(&self) -> usize {
+        self.actor.input_len()
+    }
+    fn output_len(&self) -> usize {
+        self.actor.output_len()
+    }
+    /// The output is the action
+    fn output<'a>(&self, state: &'a [T]) -> &'a [T] {
+        self.actor.output(&state[..self.actor.state_len()])
+    }
+    fn output_mut<'a>(&self, state: &'a mut [T]) -> &'a mut [T] {
+        self.actor.output_mut(&mut state[..self.actor.state_len()])
+    }
+}
+impl<T: Float> Gradient<T> for ActorObjective<T> {
+    fn compute_gradient(&self, input: &[T], weights: &[T], state: &mut [T], gradient: &mut [T]) {
+        let critic = self.critic.mlp();
+        let (actor_state, tmp) = state.split_at_mut(self.actor.state_len());
+        let (critic_input, tmp) = tmp.split_at_mut(self.critic.input_len());
+        let (critic_state, tmp) = tmp.split_at_mut(critic.state_len());
+        let (actor_back, tmp) = tmp.split_at_mut(self.actor.min_back_front_len());
+        let (actor_front, tmp) = tmp.split_at_mut(self.actor.min_back_front_len());
+        let (critic_back, tmp) = tmp.split_at_mut(critic.min_back_front_len());
+        let critic_front = &mut tmp[..critic.min_back_front_len()];
+        critic_back[0] = T::one();
+        let d_critic_input = critic.input_gradient(
+            critic_input,
+            &self.critic_weights,
+            critic_state,
+            critic_front,
+            critic_back,
+        );
+        // NOTE: dQ/da is the part of the critic input gradient after the observation
+        actor_back[..self.actor.output_len()].copy_from_slice(&d_critic_input[input.len()..]);
+        self.actor.back_prop(
+            input,
+            weights,
+            actor_state,
+            actor_front,
+            actor_back,
+            gradient,
+        );
+    }
+    fn objective(&self, state: &[T]) -> T {
+        self.q_value(state)
+    }
+}
+
+/// Twin Delayed DDPG. Two critics regress on the smallest target Q-value of the smoothed target
+/// action, and the deterministic actor follows $\nabla_a Q_1$ every `policy_delay` critic updates.
+/// DDPG is obtained with a single critic, no target smoothing and no delay, see `with_ddpg`.
+pub struct Td3<T: Float, O: Optimizer<T>>
+where
+    StandardNormal: Distribution<T>,
+{
+    objective: ActorObjective<T>,
+    critic_2: LeastSquareValue<T>,
+    actor_optimizer: O,
+    critic_optimizers: [O; 2],
+    actor_weights: Box<[T]>,
+    actor_target_weights: Box<[T]>,
+    critic_weights: [Box<[T]>; 2],
+    critic_target_weights: [Box<[T]>; 2],
+    actor_buffers: OptimizeBuffers<T>,
+    critic_buffers: OptimizeBuffers<T>,
+    replay: ReplayBuffer<T>,
+    critic_batch: Batch<T>,
+    actor_batch: Batch<T>,
+    actor_state: Box<[T]>,
+    critic_input: Box<[T]>,
+    critic_state: Box<[T]>,
+    low: T,
+    high: T,
+    gamma: T,
+    tau: T,
+    twin: bool,
+    policy_delay: usize,
+    exploration_noise: T,
+    target_noise: T,
+    target_noise_clip: T,
+    updates: usize,
+    rng: StdRng,
+    optimize_options: OptimizeOptions<T>,
+    report: OptimizeReport,
+    actor_stats: OptimizeStats<T>,
+    critic_stats: [OptimizeStats<T>; 2],
+}
+
+impl<T: Float, O: Optimizer<T>> Td3<T, O>
+where
+    StandardNormal: Distribution<T>,
+{
+    /// The actions are clipped to `[low, high]`, which the last activation of the actor should also respect.
+    /// Both critics must have the same architecture.
+    pub fn new(
+        objective: ActorObjective<T>,
+        critic_2: LeastSquareValue<T>,
+        actor_optimizer: O,
+        critic_optimizers: [O; 2],
+        replay: ReplayBuffer<T>,
+        batch_size: usize,
+        (low, high): (T, T),
+    ) -> Self {
+        let critic_1 = objective.critic();
+        debug_assert!(
+            critic_1.weights_len() == critic_2.weights_len()
+                && critic_1.state_len() == critic_2.state_len(),
+            "The twin critics must have the same architecture"
+        );
+        debug_assert!(
+            replay.observation_len() == objective.input_len()
+                && replay.action_len() == objective.output_len(),
+            "replay dimensions"
+        );
+        Td3 {
+            actor_weights: objective.empty_weights(),
+            actor_target_weights: objective.empty_weights(),
+            critic_weights: [critic_1.empty_weights(), critic_1.empty_weights()],
+            critic_target_weights: [critic_1.empty_weights(), critic_1.empty_weights()],
+            actor_buffers: OptimizeBuffers::new(objective.weights_len(), batch_size),
+            critic_buffers: OptimizeBuffers::new(critic_1.weights_len(), batch_size),
+            critic_batch: Batch::new(batch_size, critic_1.input_len(), critic_1.state_len()),
+            actor_batch: Batch::new(batch_size, objective.input_len(), objective.state_len()),
+            actor_state: objective.actor().empty_state(),
+            critic_input: vec![T::zero(); critic_1.input_len()].into_boxed_slice(),
+            critic_state: critic_1.mlp().empty_state(),
+            low,
+            high,
+            gamma: T::from(0.99).unwrap(),
+            tau: T::from(0.005).unwrap(),
+            twin: true,
+            policy_delay: 2,
+            exploration_noise: T::from(0.1).unwrap() * (high - low),
+            target_noise: T::from(0.1).unwrap() * (high - low),
+            target_noise_clip: T::from(0.25).unwrap() * (high - low),
+            updates: 0,
+            rng: StdRng::from_os_rng(),
+            optimize_options: OptimizeOptions::default(),
+            report: OptimizeReport::default(),
+            actor_stats: OptimizeStats::default(),
+            critic_stats: [OptimizeStats::default(); 2],
+            objective,
+            critic_2,
+            actor_optimizer,
+            critic_optimizers,
+            replay,
+        }
+    }
+    pub fn with_gamma(mut self, gamma: T) -> Self {
+        self.gamma = gamma;
+        self
+    }
+    /// Polyak averaging coefficient of the target weights
+    pub fn with_tau(mut self, tau: T) -> Self {
+        self.tau = tau;
+        self
+    }
+    pub fn with_policy_delay(mut self, policy_delay: usize) -> Self {
+        self.policy_delay = policy_delay.max(1);
+        self
+    }
+    /// Standard deviation of the Gaussian noise added by `act`
+    pub fn with_exploration_noise(mut self, sigma: T) -> Self {
+        self.exploration_noise = sigma;
+        self
+    }
+    /// Change the exploration noise during training, for instance following a `Schedule`
+    pub fn set_exploration_noise(&mut self, sigma: T) {
+        self.exploration_noise = sigma;
+    }
+    /// Standard deviation and clipping of the noise added to the target actions
+    pub fn with_target_noise(mut self, sigma: T, clip: T) -> Self {
+        self.target_noise = sigma;
+        self.target_noise_clip = clip;
+        self
+    }
+    /// Single critic, no target policy smoothing and an actor update after every critic update.
+    pub fn with_ddpg(mut self) -> Self {
+        self.twin = false;
+        self.policy_delay = 1;
+        self.target_noise = T::zero();
+        self.target_noise_clip = T::zero();
+        self
+    }
+    /// Seed the exploration noise, the sampling of the batches and the target noise, for reproducible runs
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.rng = StdRng::seed_from_u64(seed);
+        self
+    }
+    /// Gradient clipping of every update
+    pub fn with_optimize_options(mut self, options: OptimizeOptions<T>) -> Self {
+        self.optimize_options = options;
+        self
+    }
+    /// Steps, skipped minibatches and clipped gradients cumulated over all the updates
+    pub fn report(&self) -> OptimizeReport {
+        self.report
+    }
+    /// Q-value and gradient norm of the last actor update
+    pub fn last_actor_stats(&self) -> OptimizeStats<T> {
+        self.actor_stats
+    }
+    /// TD loss and gradient norm of the last update of the critic `0` or `1`
+    pub fn last_critic_stats(&self, critic: usize) -> OptimizeStats<T> {
+        self.critic_stats[critic]
+    }
+    pub fn actor(&self) -> &MLP<T> {
+        self.objective.actor()
+    }
+    pub fn actor_weights(&self) -> &[T] {
+        &self.actor_weights
+    }
+    /// Call `sync_targets` after modifying the weights directly, for instance at initialization.
+    pub fn actor_weights_mut(&mut self) -> &mut [T] {
+        &mut self.actor_weights
+    }
+    pub fn critic_weights_mut(&mut self, critic: usize) -> &mut [T] {
+        &mut self.critic_weights[critic]
+    }
+    pub fn sync_targets(&mut self) {
+        self.actor_target_weights
+            .copy_from_slice(&self.actor_weights);
+        for (target, weights) in self
+            .critic_target_weights
+            .iter_mut()
+            .zip(self.critic_weights.iter())
+        {
+            target.copy_from_slice(weights);
+        }
+    }
+
+    /// Action of the actor without noise
+    pub fn deterministic_action(&mut self, observation: &[T], action: &mut [T]) {
+        let actor = self.objective.actor();
+        actor.eval(observation, &self.actor_weights, &mut self.actor_state);
+        action.copy_from_slice(actor.output(&self.actor_state));
+    }
+    /// Action of the actor with Gaussian exploration noise, clipped to the bounds
+    pub fn act(&mut self, observation: &[T], action: &mut [T]) {
+        self.deterministic_action(observation, action);
+        action.iter_mut().for_each(|a| {
+            let noise: T = StandardNormal.sample(&mut self.rng);
+            *a = (*a + self.exploration_noise * noise)
+                .max(self.low)
+                .min(self.high);
+        });
+    }
+    pub fn store(
+        &mut self,
+        observation: &[T],
+        action: &[T],
+        reward: T,
+        next_observation: &[T],
+        done: bool,
+    ) {
+        self.replay
+            .push(observation, action, reward, next_observation, done);
+    }
+
+    /// Update the critics on a batch sampled from the replay buffer and, every `policy_delay`
+    /// calls, the actor and the target weights. Nothing is done until the replay buffer holds a batch.
+    pub fn update(&mut self) {
+        if self.replay.len() < self.critic_batch.len() {
+            return;
+        }
+        self.update_critics();
+        self.updates += 1;
+        if self.updates.is_multiple_of(self.policy_delay) {
+            self.update_actor();
+            self.update_targets();
+        }
+    }
+    fn update_critics(&mut self) {
+        let Td3 {
+            objective,
+            actor_target_weights,
+            critic_target_weights,
+            replay,
+            critic_batch,
+            actor_state,
+            critic_input,
+            critic_state,
+            low,
+            high,
+            gamma,
+            twin,
+            target_noise,
+            target_noise_clip,
+            rng,
+            ..
+        } = self;
+        let (actor, critic) = (&objective.actor, objective.critic.mlp());
+        let observation_len = actor.input_len();
+        replay.sample_indices(critic_batch, rng);
+        replay.fill(critic_batch, |transition, _weight, input, state| {
+            let next_q = if transition.done {
+                T::zero()
+            } else {
+                actor.eval(
+                    transition.next_observation,
+                    actor_target_weights,
+                    actor_state,
+                );
+                let (observation, action) = critic_input.split_at_mut(observation_len);
+                observation.copy_from_slice(transition.next_observation);
+                action
+                    .iter_mut()
+                    .zip(actor.output(actor_state))
+                    .for_each(|(a, &mu)| {
+                        let noise: T = StandardNormal.sample(rng);
+                        let noise = (*target_noise * noise)
+                            .max(-*target_noise_clip)
+                            .min(*target_noise_clip);
+                        *a = (mu + noise).max(*low).min(*high);
+                    });
+                let critics = if *twin { 2 } else { 1 };
+                critic_target_weights[..critics]
+                    .iter()
+                    .fold(T::infinity(), |acc, weights| {
+                        critic.eval(critic_input, weights, critic_state);
+                        acc.min(critic.output(critic_state)[0])
+                    })
+            };
+            let (observation, action) = input.split_at_mut(observation_len);
+            observation.copy_from_slice(transition.observation);
+            action.copy_from_slice(transition.action);
+            // NOTE: both critics have the same state layout, so the target is shared
+            *objective.critic.target_mut(state) = transition.reward + *gamma * next_q;
+        });
+        let options = self
+            .optimize_options
+            .with_minibatch_size(self.critic_batch.len())
+            .with_direction(Direction::Descent);
+        let mut time_steps = self.critic_batch.time_steps();
+        self.critic_stats[0] = self.critic_optimizers[0].optimize(
+            &mut self.objective.critic,
+            &mut self.critic_weights[0],
+            &mut time_steps,
+            &mut self.critic_buffers,
+            options,
+            &mut self.rng,
+        );
+        self.report += self.critic_stats[0].total;
+        if self.twin {
+            self.critic_stats[1] = self.critic_optimizers[1].optimize(
+                &mut self.critic_2,
+                &mut self.critic_weights[1],
+                &mut time_steps,
+                &mut self.critic_buffers,
+                options,
+                &mut self.rng,
+            );
+            self.report += self.critic_stats[1].total;
+        }
+    }
+    fn update_actor(&mut self) {
+        self.objective.set_critic_weights(&self.critic_weights[0]);
+        self.replay.sample(
+            &mut self.actor_batch,
+            &mut self.rng,
+            |transition, _weight, input, _state| {
+                input.copy_from_slice(transition.observation);
+            },
+        );
+        let options = self
+            .optimize_options
+            .with_minibatch_size(self.actor_batch.len())
+            .with_direction(Direction::Ascent);
+        let mut time_steps = self.actor_batch.time_steps();
+        self.actor_stats = self.actor_optimizer.optimize(
+            &mut self.objective,
+            &mut self.actor_weights,
+            &mut time_steps,
+            &mut self.actor_buffers,
+            options,
+            &mut self.rng,
+        );
+        self.report += self.actor_stats.total;
+    }
+    fn update_targets(&mut self) {
+        let tau = self.tau;
+        let polyak = |target: &mut [T], weights: &[T]| {
+            target
+                .iter_mut()
+                .zip(weights.iter())
+                .for_each(|(t, &w)| *t = tau * w + (T::one() - tau) * *t)
+        };
+        polyak(&mut self.actor_target_weights, &self.actor_weights);
+        for (target, weights) in self
+            .critic_target_weights
+            .iter_mut()
+            .zip(self.critic_weights.iter())
+        {
+            polyak(target, weights);
+        }
+    }
+}
@@ -1,11 +1,35 @@
-use crate::training::{Direction, activations::id::Id, policies::normal_policy::NormalPolicy};
+use std::time::Instant;
+
+use rand::{Rng, SeedableRng, rngs::StdRng};
+
+use crate::training::{
+    Direction,
+    activations::{id::Id, tanh::Tanh},
+    dqn::{Dqn, TargetUpdate, TdLoss},
+    init::Init,
+    policies::normal_policy::NormalPolicy,
+    policies::squashed_normal_policy::SquashedNormalPolicy,
+    ppo::{ClippedSurrogate, Ppo},
+    replay::ReplayBuffer,
+    sac::{Sac, SoftActorObjective},
+    td3::{ActorObjective, Td3},
+};
 
 use super::{
-    Eval, Optimizer, TimeStep, Value, Weights, least_squar_value::LeastSquareValue, mlp::MLP,
-    optimizers::adam::Adam,
+    Eval, OptimizeBuffers, OptimizeOptions, Optimizer, TimeStep, Value, Weights,
+    least_squar_value::LeastSquareValue,
+    mlp::MLP,
+    optimizers::{
+        adam::Adam, adamax::AdaMax, adamw::AdamW, lion::Lion, rmsprop::RmsProp, sgd::Sgd,
+    },
 };
 
-pub fn test_value_adam() {
+/// Least-squares regression of `x + y` on a grid, returning the final error.
+fn value_regression<O: Optimizer<f32>>(
+    optimizer: impl FnOnce(usize) -> O,
+    epochs: usize,
+    rng: &mut impl Rng,
+) -> f32 {
     let mut value = LeastSquareValue::<f32>::new(MLP::new(
         2,
         vec![Id::layer(10), Id::layer(10), Id::layer(1)],
@@ -13,11 +37,9 @@ pub fn test_value_adam() {
     let mut weights = value.empty_weights();
     weights
         .iter_mut()
-        .for_each(|w| *w = rand::random_range(-1e-1f32..=1e-1));
-    let mut gradient = value.empty_weights();
-    let mut tmp_gradient = value.empty_weights();
+        .for_each(|w| *w = rng.random_range(-1e-1f32..=1e-1));
 
-    let mut adam = Adam::<f32>::new(value.weights_len()).with_alpha(1e-3);
+    let mut optimizer = optimizer(value.weights_len());
     let f = |x, y| x + y;
 
     let n = 64;
@@ -44,29 +66,60 @@ pub fn test_value_adam() {
         .iter_mut()
         .map(|(input, state)| TimeStep { input, state })
         .collect::<Vec<_>>();
+    let mut buffers = OptimizeBuffers::new(value.weights_len(), time_steps.len());
 
-    adam.optimize(
-        10,
-        64,
+    optimizer.optimize(
         &mut value,
         &mut weights,
-        &mut gradient,
-        &mut tmp_gradient,
-        &mut time_steps,
-        Direction::Descent,
+        &mut time_steps[..],
+        &mut buffers,
+        OptimizeOptions::default()
+            .with_epochs(epochs)
+            .with_minibatch_size(64),
+        rng,
     );
 
-    let d = ctx
-        .into_iter()
+    ctx.into_iter()
         .map(|(input @ [x, y], mut state)| {
             value.eval(&input, &weights, &mut state);
             (f(x, y) - value.value(&state)).powi(2)
         })
         .sum::<f32>()
-        .sqrt();
+        .sqrt()
+}
+
+pub fn test_value_adam() {
+    let d = value_regression(
+        |len| Adam::<f32>::new(len).with_alpha(1e-3),
+        10,
+        &mut rand::rng(),
+    );
     println!("d: {d:.2e}");
 }
 
+/// Compare the optimizers on the regression of `test_value_adam`, with the same seed for all.
+pub fn benchmark_optimizers() {
+    fn run<O: Optimizer<f32>>(name: &str, optimizer: impl FnOnce(usize) -> O) {
+        let start = Instant::now();
+        let d = value_regression(optimizer, 10, &mut StdRng::seed_from_u64(0));
+        println!("{name:>12}: d = {d:.2e} in {:?}", start.elapsed());
+    }
+    run("sgd", |len| Sgd::new(len).with_alpha(5e-2));
+    run("momentum", |len| {
+        Sgd::new(len).with_parameters(5e-3, 0.9, false)
+    });
+    run("nesterov", |len| {
+        Sgd::new(len).with_parameters(5e-3, 0.9, true)
+    });
+    run("rmsprop", |len| RmsProp::new(len).with_alpha(1e-3));
+    run("adam", |len| Adam::new(len).with_alpha(1e-3));
+    run("adamax", |len| {
+        AdaMax::new(len).with_parameters(2e-3, 0.9, 0.999, 1e-8)
+    });
+    run("adamw", |len| AdamW::new(len).with_alpha(1e-3));
+    run("lion", |len| Lion::new(len).with_alpha(3e-4));
+}
+
 pub fn test_policy_adam() {
     let mut policy = NormalPolicy::<f32>::new(
         MLP::new(2, vec![Id::layer(10), Id::layer(10), Id::layer(1)]),
@@ -76,8 +129,6 @@ pub fn test_policy_adam() {
     weights
         .iter_mut()
         .for_each(|w| *w = rand::random_range(-1e-1f32..=1e-1));
-    let mut gradient = policy.empty_weights();
-    let mut tmp_gradient = policy.empty_weights();
 
     let mut adam = Adam::<f32>::new(policy.weights_len()).with_alpha(1e-3);
     let f = |x, y| x + y;
@@ -106,17 +157,19 @@ pub fn test_policy_adam() {
         .iter_mut()
         .map(|(input, state)| TimeStep { input, state })
         .collect::<Vec<_>>();
+    let mut buffers = OptimizeBuffers::new(policy.weights_len(), time_steps.len());
 
-    // FIXME: optimizing a stochastic policy by itself does not lead anywhere. An actual use of it such as PPO should be what is optimized. The following adam.optimize makes no sense, although it is still usefull as a test to check that there is no segmentatition fault.
+    // FIXME: optimizing a stochastic policy by itself does not lead anywhere. An actual use of it such as PPO should be what is optimized (see `test_ppo_adam`). The following adam.optimize makes no sense, although it is still usefull as a test to check that there is no segmentatition fault.
     adam.optimize(
-        10,
-        64,
         &mut policy,
         &mut weights,
-        &mut gradient,
-        &mut tmp_gradient,
-        &mut time_steps,
-        Direction::Ascent,
+        &mut time_steps[..],
+        &mut buffers,
+        OptimizeOptions::default()
+            .with_epochs(10)
+            .with_minibatch_size(64)
+            .with_direction(Direction::Ascent),
+        &mut rand::rng(),
     );
 
     let d = ctx
@@ -129,3 +182,1879 @@ pub fn test_policy_adam() {
         .sqrt();
     println!("d: {d:.2e}");
 }
+
+pub fn test_ppo_adam() {
+    let mut rng = rand::rng();
+    let policy_mlp = MLP::<f32>::new(2, vec![Tanh::layer(16), Id::layer(1)]);
+    let mut policy_weights = policy_mlp.empty_weights();
+    policy_mlp.init_weights(
+        &mut policy_weights,
+        Init::Orthogonal { gain: 2f32.sqrt() },
+        &mut rng,
+    );
+    policy_mlp.scale_final_layer(&mut policy_weights, 0.01);
+    let value_mlp = MLP::<f32>::new(2, vec![Tanh::layer(16), Id::layer(1)]);
+    let mut value_weights = value_mlp.empty_weights();
+    value_mlp.init_weights(
+        &mut value_weights,
+        Init::Orthogonal { gain: 2f32.sqrt() },
+        &mut rng,
+    );
+
+    let surrogate = ClippedSurrogate::new(NormalPolicy::new(policy_mlp, 0.1));
+    let value = LeastSquareValue::new(value_mlp);
+    let policy_adam = Adam::<f32>::new(surrogate.weights_len()).with_alpha(1e-3);
+    let value_adam = Adam::<f32>::new(value.weights_len()).with_alpha(1e-3);
+    let ctx_count = 256;
+    let mut ppo = Ppo::new(surrogate, value, policy_adam, value_adam, ctx_count, 1)
+        .with_epochs(4)
+        .with_minibatch_size(64);
+    ppo.policy_weights_mut().copy_from_slice(&policy_weights);
+    ppo.value_weights_mut().copy_from_slice(&value_weights);
+    let f = |x: f32, y: f32| x + y;
+
+    for i in 0..=200 {
+        let mut ctx_list = (0..ctx_count)
+            .map(|_| {
+                [
+                    rand::random_range(-1f32..=1.0),
+                    rand::random_range(-1f32..=1.0),
+                ]
+            })
+            .collect::<Vec<_>>();
+        ppo.collect(
+            &mut ctx_list,
+            |ctx, input| input.copy_from_slice(ctx),
+            |&mut [x, y], action| (-(f(x, y) - action[0]).powi(2), true),
+        );
+        ppo.update();
+        if i % 50 == 0 {
+            println!("{i:>3}: mean reward = {:.2e}", ppo.mean_reward());
+        }
+    }
+}
+
+pub fn test_dqn_adam() {
+    let loss = TdLoss::new(MLP::new(1, vec![Tanh::layer(16), Id::layer(2)]));
+    let adam = Adam::<f32>::new(loss.weights_len()).with_alpha(1e-3);
+    let replay = ReplayBuffer::new(4096, 1, 1).with_prioritization(0.6, 0.4);
+    let mut dqn = Dqn::new(loss, adam, replay, 64)
+        .with_double_q(true)
+        .with_target_update(TargetUpdate::Polyak { tau: 1e-2 })
+        .with_epsilon(1.0, 0.05, 2000);
+    dqn.weights_mut()
+        .iter_mut()
+        .for_each(|w| *w = rand::random_range(-1e-1f32..=1e-1));
+    dqn.sync_target();
+    // NOTE: the action 1 is rewarded by x and the action 0 by -x, so the best mean reward is 0.5
+    let reward = |x: f32, action: usize| if action == 1 { x } else { -x };
+
+    let mut total = 0.0;
+    for i in 1..=4000 {
+        let x = rand::random_range(-1f32..=1.0);
+        let action = dqn.act(&[x]);
+        dqn.store(&[x], action, reward(x, action), &[0.0], true);
+        dqn.update();
+        total += reward(x, dqn.greedy_action(&[x]));
+        if i % 1000 == 0 {
+            println!(
+                "{i:>4}: epsilon = {:.2}, mean greedy reward = {:.2e}",
+                dqn.epsilon(),
+                total / 1000.0
+            );
+            total = 0.0;
+        }
+    }
+}
+
+pub fn test_td3_adam() {
+    let critic = || LeastSquareValue::new(MLP::new(2, vec![Tanh::layer(16), Id::layer(1)]));
+    let objective =
+        ActorObjective::new(MLP::new(1, vec![Tanh::layer(16), Tanh::layer(1)]), critic());
+    let adam = |len| Adam::<f32>::new(len).with_alpha(3e-3);
+    let (actor_len, critic_len) = (objective.weights_len(), objective.critic().weights_len());
+    let replay = ReplayBuffer::new(4096, 1, 1);
+    let mut td3 = Td3::new(
+        objective,
+        critic(),
+        Adam::new(actor_len).with_alpha(1e-3),
+        [adam(critic_len), adam(critic_len)],
+        replay,
+        64,
+        (-1.0, 1.0),
+    );
+    td3.actor_weights_mut()
+        .iter_mut()
+        .for_each(|w| *w = rand::random_range(-1e-1f32..=1e-1));
+    for c in 0..2 {
+        td3.critic_weights_mut(c)
+            .iter_mut()
+            .for_each(|w| *w = rand::random_range(-1e-1f32..=1e-1));
+    }
+    td3.sync_targets();
+    // NOTE: the best action is x / 2, with a reward of 0
+    let reward = |x: f32, a: f32| -(a - x / 2.0).powi(2);
+
+    let mut action = [0.0];
+    let mut total = 0.0;
+    for i in 1..=4000 {
+        let x = rand::random_range(-1f32..=1.0);
+        td3.act(&[x], &mut action);
+        td3.store(&[x], &action, reward(x, action[0]), &[0.0], true);
+        td3.update();
+        td3.deterministic_action(&[x], &mut action);
+        total += reward(x, action[0]);
+        if i % 1000 == 0 {
+            println!("{i:>4}: mean deterministic reward = {:.2e}", total / 1000.0);
+            total = 0.0;
+        }
+    }
+}
+
+pub fn test_sac_adam() {
+    let critic = || LeastSquareValue::new(MLP::new(2, vec![Tanh::layer(16), Id::layer(1)]));
+    let policy = SquashedNormalPolicy::new(
+        NormalPolicy::new(MLP::new(1, vec![Tanh::layer(16), Id::layer(2)]), 1.0)
+            .with_state_dependent_log_std(),
+        -1.0,
+        1.0,
+    );
+    let objective = SoftActorObjective::new(policy, [critic(), critic()]);
+    let replay = ReplayBuffer::new(4096, 1, 1);
+    let mut sac = Sac::new(
+        objective,
+        |len| Adam::<f32>::new(len).with_alpha(3e-3),
+        replay,
+        64,
+    )
+    .with_alpha(0.1);
+    sac.policy_weights_mut()
+        .iter_mut()
+        .for_each(|w| *w = rand::random_range(-1e-1f32..=1e-1));
+    for c in 0..2 {
+        sac.critic_weights_mut(c)
+            .iter_mut()
+            .for_each(|w| *w = rand::random_range(-1e-1f32..=1e-1));
+    }
+    sac.sync_targets();
+    // NOTE: the best action is x / 2, with a reward of 0
+    let reward = |x: f32, a: f32| -(a - x / 2.0).powi(2);
+
+    let mut action = [0.0];
+    let mut total = 0.0;
+    for i in 1..=6000 {
+        let x = rand::random_range(-1f32..=1.0);
+        sac.act(&[x], &mut action);
+        sac.store(&[x], &action, reward(x, action[0]), &[0.0], true);
+        sac.update();
+        sac.deterministic_action(&[x], &mut action);
+        total += reward(x, action[0]);
+        if i % 1000 == 0 {
+            println!(
+                "{i:>4}: alpha = {:.2e}, mean deterministic reward = {:.2e}",
+                sac.alpha(),
+                total / 1000.0
+            );
+            total = 0.0;
+        }
+    }
+}
+
+#[cfg(test)]
+mod advantage {
+    use crate::training::{
+        Eval, Value,
+        activations::id::Id,
+        advantage::{self, Gae},
+        least_squar_value::LeastSquareValue,
+        mlp::MLP,
+    };
+
+    // NOTE: the episode ends after the second step and the last one is truncated with a
+    // bootstrap value of 10
+    const REWARDS: [f64; 4] = [1.0, 2.0, 3.0, 4.0];
+    const DONES: [bool; 4] = [false, true, false, false];
+    const VALUES: [f64; 4] = [0.5, 1.0, 2.0, 4.0];
+    const BOOTSTRAP: f64 = 10.0;
+
+    fn assert_close(a: &[f64], b: &[f64]) {
+        assert!(
+            a.iter().zip(b).all(|(a, b)| (a - b).abs() < 1e-12),
+            "{a:?} != {b:?}"
+        );
+    }
+
+    #[test]
+    fn returns() {
+        let mut returns = [0.0; 4];
+        advantage::discounted_returns(&REWARDS, &DONES, BOOTSTRAP, 0.5, &mut returns);
+        assert_close(&returns, &[2.0, 2.0, 7.5, 9.0]);
+
+        advantage::n_step_returns(&REWARDS, &DONES, &VALUES, BOOTSTRAP, 0.5, 1, &mut returns);
+        assert_close(&returns, &[1.5, 2.0, 5.0, 9.0]);
+        advantage::n_step_returns(&REWARDS, &DONES, &VALUES, BOOTSTRAP, 0.5, 2, &mut returns);
+        assert_close(&returns, &[2.0, 2.0, 7.5, 9.0]);
+    }
+
+    #[test]
+    fn normalize() {
+        let mut advantages = [1.0, 2.0, 3.0, 4.0];
+        advantage::normalize(&mut advantages);
+        let std = 1.25f64.sqrt();
+        assert_close(&advantages, &[-1.5 / std, -0.5 / std, 0.5 / std, 1.5 / std]);
+    }
+
+    #[test]
+    fn gae() {
+        let mut advantages = [0.0; 4];
+        Gae::new(0.5, 0.5).advantages(&REWARDS, &DONES, &VALUES, BOOTSTRAP, &mut advantages);
+        assert_close(&advantages, &[1.25, 1.0, 4.25, 5.0]);
+        // NOTE: GAE(1) is the discounted return minus the value
+        Gae::new(0.5, 1.0).advantages(&REWARDS, &DONES, &VALUES, BOOTSTRAP, &mut advantages);
+        assert_close(&advantages, &[1.5, 1.0, 5.5, 5.0]);
+
+        let mut value = LeastSquareValue::new(MLP::new(1, vec![Id::layer(1)]));
+        let state_len = value.state_len();
+        let mut states = vec![0.0; 4 * state_len];
+        states
+            .chunks_mut(state_len)
+            .zip(VALUES)
+            .for_each(|(state, v)| value.output_mut(state)[0] = v);
+        Gae::new(0.5, 0.5).with_normalization(true).estimate(
+            &mut value,
+            &mut states,
+            &REWARDS,
+            &DONES,
+            BOOTSTRAP,
+            &mut advantages,
+        );
+        let targets = states
+            .chunks(state_len)
+            .map(|state| value.target(state))
+            .collect::<Vec<_>>();
+        assert_close(&targets, &[1.75, 2.0, 6.25, 9.0]);
+        let mut normalized = [1.25, 1.0, 4.25, 5.0];
+        advantage::normalize(&mut normalized);
+        assert_close(&advantages, &normalized);
+    }
+}
+
+#[cfg(test)]
+mod gradcheck {
+    use rand::{Rng, SeedableRng, rngs::StdRng};
+
+    use crate::training::{
+        BackProp, Eval, Gradient, StochasticPolicy, Value, Weights,
+        activations::{id::Id, relu::ReLu, tanh::Tanh},
+        dqn::TdLoss,
+        gradcheck::GradCheck,
+        layer_matrix::LayerMatrix,
+        least_squar_value::LeastSquareValue,
+        mlp::MLP,
+        model_based::DynamicsLoss,
+        ops::{
+            add::add, concat::concat, mul::mul, parallel::parallel, sequential::sequential,
+            sub::sub,
+        },
+        policies::{
+            beta_policy::BetaPolicy, categorical_policy::CategoricalPolicy,
+            normal_policy::NormalPolicy, squashed_normal_policy::SquashedNormalPolicy,
+        },
+        ppo::ClippedSurrogate,
+        sac::SoftActorObjective,
+        td3::ActorObjective,
+    };
+    /// Seeded so that a failure at a ReLU kink reproduces instead of showing up at random
+    fn rng() -> StdRng {
+        StdRng::seed_from_u64(0)
+    }
+    fn random_vec(rng: &mut impl Rng, len: usize) -> Vec<f64> {
+        (0..len).map(|_| rng.random_range(-1f64..=1.0)).collect()
+    }
+    fn check_back_prop<B: BackProp<f64>>(name: &str, component: &B) {
+        let rng = &mut rng();
+        let input = random_vec(rng, component.input_len());
+        let weights = random_vec(rng, component.weights_len());
+        let output_gradient = random_vec(rng, component.output_len());
+        let report = GradCheck::new().back_prop(component, &input, &weights, &output_gradient);
+        assert!(report.is_ok(), "{name}: {report}");
+
+        // NOTE: skipping the gradient with respect to the weights must not change the input one
+        let mut state = component.empty_state();
+        component.eval(&input, &weights, &mut state);
+        let len = component.back_front_len();
+        let (mut front, mut back) = (vec![0.0; len], vec![0.0; len]);
+        back[..output_gradient.len()].copy_from_slice(&output_gradient);
+        let mut gradient = component.empty_weights();
+        component.back_prop(
+            &input,
+            &weights,
+            &state,
+            &mut front,
+            &mut back,
+            &mut gradient,
+        );
+        let expected = front[..input.len()].to_vec();
+        back[..output_gradient.len()].copy_from_slice(&output_gradient);
+        let input_gradient =
+            component.input_gradient(&input, &weights, &state, &mut front, &mut back);
+        assert_eq!(input_gradient, &expected[..], "{name} input gradient");
+    }
+    fn mlp(inputs: usize, outputs: usize) -> MLP<f64> {
+        MLP::new(
+            inputs,
+            vec![
+                Tanh::layer(6),
+                ReLu::layer(5),
+                Tanh::layer(4),
+                Id::layer(outputs),
+            ],
+        )
+    }
+
+    #[test]
+    fn gradcheck_layer_matrix() {
+        check_back_prop("LayerMatrix", &LayerMatrix::new(3, 4));
+    }
+
+    #[test]
+    fn gradcheck_activations() {
+        check_back_prop("Id", &Id::new(4));
+        check_back_prop("ReLu", &ReLu::new(4));
+        check_back_prop("Tanh", &Tanh::new(4));
+    }
+
+    #[test]
+    fn gradcheck_mlp() {
+        let rng = &mut rng();
+        let mlp = mlp(3, 2);
+        let report = GradCheck::new()
+            .back_prop(
+                &mlp,
+                &random_vec(rng, 3),
+                &random_vec(rng, mlp.weights_len()),
+                &random_vec(rng, 2),
+            )
+            .with_layer(|i| mlp.layer_of_weight(i));
+        assert!(report.is_ok(), "MLP: {report}");
+        check_back_prop("MLP", &mlp);
+    }
+
+    #[test]
+    fn gradcheck_combinators() {
+        check_back_prop("Add", &add(mlp(3, 4), mlp(2, 4)));
+        check_back_prop("Add broadcast", &add(mlp(3, 1), mlp(2, 4)));
+        check_back_prop("Concat", &concat(mlp(3, 4), mlp(2, 1)));
+        check_back_prop("Parallel", &parallel(mlp(3, 4), LayerMatrix::new(3, 2)));
+        check_back_prop("Sequential", &sequential(mlp(3, 4), mlp(4, 2)));
+    }
+
+    fn value(inputs: usize) -> LeastSquareValue<f64> {
+        LeastSquareValue::new(mlp(inputs, 1))
+    }
+
+    #[test]
+    fn gradcheck_least_square_value() {
+        let rng = &mut rng();
+        let mut value = value(3);
+        let mut state = value.empty_state();
+        value.set_target(0.5, &mut state);
+        let weights = random_vec(rng, value.weights_len());
+        let report = GradCheck::new()
+            .gradient(&value, &random_vec(rng, 3), &weights, &state)
+            .with_layer(|i| value.mlp().layer_of_weight(i));
+        assert!(report.is_ok(), "LeastSquareValue: {report}");
+    }
+
+    #[test]
+    fn gradcheck_td_loss() {
+        let rng = &mut rng();
+        let input = random_vec(rng, 3);
+        for delta in [10.0, 0.1] {
+            let loss = TdLoss::new(mlp(3, 4)).with_delta(delta);
+            let weights = random_vec(rng, loss.weights_len());
+            let mut state = loss.empty_state();
+            loss.set_action(2, &mut state);
+            loss.set_target(0.7, &mut state);
+            loss.set_weight(0.6, &mut state);
+            let report = GradCheck::new()
+                .gradient(&loss, &input, &weights, &state)
+                .with_layer(|i| loss.mlp().layer_of_weight(i));
+            assert!(report.is_ok(), "TdLoss: {report}");
+        }
+    }
+
+    #[test]
+    fn gradcheck_dynamics_loss() {
+        let rng = &mut rng();
+        let loss = DynamicsLoss::new(mlp(3, 3));
+        let weights = random_vec(rng, loss.weights_len());
+        let mut state = loss.empty_state();
+        loss.set_target(&random_vec(rng, 3), &mut state);
+        let report = GradCheck::new()
+            .gradient(&loss, &random_vec(rng, 3), &weights, &state)
+            .with_layer(|i| loss.mlp().layer_of_weight(i));
+        assert!(report.is_ok(), "DynamicsLoss: {report}");
+    }
+
+    #[test]
+    fn gradcheck_actor_objective() {
+        let rng = &mut rng();
+        let mut objective = ActorObjective::new(mlp(3, 2), value(5));
+        objective.set_critic_weights(&random_vec(rng, objective.critic().weights_len()));
+        let weights = random_vec(rng, objective.weights_len());
+        let state = objective.empty_state();
+        let report = GradCheck::new()
+            .gradient(&objective, &random_vec(rng, 3), &weights, &state)
+            .with_layer(|i| objective.actor().layer_of_weight(i));
+        assert!(report.is_ok(), "ActorObjective: {report}");
+    }
+
+    #[test]
+    fn gradcheck_soft_actor_objective() {
+        let rng = &mut rng();
+        let policies = [
+            NormalPolicy::new(mlp(3, 2), 0.5).with_global_log_std(),
+            NormalPolicy::new(mlp(3, 4), 0.5).with_state_dependent_log_std(),
+        ];
+        for policy in policies {
+            let policy = SquashedNormalPolicy::new(policy, -2.0, 1.0);
+            let mut objective = SoftActorObjective::new(policy, [value(5), value(5)]);
+            for j in 0..2 {
+                objective
+                    .set_critic_weights(j, &random_vec(rng, objective.critics()[j].weights_len()));
+            }
+            objective.set_alpha(0.3);
+            let weights = random_vec(rng, objective.weights_len());
+            let mut state = objective.empty_state();
+            objective.sample_noise(&mut state, rng);
+            let report =
+                GradCheck::new().gradient(&objective, &random_vec(rng, 3), &weights, &state);
+            assert!(report.is_ok(), "SoftActorObjective: {report}");
+        }
+    }
+
+    #[test]
+    fn gradcheck_normal_policy() {
+        let rng = &mut rng();
+        let policy = NormalPolicy::new(mlp(3, 2), 0.5);
+        let input = random_vec(rng, 3);
+        let weights = random_vec(rng, policy.weights_len());
+        let mut state = policy.empty_state();
+        policy.stochastic_eval(&input, &weights, &mut state, rng);
+        let report = GradCheck::new()
+            .gradient(&policy, &input, &weights, &state)
+            .with_layer(|i| policy.mlp().layer_of_weight(i));
+        assert!(report.is_ok(), "NormalPolicy: {report}");
+    }
+
+    #[test]
+    fn gradcheck_learned_std() {
+        let rng = &mut rng();
+        let policies = [
+            NormalPolicy::new(mlp(3, 2), 0.5).with_global_log_std(),
+            NormalPolicy::new(mlp(3, 4), 0.5).with_state_dependent_log_std(),
+        ];
+        for policy in policies {
+            let input = random_vec(rng, 3);
+            let weights = random_vec(rng, policy.weights_len());
+            let mut state = policy.empty_state();
+            policy.stochastic_eval(&input, &weights, &mut state, rng);
+            let report = GradCheck::new()
+                .gradient(&policy, &input, &weights, &state)
+                .with_layer(|i| policy.mlp().layer_of_weight(i));
+            assert!(report.is_ok(), "NormalPolicy: {report}");
+
+            let surrogate = ClippedSurrogate::new(policy).with_entropy_coefficient(0.1);
+            let mut state = surrogate.empty_state();
+            let policy_state = surrogate.policy_state_mut(&mut state);
+            surrogate
+                .policy()
+                .stochastic_eval(&input, &weights, policy_state, rng);
+            let probability = surrogate.policy().probability(policy_state);
+            surrogate.set_old_probability(probability * 1.05, &mut state);
+            surrogate.set_advantage(-0.7, &mut state);
+            let report = GradCheck::new().gradient(&surrogate, &input, &weights, &state);
+            assert!(report.is_ok(), "ClippedSurrogate: {report}");
+        }
+    }
+
+    #[test]
+    fn gradcheck_squashed_normal_policy() {
+        let rng = &mut rng();
+        let policy = SquashedNormalPolicy::new(
+            NormalPolicy::new(mlp(3, 2), 0.5).with_global_log_std(),
+            -2.0,
+            1.0,
+        );
+        let input = random_vec(rng, 3);
+        let weights = random_vec(rng, policy.weights_len());
+        let mut state = policy.empty_state();
+        policy.stochastic_eval(&input, &weights, &mut state, rng);
+        assert!(
+            policy
+                .stochastic_output(&state)
+                .iter()
+                .all(|a| (-2.0..=1.0).contains(a))
+        );
+        let report = GradCheck::new().gradient(&policy, &input, &weights, &state);
+        assert!(report.is_ok(), "SquashedNormalPolicy: {report}");
+
+        let surrogate = ClippedSurrogate::new(policy).with_entropy_coefficient(0.1);
+        let mut state = surrogate.empty_state();
+        let policy_state = surrogate.policy_state_mut(&mut state);
+        surrogate
+            .policy()
+            .stochastic_eval(&input, &weights, policy_state, rng);
+        let probability = surrogate.policy().probability(policy_state);
+        surrogate.set_old_probability(probability * 0.95, &mut state);
+        surrogate.set_advantage(0.8, &mut state);
+        let report = GradCheck::new().gradient(&surrogate, &input, &weights, &state);
+        assert!(report.is_ok(), "ClippedSurrogate: {report}");
+    }
+
+    #[test]
+    fn gradcheck_beta_policy() {
+        let rng = &mut rng();
+        let policy = BetaPolicy::new(mlp(3, 4));
+        assert_eq!(policy.output_range(), (Some(0.0), Some(1.0)));
+        let policy = policy.with_bounds(-1.0, 3.0);
+        let input = random_vec(rng, 3);
+        let weights = random_vec(rng, policy.weights_len());
+        let mut state = policy.empty_state();
+        policy.stochastic_eval(&input, &weights, &mut state, rng);
+        assert!(
+            policy
+                .stochastic_output(&state)
+                .iter()
+                .all(|a| (-1.0..=3.0).contains(a))
+        );
+        let report = GradCheck::new()
+            .gradient(&policy, &input, &weights, &state)
+            .with_layer(|i| policy.mlp().layer_of_weight(i));
+        assert!(report.is_ok(), "BetaPolicy: {report}");
+
+        let surrogate = ClippedSurrogate::new(policy).with_entropy_coefficient(0.1);
+        let mut state = surrogate.empty_state();
+        let policy_state = surrogate.policy_state_mut(&mut state);
+        surrogate
+            .policy()
+            .stochastic_eval(&input, &weights, policy_state, rng);
+        let probability = surrogate.policy().probability(policy_state);
+        surrogate.set_old_probability(probability * 1.05, &mut state);
+        surrogate.set_advantage(1.3, &mut state);
+        let report = GradCheck::new().gradient(&surrogate, &input, &weights, &state);
+        assert!(report.is_ok(), "ClippedSurrogate: {report}");
+    }
+
+    #[test]
+    fn gradcheck_clipped_surrogate() {
+        let rng = &mut rng();
+        let surrogate =
+            ClippedSurrogate::new(NormalPolicy::new(mlp(3, 2), 0.5)).with_entropy_coefficient(0.1);
+        let input = random_vec(rng, 3);
+        let weights = random_vec(rng, surrogate.weights_len());
+        let mut state = surrogate.empty_state();
+        let policy_state = surrogate.policy_state_mut(&mut state);
+        surrogate
+            .policy()
+            .stochastic_eval(&input, &weights, policy_state, rng);
+        let probability = surrogate.policy().probability(policy_state);
+        surrogate.set_old_probability(probability * 1.05, &mut state);
+        surrogate.set_advantage(1.3, &mut state);
+        let report = GradCheck::new().gradient(&surrogate, &input, &weights, &state);
+        assert!(report.is_ok(), "ClippedSurrogate: {report}");
+    }
+
+    #[test]
+    fn gradcheck_sub_mul() {
+        let rng = &mut rng();
+        let (weights_len, state_len) = (value(3).weights_len(), value(3).state_len());
+        let mut weights = random_vec(rng, weights_len);
+        weights.extend_from_within(..);
+        let input = random_vec(rng, 6);
+        let check = GradCheck::new().with_tied_weights(weights_len);
+        let set_targets = |state: &mut [f64]| {
+            state[state_len - 1] = 0.5;
+            state[2 * state_len - 1] = -0.3;
+        };
+
+        let op = sub(value(3), value(3));
+        let mut state = op.empty_state();
+        set_targets(&mut state);
+        let report = check.gradient(&op, &input, &weights, &state);
+        assert!(report.is_ok(), "Sub: {report}");
+
+        let op = mul(mlp(3, 1), mlp(3, 1));
+        let state = op.empty_state();
+        let report = check.gradient(&op, &input, &weights, &state);
+        assert!(report.is_ok(), "Mul: {report}");
+
+        // NOTE: a vector minus or times a scalar, whose weights are independent
+        let (a, b) = (DynamicsLoss::new(mlp(3, 3)), value(3));
+        let op = sub(DynamicsLoss::new(mlp(3, 3)), value(3));
+        let weights = random_vec(rng, op.weights_len());
+        let mut state = op.empty_state();
+        let (state_a, state_b) = state.split_at_mut(a.state_len());
+        a.set_target(&random_vec(rng, 3), state_a);
+        state_b[b.state_len() - 1] = 0.5;
+        let report = GradCheck::new().gradient(&op, &input, &weights, &state);
+        assert!(report.is_ok(), "Sub vector scalar: {report}");
+
+        let op = mul(mlp(3, 3), mlp(3, 1));
+        let weights = random_vec(rng, op.weights_len());
+        let mut state = op.empty_state();
+        let report = GradCheck::new().gradient(&op, &input, &weights, &state);
+        assert!(report.is_ok(), "Mul vector scalar: {report}");
+        check_back_prop("Mul vector scalar", &op);
+
+        op.eval(&input, &weights, &mut state);
+        let (a, b) = (mlp(3, 3), mlp(3, 1));
+        let (mut state_a, mut state_b) = (a.empty_state(), b.empty_state());
+        a.eval(&input[..3], &weights[..a.weights_len()], &mut state_a);
+        b.eval(&input[3..], &weights[a.weights_len()..], &mut state_b);
+        let output_b = b.output(&state_b)[0];
+        op.output(&state)
+            .iter()
+            .zip(a.output(&state_a))
+            .for_each(|(o, a)| assert_eq!(*o, a * output_b));
+        assert_eq!(op.objective(&state), op.output(&state).iter().sum::<f64>());
+    }
+
+    #[test]
+    fn gradcheck_mul_untied() {
+        let rng = &mut rng();
+        let input = random_vec(rng, 5);
+        let cases = [
+            ("Mul untied", mul(mlp(3, 3), mlp(2, 3))),
+            ("Mul scalar vector", mul(mlp(3, 1), mlp(2, 3))),
+            ("Mul vector scalar", mul(mlp(3, 3), mlp(2, 1))),
+        ];
+        for (name, op) in cases {
+            let weights = random_vec(rng, op.weights_len());
+            let state = op.empty_state();
+            let report = GradCheck::new().gradient(&op, &input, &weights, &state);
+            assert!(report.is_ok(), "{name}: {report}");
+            check_back_prop(name, &op);
+        }
+
+        let (a, b) = (mlp(3, 1), mlp(2, 3));
+        let op = mul(mlp(3, 1), mlp(2, 3));
+        let weights = random_vec(rng, op.weights_len());
+        let mut state = op.empty_state();
+        op.eval(&input, &weights, &mut state);
+        let (mut state_a, mut state_b) = (a.empty_state(), b.empty_state());
+        a.eval(&input[..3], &weights[..a.weights_len()], &mut state_a);
+        b.eval(&input[3..], &weights[a.weights_len()..], &mut state_b);
+        let output_a = a.output(&state_a)[0];
+        op.output(&state)
+            .iter()
+            .zip(b.output(&state_b))
+            .for_each(|(o, b)| assert_eq!(*o, output_a * b));
+    }
+
+    #[test]
+    fn gradcheck_sub_broadcast() {
+        let rng = &mut rng();
+        let (a, b) = (value(3), DynamicsLoss::new(mlp(2, 3)));
+        let op = sub(value(3), DynamicsLoss::new(mlp(2, 3)));
+        let weights = random_vec(rng, op.weights_len());
+        let input = random_vec(rng, 5);
+        let mut state = op.empty_state();
+        let (state_a, state_b) = state.split_at_mut(a.state_len());
+        state_a[a.state_len() - 1] = 0.5;
+        b.set_target(&random_vec(rng, 3), &mut state_b[..b.state_len()]);
+        let report = GradCheck::new().gradient(&op, &input, &weights, &state);
+        assert!(report.is_ok(), "Sub broadcast: {report}");
+
+        op.eval(&input, &weights, &mut state);
+        let (state_a, state_b) = state.split_at(a.state_len());
+        let output_a = a.output(state_a)[0];
+        let output_b = b.output(&state_b[..b.state_len()]);
+        op.output(&state)
+            .iter()
+            .zip(output_b)
+            .for_each(|(o, b)| assert_eq!(*o, output_a - b));
+    }
+
+    #[test]
+    fn gradcheck_categorical_policy() {
+        let rng = &mut rng();
+        let policy = CategoricalPolicy::new(mlp(3, 4)).with_temperature(0.7);
+        let input = random_vec(rng, 3);
+        let weights = random_vec(rng, policy.weights_len());
+        let mut state = policy.empty_state();
+        policy.stochastic_eval(&input, &weights, &mut state, rng);
+        let report = GradCheck::new()
+            .gradient(&policy, &input, &weights, &state)
+            .with_layer(|i| policy.mlp().layer_of_weight(i));
+        assert!(report.is_ok(), "CategoricalPolicy: {report}");
+
+        let surrogate = ClippedSurrogate::new(policy).with_entropy_coefficient(0.1);
+        let mut state = surrogate.empty_state();
+        let policy_state = surrogate.policy_state_mut(&mut state);
+        surrogate
+            .policy()
+            .stochastic_eval(&input, &weights, policy_state, rng);
+        let probability = surrogate.policy().probability(policy_state);
+        surrogate.set_old_probability(probability * 0.95, &mut state);
+        surrogate.set_advantage(-0.8, &mut state);
+        let report = GradCheck::new().gradient(&surrogate, &input, &weights, &state);
+        assert!(
+            report.is_ok(),
+            "ClippedSurrogate<CategoricalPolicy>: {report}"
+        );
+    }
+}
+
+#[cfg(test)]
+mod rollout {
+    use crate::training::{
+        TimeSteps,
+        env::{Env, RolloutBuffer, Step},
+    };
+
+    /// Counts the steps of the episode, which is truncated after `len` steps or terminated by a negative action
+    struct Counter {
+        len: usize,
+        t: usize,
+        resets: usize,
+    }
+    impl Env<f64> for Counter {
+        fn observation_len(&self) -> usize {
+            1
+        }
+        fn action_len(&self) -> usize {
+            1
+        }
+        fn reset(&mut self, observation: &mut [f64]) {
+            self.t = 0;
+            self.resets += 1;
+            observation[0] = 0.0;
+        }
+        fn step(&mut self, action: &[f64], observation: &mut [f64]) -> Step<f64> {
+            self.t += 1;
+            observation[0] = self.t as f64;
+            Step {
+                reward: 1.0,
+                terminated: action[0] < 0.0,
+                truncated: self.t == self.len,
+            }
+        }
+    }
+
+    #[test]
+    fn rollout_buffer() {
+        let mut envs = [3, 4].map(|len| Counter {
+            len,
+            t: 0,
+            resets: 0,
+        });
+        let mut buffer = RolloutBuffer::new(2, 5, 1, 1, 1);
+        buffer.collect(&mut envs, |input, state, action| {
+            state[0] = input[0];
+            action[0] = if input[0] == 1.0 { -1.0 } else { 1.0 };
+        });
+        // NOTE: the action terminates every episode at its second step
+        assert_eq!(
+            buffer.dones(),
+            [
+                false, true, false, true, false, false, true, false, true, false
+            ]
+        );
+        assert!(buffer.truncated().iter().all(|t| !t));
+        assert_eq!(buffer.states(), [0., 1., 0., 1., 0., 0., 1., 0., 1., 0.]);
+        assert_eq!(buffer.last_observation(0), [1.0]);
+        assert_eq!(envs[0].resets, 3);
+
+        buffer.collect(&mut envs, |input, state, action| {
+            state[0] = input[0];
+            action[0] = 1.0;
+        });
+        // NOTE: the episodes continue from the previous collect
+        assert_eq!(buffer.states(), [1., 2., 0., 1., 2., 1., 2., 3., 0., 1.]);
+        assert_eq!(
+            buffer.truncated(),
+            [
+                false, true, false, false, true, false, false, true, false, false
+            ]
+        );
+        buffer.bootstrap_truncated(0.5, |observation| observation[0]);
+        assert_eq!(buffer.rewards(), [1., 2.5, 1., 1., 2.5, 1., 1., 3., 1., 1.]);
+        assert_eq!(buffer.time_steps().len(), 10);
+    }
+
+    #[test]
+    fn episodes() {
+        let mut envs = [3, 10].map(|len| Counter {
+            len,
+            t: 0,
+            resets: 0,
+        });
+        let mut buffer = RolloutBuffer::new(2, 5, 1, 1, 1);
+        for (e, env) in envs.iter_mut().enumerate() {
+            buffer.collect_episode(e, env, |input, state, action| {
+                state[0] = input[0];
+                action[0] = 1.0;
+            });
+        }
+        assert_eq!((buffer.recorded_len(0), buffer.recorded_len(1)), (3, 5));
+        assert_eq!(buffer.env_steps(1), 5..10);
+        assert_eq!(buffer.recorded_steps(), 8);
+        assert_eq!(buffer.mean_reward(), 1.0);
+        assert_eq!(buffer.truncated()[..5], [false, false, true, false, false]);
+        assert_eq!(buffer.last_observation(1), [5.0]);
+        let time_steps = buffer.time_steps();
+        assert_eq!(time_steps.len(), 8);
+        assert_eq!(time_steps.get(2).1, [2.0]);
+        assert_eq!(time_steps.get(3).1, [0.0]);
+        assert_eq!(time_steps.get(7).1, [4.0]);
+    }
+}
+
+#[cfg(test)]
+mod replay {
+    use crate::training::{
+        TimeSteps,
+        replay::{Batch, ReplayBuffer, SumTree},
+    };
+
+    #[test]
+    fn sum_tree() {
+        let mut tree = SumTree::new(5);
+        [1.0, 0.0, 2.0, 3.0, 4.0]
+            .iter()
+            .enumerate()
+            .for_each(|(i, &p)| tree.set(i, p));
+        assert_eq!(tree.total(), 10.0);
+        assert_eq!(tree.find(0.5), 0);
+        assert_eq!(tree.find(1.0), 2);
+        assert_eq!(tree.find(5.5), 3);
+        assert_eq!(tree.find(9.9), 4);
+    }
+
+    #[test]
+    fn prioritized_replay() {
+        let mut replay = ReplayBuffer::new(4, 1, 1).with_prioritization(1.0, 1.0);
+        for i in 0..6 {
+            replay.push(&[i as f64], &[0.0], 0.0, &[i as f64 + 1.0], false);
+        }
+        // NOTE: the ring buffer now holds the observations 4, 5, 2, 3
+        assert_eq!(replay.len(), 4);
+        assert_eq!(replay.get(0).observation, [4.0]);
+        replay.update_priorities(&[0, 1, 2, 3], &[0.0, 0.0, 0.0, 1.0]);
+
+        let mut batch = Batch::new(8, 1, 1);
+        replay.sample(
+            &mut batch,
+            &mut rand::rng(),
+            |transition, weight, input, state| {
+                input.copy_from_slice(transition.observation);
+                state[0] = weight;
+            },
+        );
+        assert!(batch.indices().iter().all(|&i| i == 3));
+        assert!(batch.inputs().iter().all(|&o| o == 3.0));
+        assert!(batch.weights().iter().all(|&w| w == 1.0));
+        assert_eq!(batch.time_steps().len(), 8);
+    }
+}
+
+#[cfg(test)]
+mod es {
+    use crate::training::{
+        Eval, Gradient, Value, Weights,
+        activations::{id::Id, tanh::Tanh},
+        least_squar_value::LeastSquareValue,
+        mlp::MLP,
+        optimizers::{
+            adam::Adam,
+            es::{Es, SeparableCmaEs, rank_shaping},
+        },
+    };
+
+    #[test]
+    fn centered_ranks() {
+        let mut order = [0; 5];
+        let mut shaped = [0.0; 5];
+        rank_shaping(&[10.0, -3.0, 100.0, 0.0, 7.0], &mut order, &mut shaped);
+        assert_eq!(shaped, [0.25, -0.5, 0.5, -0.25, 0.0]);
+    }
+
+    /// A NaN fitness ranks last rather than panicking in the sort, and is left out of the raw
+    /// estimate rather than turning the weights into NaN.
+    #[test]
+    fn nan_fitness() {
+        let mut order = [0; 5];
+        let mut shaped = [0.0; 5];
+        rank_shaping(&[1.0, f64::NAN, -2.0, 3.0, 0.0], &mut order, &mut shaped);
+        assert_eq!(shaped, [0.25, -0.5, -0.25, 0.5, 0.0]);
+
+        let mut cma = SeparableCmaEs::new(&[0.0; 3], 0.5);
+        for _ in 0..50 {
+            cma.step(|w| {
+                if w[0] > 0.0 {
+                    f64::NAN
+                } else {
+                    -w.iter().map(|x| (x + 1.0).powi(2)).sum::<f64>()
+                }
+            });
+        }
+        let (best, best_fitness) = cma.best();
+        assert!(best_fitness.is_finite() && best[0] <= 0.0, "{best:?}");
+        assert!(cma.mean().iter().all(|m| m.is_finite()));
+
+        let mut es = Es::new(3, 8)
+            .with_sigma(0.5)
+            .with_rank_shaping(false)
+            .with_seed(0);
+        let mut adam = Adam::new(3).with_alpha(0.05);
+        let mut weights = [0.0; 3];
+        let mut nan = false;
+        for _ in 0..20 {
+            let mean = es.step(&mut adam, &mut weights, |w| {
+                if w[0] > 0.5 {
+                    f64::NAN
+                } else {
+                    -w.iter().map(|x| (x + 1.0).powi(2)).sum::<f64>()
+                }
+            });
+            assert!(mean.is_finite());
+            nan |= es.fitness().iter().any(|f| f.is_nan());
+        }
+        assert!(nan);
+        assert!(weights.iter().all(|w| w.is_finite()), "{weights:?}");
+    }
+
+    /// The raw ES estimate must agree with the hand-written back propagation.
+    #[test]
+    fn es_matches_back_prop() {
+        let mut value =
+            LeastSquareValue::<f64>::new(MLP::new(2, vec![Tanh::layer(4), Id::layer(1)]));
+        let input = [0.3, -0.7];
+        let weights: Vec<f64> = (0..value.weights_len())
+            .map(|_| rand::random_range(-1.0..=1.0))
+            .collect();
+        let mut state = value.empty_state();
+        value.set_target(2.0, &mut state);
+        value.eval(&input, &weights, &mut state);
+        let mut gradient = value.empty_weights();
+        value.compute_gradient(&input, &weights, &mut state, &mut gradient);
+
+        let mut es = Es::new(weights.len(), 4000)
+            .with_sigma(1e-4)
+            .with_rank_shaping(false);
+        let estimate = es.estimate_gradient(&weights, |w| {
+            value.eval(&input, w, &mut state);
+            -value.objective(&state)
+        });
+        let dot: f64 = estimate
+            .iter()
+            .zip(gradient.iter())
+            .map(|(e, g)| e * g)
+            .sum();
+        let norm = |v: &[f64]| v.iter().map(|x| x * x).sum::<f64>().sqrt();
+        let cosine = -dot / (norm(estimate) * norm(&gradient));
+        assert!(cosine > 0.9, "cosine similarity {cosine}");
+    }
+
+    #[test]
+    fn es_quadratic() {
+        let target = [1.0, -2.0, 0.5];
+        let fitness = |w: &[f64]| -> f64 {
+            -w.iter()
+                .zip(target.iter())
+                .map(|(w, t)| (w - t).powi(2))
+                .sum::<f64>()
+        };
+        let mut weights = [0.0; 3];
+        let mut es = Es::new(3, 20).with_sigma(0.1);
+        let mut adam = Adam::new(3).with_alpha(0.05);
+        for _ in 0..300 {
+            es.step(&mut adam, &mut weights, fitness);
+        }
+        assert!(fitness(&weights) > -1e-2, "{weights:?}");
+    }
+
+    /// Ill-conditioned ellipsoid, which needs the diagonal covariance to be adapted.
+    #[test]
+    fn cma_es_ellipsoid() {
+        let fitness = |w: &[f64]| -> f64 {
+            -w.iter()
+                .enumerate()
+                .map(|(i, x)| 1e4f64.powf(i as f64 / 9.0) * (x - 1.0).powi(2))
+                .sum::<f64>()
+        };
+        let mut cma = SeparableCmaEs::new(&[0.0; 10], 0.5);
+        for _ in 0..300 {
+            cma.step(fitness);
+        }
+        let (best, best_fitness) = cma.best();
+        assert!(best_fitness > -1e-8, "{best:?}: {best_fitness}");
+        // NOTE: the variances follow the inverse of the curvature
+        assert!(cma.variances()[0] > 100.0 * cma.variances()[9]);
+        assert!(-fitness(cma.mean()) < 1e-8);
+    }
+}
+
+#[cfg(test)]
+mod planning {
+    use crate::training::planning::Cem;
+
+    /// Model-predictive control of a double integrator brought back to the origin.
+    #[test]
+    fn cem_mpc() {
+        let dt = 0.1;
+        let dynamics = |state: &mut [f64], action: &[f64]| {
+            state[1] += dt * action[0];
+            state[0] += dt * state[1];
+            -(state[0] * state[0] + 0.1 * state[1] * state[1] + 0.01 * action[0] * action[0])
+        };
+        let mut cem = Cem::new(15, 1, 64, 8)
+            .with_iterations(4)
+            .with_bounds(-1.0, 1.0);
+        let mut state = [1.0, 0.0];
+        for _ in 0..100 {
+            let action = cem.plan_rollout(&state, dynamics)[0];
+            assert!((-1.0..=1.0).contains(&action));
+            dynamics(&mut state, &[action]);
+        }
+        assert!(state[0].abs() < 0.1 && state[1].abs() < 0.1, "{state:?}");
+    }
+
+    /// Sequences scored NaN are never elites.
+    #[test]
+    fn nan_score() {
+        let mut cem = Cem::new(5, 1, 32, 4).with_bounds(-1.0, 1.0);
+        let action = cem.plan(|actions: &[f64]| {
+            if actions[0] < 0.0 {
+                f64::NAN
+            } else {
+                -(actions[0] - 0.5).powi(2)
+            }
+        })[0];
+        assert!(action >= 0.0, "{action}");
+        assert!(cem.mean().iter().all(|m| m.is_finite()));
+    }
+}
+
+#[cfg(test)]
+mod model_based {
+    use crate::training::{
+        activations::{id::Id, tanh::Tanh},
+        env::Env,
+        mlp::MLP,
+        model_based::{DynamicsEnsemble, DynamicsLoss, ModelEnv},
+        optimizers::adam::Adam,
+        planning::Cem,
+        replay::ReplayBuffer,
+    };
+
+    /// $s' = s + a / 5$ with the reward $-s^2$
+    fn step(state: &mut [f64], action: &[f64]) -> f64 {
+        let reward = -state[0] * state[0];
+        state[0] += 0.2 * action[0];
+        reward
+    }
+
+    #[test]
+    fn dynamics_ensemble() {
+        let mut replay = ReplayBuffer::new(2000, 1, 1);
+        for _ in 0..2000 {
+            let observation = [rand::random_range(-1.0..=1.0)];
+            let action = [rand::random_range(-1.0..=1.0)];
+            let mut next = observation;
+            let reward = step(&mut next, &action);
+            replay.push(&observation, &action, reward, &next, false);
+        }
+        let loss = DynamicsLoss::new(MLP::new(2, vec![Tanh::layer(16), Id::layer(2)]));
+        let mut ensemble =
+            DynamicsEnsemble::new(loss, 4, |len| Adam::new(len).with_alpha(1e-2), 32);
+        for member in 0..ensemble.members() {
+            ensemble
+                .weights_mut(member)
+                .iter_mut()
+                .for_each(|w| *w = rand::random_range(-0.5..=0.5));
+        }
+        let error = ensemble.train(&replay, 1500);
+        assert!(error < 1e-3, "dynamics loss {error}");
+
+        let mut next = [0.0];
+        let (reward, near) = ensemble.predict_mean(&[0.5], &[-0.5], &mut next);
+        assert!((next[0] - 0.4).abs() < 0.05 && (reward + 0.25).abs() < 0.05);
+        let (_, far) = ensemble.predict_mean(&[8.0], &[-0.5], &mut next);
+        assert!(far > 10.0 * near, "disagreement {near} near and {far} far");
+
+        {
+            let mut env = ModelEnv::new(&ensemble, &replay, 3).with_penalty(1.0);
+            let mut observation = [0.0];
+            env.reset(&mut observation);
+            assert!((-1.0..=1.0).contains(&observation[0]));
+            for t in 1..=3 {
+                assert_eq!(env.step(&[0.0], &mut observation).truncated, t == 3);
+            }
+        }
+
+        // NOTE: model-predictive control with the learned model on the real system
+        let mut cem = Cem::new(10, 1, 64, 8).with_bounds(-1.0, 1.0);
+        let mut state = [1.0];
+        for _ in 0..30 {
+            let action = ensemble.plan(&mut cem, &state, 1.0)[0];
+            step(&mut state, &[action]);
+        }
+        assert!(state[0].abs() < 0.1, "{state:?}");
+    }
+}
+
+#[cfg(test)]
+mod init {
+    use crate::training::{
+        Weights,
+        activations::{id::Id, tanh::Tanh},
+        init::Init,
+        layer_matrix::LayerMatrix,
+        mlp::MLP,
+    };
+
+    fn dot(weights: &[f64], inputs: usize, a: usize, b: usize) -> f64 {
+        let row = |k: usize| &weights[k * (inputs + 1) + 1..][..inputs];
+        row(a).iter().zip(row(b).iter()).map(|(x, y)| x * y).sum()
+    }
+
+    #[test]
+    fn orthogonal() {
+        let mut rng = rand::rng();
+        for (inputs, outputs) in [(5, 3), (3, 5), (4, 4)] {
+            let layer = LayerMatrix::<f64>::new(inputs, outputs);
+            let mut weights = vec![1.0; layer.weights_len()];
+            layer.init_weights(&mut weights, Init::Orthogonal { gain: 2.0 }, &mut rng);
+            assert!(weights.chunks(inputs + 1).all(|row| row[0] == 0.0));
+            if outputs <= inputs {
+                for a in 0..outputs {
+                    for b in 0..outputs {
+                        let expected = if a == b { 4.0 } else { 0.0 };
+                        assert!((dot(&weights, inputs, a, b) - expected).abs() < 1e-10);
+                    }
+                }
+            } else {
+                // NOTE: the columns are orthogonal, so the rows have a squared norm of 4 on average
+                let trace: f64 = (0..outputs).map(|a| dot(&weights, inputs, a, a)).sum();
+                assert!((trace - 4.0 * inputs as f64).abs() < 1e-10);
+            }
+        }
+    }
+
+    #[test]
+    fn mlp_init() {
+        let mlp = MLP::<f64>::new(3, vec![Tanh::layer(100), Id::layer(2)]);
+        let mut weights = vec![1.0; mlp.weights_len()];
+        mlp.init_weights(&mut weights, Init::XavierUniform, &mut rand::rng());
+        let (first, last) = weights.split_at(4 * 100);
+        let bound = (6.0f64 / 103.0).sqrt();
+        assert!(first.iter().all(|w| w.abs() <= bound));
+        assert!(first.iter().any(|w| w.abs() > bound / 2.0));
+        let bound = (6.0f64 / 102.0).sqrt();
+        assert!(last.iter().all(|w| w.abs() <= bound));
+
+        let before = weights.clone();
+        mlp.scale_final_layer(&mut weights, 0.01);
+        assert_eq!(weights[..400], before[..400]);
+        assert!(
+            weights[400..]
+                .iter()
+                .zip(before[400..].iter())
+                .all(|(w, b)| *w == 0.01 * b)
+        );
+    }
+}
+
+#[cfg(test)]
+mod seed {
+    use rand::{Rng, SeedableRng, rngs::StdRng};
+
+    use crate::training::{
+        Weights,
+        activations::{id::Id, tanh::Tanh},
+        dqn::{Dqn, TdLoss},
+        least_squar_value::LeastSquareValue,
+        mlp::MLP,
+        optimizers::{adam::Adam, es::Es},
+        policies::normal_policy::NormalPolicy,
+        ppo::{ClippedSurrogate, Ppo},
+        replay::ReplayBuffer,
+    };
+
+    fn dqn_run(seed: u64) -> Vec<f64> {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let loss = TdLoss::new(MLP::new(1, vec![Tanh::layer(8), Id::layer(2)]));
+        let adam = Adam::new(loss.weights_len()).with_alpha(1e-3);
+        let replay = ReplayBuffer::new(256, 1, 1).with_prioritization(0.6, 0.4);
+        let mut dqn = Dqn::new(loss, adam, replay, 16)
+            .with_epsilon(1.0, 0.05, 100)
+            .with_seed(seed);
+        dqn.weights_mut()
+            .iter_mut()
+            .for_each(|w| *w = rng.random_range(-1e-1..=1e-1));
+        dqn.sync_target();
+        for _ in 0..200 {
+            let x = rng.random_range(-1.0..=1.0);
+            let action = dqn.act(&[x]);
+            let reward = if action == 1 { x } else { -x };
+            dqn.store(&[x], action, reward, &[0.0], true);
+            dqn.update();
+        }
+        dqn.weights().to_vec()
+    }
+
+    #[test]
+    fn dqn_is_reproducible() {
+        assert_eq!(dqn_run(7), dqn_run(7));
+        assert_ne!(dqn_run(7), dqn_run(8));
+    }
+
+    #[test]
+    fn es_is_reproducible() {
+        let run = |seed| {
+            let fitness = |w: &[f64]| -w.iter().map(|w| (w - 1.0).powi(2)).sum::<f64>();
+            let mut weights = [0.0; 3];
+            let mut es = Es::new(3, 8).with_seed(seed);
+            let mut adam = Adam::new(3).with_alpha(0.05);
+            for _ in 0..20 {
+                es.step(&mut adam, &mut weights, fitness);
+            }
+            weights
+        };
+        assert_eq!(run(3), run(3));
+        assert_ne!(run(3), run(4));
+    }
+
+    fn ppo_run(seed: u64) -> Vec<f64> {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let surrogate = ClippedSurrogate::new(NormalPolicy::new(
+            MLP::new(1, vec![Tanh::layer(8), Id::layer(1)]),
+            0.1,
+        ));
+        let value = LeastSquareValue::new(MLP::new(1, vec![Tanh::layer(8), Id::layer(1)]));
+        let policy_adam = Adam::new(surrogate.weights_len()).with_alpha(1e-3);
+        let value_adam = Adam::new(value.weights_len()).with_alpha(1e-3);
+        let mut ppo = Ppo::new(surrogate, value, policy_adam, value_adam, 16, 1)
+            .with_epochs(2)
+            .with_minibatch_size(8)
+            .with_seed(seed);
+        ppo.policy_weights_mut()
+            .iter_mut()
+            .for_each(|w| *w = rng.random_range(-1e-1..=1e-1));
+        for _ in 0..10 {
+            let mut ctx_list = (0..16)
+                .map(|_| rng.random_range(-1.0..=1.0))
+                .collect::<Vec<f64>>();
+            ppo.collect(
+                &mut ctx_list,
+                |&x, input| input[0] = x,
+                |&mut x, action| (-(x - action[0]).powi(2), true),
+            );
+            ppo.update();
+        }
+        ppo.policy_weights().to_vec()
+    }
+
+    #[test]
+    fn ppo_is_reproducible() {
+        assert_eq!(ppo_run(7), ppo_run(7));
+        assert_ne!(ppo_run(7), ppo_run(8));
+    }
+}
+
+#[cfg(test)]
+mod acceleration {
+    use crate::{
+        network::reinforcement::Reinforce,
+        simulation::{
+            Simulation, UpadeParameter,
+            acceleration::{Acceleration, Param},
+        },
+    };
+
+    /// The weights and the exploration noise of `Reinforcement` only depend on the seed parameter.
+    #[test]
+    fn acceleration_is_reproducible() {
+        let run = |seed| {
+            let mut acceleration = Acceleration::new();
+            acceleration.update_parameter(UpadeParameter::Integer {
+                tag: Param::Seed,
+                value: seed,
+            });
+            acceleration.reset();
+            for _ in 0..3 {
+                acceleration.reinforce();
+            }
+            acceleration.reinforcement_network().forward([0.1; 6])
+        };
+        assert_eq!(run(3), run(3));
+        assert_ne!(run(3), run(4));
+    }
+}
+
+#[cfg(test)]
+mod serialize {
+    use crate::training::{
+        Eval, Weights,
+        activations::{id::Id, relu::ReLu, tanh::Tanh},
+        mlp::MLP,
+        serialize::{
+            ActivationRegistry, Encoder, LoadError, from_bytes, from_text, to_bytes, to_text,
+        },
+    };
+
+    fn model() -> (MLP<f32>, Box<[f32]>) {
+        let mlp = MLP::new(3, vec![Tanh::layer(5), ReLu::layer(4), Id::layer(2)]);
+        let weights = (0..mlp.weights_len())
+            .map(|_| rand::random_range(-1.0..=1.0))
+            .collect();
+        (mlp, weights)
+    }
+
+    fn assert_same(a: &(MLP<f32>, Box<[f32]>), b: &(MLP<f32>, Box<[f32]>)) {
+        assert_eq!(a.1, b.1);
+        let names = |mlp: &MLP<f32>| mlp.layers().map(|(n, a)| (n, a.name())).collect::<Vec<_>>();
+        assert_eq!(names(&a.0), names(&b.0));
+        let input = [0.1, -0.2, 0.3];
+        let (mut sa, mut sb) = (a.0.empty_state(), b.0.empty_state());
+        a.0.eval(&input, &a.1, &mut sa);
+        b.0.eval(&input, &b.1, &mut sb);
+        assert_eq!(a.0.output(&sa), b.0.output(&sb));
+    }
+
+    #[test]
+    fn round_trip() {
+        let registry = ActivationRegistry::<f32>::default();
+        let model = model();
+        let bytes = to_bytes(&model.0, &model.1);
+        assert_same(&model, &from_bytes(&bytes, &registry).unwrap());
+        let text = to_text(&model.0, &model.1);
+        assert_same(&model, &from_text(&text, &registry).unwrap());
+    }
+
+    #[test]
+    fn typed_errors() {
+        let registry = ActivationRegistry::<f32>::default();
+        let (mlp, weights) = model();
+
+        let mut bytes = to_bytes(&mlp, &weights);
+        bytes[20] ^= 1;
+        assert!(matches!(
+            from_bytes(&bytes, &registry),
+            Err(LoadError::Checksum { .. })
+        ));
+        assert!(matches!(
+            from_bytes(b"nope", &registry),
+            Err(LoadError::NotAModel)
+        ));
+
+        let text = to_text(&mlp, &weights);
+        let unknown = text.replace("relu", "gelu");
+        assert!(matches!(
+            from_text(&unknown, &registry),
+            Err(LoadError::UnknownActivation(name)) if name == "gelu"
+        ));
+        let wrong_len = text.replace(
+            &format!("weights {}", weights.len()),
+            &format!("weights {}", weights.len() - 1),
+        );
+        assert!(matches!(
+            from_text(&wrong_len, &registry),
+            Err(LoadError::WeightsLen { expected, found }) if expected == weights.len() && found == expected - 1
+        ));
+        let lines = text.lines().collect::<Vec<_>>();
+        let truncated = lines[..lines.len() - 1].join("\n");
+        assert!(matches!(
+            from_text(&truncated, &registry),
+            Err(LoadError::Truncated)
+        ));
+        assert!(matches!(
+            from_text::<f32>(&text, &ActivationRegistry::empty()),
+            Err(LoadError::UnknownActivation(_))
+        ));
+    }
+
+    #[test]
+    fn invalid_architecture() {
+        let registry = ActivationRegistry::<f32>::default();
+        let invalid = |text: &str| {
+            matches!(
+                from_text(text, &registry),
+                Err(LoadError::InvalidArchitecture(_))
+            )
+        };
+        assert!(invalid("mlp 1\ninputs 2\nweights 0\n"));
+        assert!(invalid("mlp 1\ninputs 2\nlayer 0 id\nweights 0\n"));
+        assert!(invalid("mlp 1\ninputs 0\nlayer 1 id\nweights 1\n0\n"));
+        assert!(invalid(&format!(
+            "mlp 1\ninputs {}\nlayer 2 id\nweights 0\n",
+            usize::MAX
+        )));
+
+        let mut encoder = Encoder::new(*b"RMLP");
+        encoder.u64(2);
+        encoder.u64(0);
+        encoder.floats::<f32>(&[]);
+        assert!(matches!(
+            from_bytes(&encoder.finish(), &registry),
+            Err(LoadError::InvalidArchitecture(_))
+        ));
+    }
+}
+
+#[cfg(test)]
+mod checkpoint {
+    use crate::training::{
+        Optimizer, Weights,
+        activations::{id::Id, tanh::Tanh},
+        checkpoint::{from_bytes, to_bytes},
+        mlp::MLP,
+        optimizers::{adam::Adam, adamax::AdaMax},
+        serialize::{ActivationRegistry, LoadError},
+    };
+
+    fn steps(optimizer: &mut impl Optimizer<f32>, weights: &mut [f32], n: usize) {
+        let mut gradient = vec![0.0; weights.len()];
+        for _ in 0..n {
+            gradient
+                .iter_mut()
+                .zip(weights.iter())
+                .for_each(|(g, w)| *g = (w - 1.0) * w.sin());
+            optimizer.step(weights, &mut gradient);
+        }
+    }
+
+    /// Resuming from a checkpoint gives exactly the same weights as an uninterrupted run.
+    #[test]
+    fn resume() {
+        let mlp = MLP::new(2, vec![Tanh::layer(4), Id::layer(1)]);
+        let initial: Vec<f32> = (0..mlp.weights_len())
+            .map(|_| rand::random_range(-1.0..=1.0))
+            .collect();
+        let new_adam = || Adam::new(mlp.weights_len()).with_alpha(1e-2);
+
+        let mut weights = initial.clone();
+        steps(&mut new_adam(), &mut weights, 20);
+
+        let mut adam = new_adam();
+        let mut resumed = initial.clone();
+        steps(&mut adam, &mut resumed, 10);
+        let bytes = to_bytes(&[(&mlp, &resumed)], &[adam.state()]);
+        let checkpoint = from_bytes(&bytes, &ActivationRegistry::default()).unwrap();
+        let (_, mut resumed) = checkpoint.models.into_iter().next().unwrap();
+        let mut adam = Adam::new(mlp.weights_len());
+        adam.load_state(&checkpoint.optimizers[0]).unwrap();
+        steps(&mut adam, &mut resumed, 10);
+
+        assert_eq!(&weights[..], &resumed[..]);
+    }
+
+    #[test]
+    fn wrong_optimizer() {
+        let state = Adam::<f32>::new(3).state();
+        assert!(matches!(
+            AdaMax::new(3).load_state(&state),
+            Err(LoadError::WrongOptimizer { .. })
+        ));
+        assert!(matches!(
+            Adam::new(4).load_state(&state),
+            Err(LoadError::WeightsLen {
+                expected: 4,
+                found: 3
+            })
+        ));
+
+        let mut truncated = state.clone();
+        truncated.buffers.pop();
+        assert!(matches!(
+            Adam::new(3).load_state(&truncated),
+            Err(LoadError::StateLen {
+                what: "buffers",
+                expected: 2,
+                found: 1
+            })
+        ));
+        let mut truncated = state.clone();
+        truncated.scalars.pop();
+        assert!(matches!(
+            Adam::new(3).load_state(&truncated),
+            Err(LoadError::StateLen {
+                what: "scalars",
+                ..
+            })
+        ));
+    }
+}
+
+#[cfg(test)]
+mod optimizers {
+    use crate::training::{
+        Optimizer,
+        optimizers::{adamw::AdamW, lion::Lion, rmsprop::RmsProp, sgd::Sgd},
+    };
+
+    const TARGET: [f64; 4] = [1.0, -2.0, 0.5, 3.0];
+
+    fn minimize(optimizer: &mut impl Optimizer<f64>, weights: &mut [f64], steps: usize) {
+        let mut gradient = [0.0; 4];
+        for _ in 0..steps {
+            gradient
+                .iter_mut()
+                .zip(weights.iter().zip(TARGET.iter()))
+                .for_each(|(g, (w, t))| *g = 2.0 * (w - t));
+            optimizer.step(weights, &mut gradient);
+        }
+    }
+
+    fn check<O: Optimizer<f64>>(name: &str, new: impl Fn() -> O, tolerance: f64) {
+        let mut weights = [0.0; 4];
+        minimize(&mut new(), &mut weights, 2000);
+        let error = weights
+            .iter()
+            .zip(TARGET.iter())
+            .map(|(w, t)| (w - t).abs())
+            .fold(0.0, f64::max);
+        assert!(error < tolerance, "{name}: {weights:?}");
+
+        // NOTE: a fresh optimizer with the state of another continues exactly as the other
+        let (mut a, mut b) = ([0.0; 4], [0.0; 4]);
+        let mut optimizer = new();
+        minimize(&mut optimizer, &mut a, 10);
+        b.copy_from_slice(&a);
+        let mut resumed = new();
+        resumed.load_state(&optimizer.state()).unwrap();
+        minimize(&mut optimizer, &mut a, 10);
+        minimize(&mut resumed, &mut b, 10);
+        assert_eq!(a, b, "{name}");
+    }
+
+    #[test]
+    fn converge_and_resume() {
+        check("sgd", || Sgd::new(4).with_alpha(0.1), 1e-8);
+        check(
+            "momentum",
+            || Sgd::new(4).with_parameters(0.02, 0.9, false),
+            1e-8,
+        );
+        check(
+            "nesterov",
+            || Sgd::new(4).with_parameters(0.02, 0.9, true),
+            1e-8,
+        );
+        check("rmsprop", || RmsProp::new(4).with_alpha(1e-2), 1e-1);
+        check(
+            "adamw",
+            || AdamW::new(4).with_alpha(1e-2).with_weight_decay(0.0),
+            1e-3,
+        );
+        check("lion", || Lion::new(4).with_alpha(1e-2), 2e-2);
+    }
+
+    /// The decoupled decay shrinks the solution toward zero, whatever the scale of the gradient.
+    #[test]
+    fn adamw_decay() {
+        let mut weights = [0.0; 4];
+        minimize(
+            &mut AdamW::new(4).with_alpha(1e-2).with_weight_decay(1.0),
+            &mut weights,
+            4000,
+        );
+        assert!(
+            weights
+                .iter()
+                .zip(TARGET.iter())
+                .all(|(w, t)| w.abs() < t.abs() && w * t > 0.0),
+            "{weights:?}"
+        );
+    }
+}
+
+#[cfg(test)]
+mod schedule {
+    use crate::training::{
+        Optimizer,
+        checkpoint::{from_bytes, to_bytes},
+        optimizers::{adam::Adam, sgd::Sgd},
+        schedule::{Schedule, Scheduled},
+        serialize::ActivationRegistry,
+    };
+
+    fn close(a: f64, b: f64) -> bool {
+        (a - b).abs() < 1e-12
+    }
+
+    #[test]
+    fn values() {
+        let linear = Schedule::Linear {
+            start: 1.0,
+            end: 0.0,
+            steps: 10,
+        };
+        assert!(close(linear.value(0), 1.0));
+        assert!(close(linear.value(5), 0.5));
+        assert!(close(linear.value(20), 0.0));
+
+        let cosine = Schedule::Cosine {
+            start: 1.0,
+            end: 0.5,
+            steps: 10,
+        };
+        assert!(close(cosine.value(0), 1.0));
+        assert!(close(cosine.value(5), 0.75));
+        assert!(close(cosine.value(10), 0.5));
+
+        let warmup = Schedule::Warmup {
+            start: 0.0,
+            steps: 4,
+            then: Box::new(linear.clone()),
+        };
+        assert!(close(warmup.value(2), 0.5));
+        assert!(close(warmup.value(4), 1.0));
+        assert!(close(warmup.value(9), 0.5));
+
+        let step = Schedule::Step {
+            start: 1.0,
+            factor: 0.5,
+            every: 3,
+        };
+        assert!(close(step.value(2), 1.0));
+        assert!(close(step.value(7), 0.25));
+
+        let exponential = Schedule::Exponential {
+            start: 2.0,
+            rate: 0.5,
+        };
+        assert!(close(exponential.value(3), 0.25));
+        assert!(close(Schedule::Constant(0.3).value(100), 0.3));
+    }
+
+    #[test]
+    fn scheduled_optimizer() {
+        let schedule = Schedule::Linear {
+            start: 0.1,
+            end: 0.0,
+            steps: 4,
+        };
+        let mut sgd = Scheduled::new(Sgd::new(1), schedule.clone());
+        let mut weights = [0.0];
+        for _ in 0..6 {
+            sgd.step(&mut weights, &mut [-1.0]);
+        }
+        // NOTE: 0.1 + 0.075 + 0.05 + 0.025 + 0 + 0
+        assert!(close(weights[0], 0.25));
+        assert!(close(sgd.learning_rate(), 0.0));
+
+        let mut adam = Scheduled::new(Adam::new(1), schedule.clone());
+        adam.step(&mut weights, &mut [1.0]);
+        let mut resumed = Scheduled::new(Adam::new(1), schedule);
+        resumed.load_state(&adam.state()).unwrap();
+        assert_eq!(resumed.steps(), 1);
+        assert!(close(resumed.learning_rate(), 0.075));
+        assert!(Adam::new(1).load_state(&adam.state()).is_err());
+
+        // NOTE: 2^24 + 1 steps are not representable in f32
+        let mut sgd = Scheduled::new(Sgd::<f32>::new(1), Schedule::Constant(0.1));
+        let mut state = sgd.state();
+        state.counters[0] = (1 << 24) + 1;
+        let bytes = to_bytes(&[], &[state]);
+        let checkpoint = from_bytes(&bytes, &ActivationRegistry::default()).unwrap();
+        sgd.load_state(&checkpoint.optimizers[0]).unwrap();
+        assert_eq!(sgd.steps(), (1 << 24) + 1);
+    }
+
+    #[test]
+    fn set_learning_rate() {
+        let schedule = Schedule::Linear {
+            start: 0.1,
+            end: 0.0,
+            steps: 4,
+        };
+        let mut sgd = Scheduled::new(Sgd::new(1), schedule.clone());
+        let mut weights = [0.0];
+        sgd.step(&mut weights, &mut [-1.0]);
+        sgd.set_learning_rate(0.15);
+        assert!(close(sgd.learning_rate(), 0.15));
+        sgd.step(&mut weights, &mut [-1.0]);
+        // NOTE: the rest of the schedule is doubled
+        assert!(close(weights[0], 0.25));
+        assert!(close(sgd.learning_rate(), 0.1));
+
+        let mut resumed = Scheduled::new(Sgd::new(1), schedule);
+        resumed.load_state(&sgd.state()).unwrap();
+        assert!(close(resumed.learning_rate(), 0.1));
+
+        for _ in 0..2 {
+            sgd.step(&mut weights, &mut [-1.0]);
+        }
+        assert!(close(sgd.learning_rate(), 0.0));
+        sgd.set_learning_rate(0.01);
+        assert!(close(sgd.learning_rate(), 0.01));
+        sgd.step(&mut weights, &mut [-1.0]);
+        assert!(close(sgd.learning_rate(), 0.01));
+    }
+}
+
+#[cfg(test)]
+mod optimize {
+    use crate::training::{
+        EarlyStop, EpochStats, Eval, OptimizeBuffers, OptimizeOptions, OptimizeReport,
+        OptimizeStats, Optimizer, TimeStep, Value, Weights,
+        activations::id::Id,
+        activations::tanh::Tanh,
+        least_squar_value::LeastSquareValue,
+        mlp::MLP,
+        optimizers::{adam::Adam, sgd::Sgd},
+        policies::normal_policy::NormalPolicy,
+        ppo::{ClippedSurrogate, Ppo},
+    };
+
+    fn run<E>(
+        targets: &[f64],
+        alpha: f64,
+        options: OptimizeOptions<f64, E>,
+    ) -> (Box<[f64]>, OptimizeStats<f64>)
+    where
+        E: for<'a> EarlyStop<f64, LeastSquareValue<f64>, [TimeStep<'a, f64>]>,
+    {
+        let mut value = LeastSquareValue::new(MLP::new(1, vec![Id::layer(1)]));
+        let mut weights = value.empty_weights();
+        let mut buffers = OptimizeBuffers::new(value.weights_len(), targets.len());
+        let inputs = vec![[1.0]; targets.len()];
+        let mut states = targets
+            .iter()
+            .map(|&t| {
+                let mut state = value.empty_state();
+                value.set_target(t, &mut state);
+                state
+            })
+            .collect::<Vec<_>>();
+        let mut time_steps = inputs
+            .iter()
+            .zip(states.iter_mut())
+            .map(|(input, state)| TimeStep { input, state })
+            .collect::<Vec<_>>();
+        let stats = Sgd::new(weights.len()).with_alpha(alpha).optimize(
+            &mut value,
+            &mut weights,
+            &mut time_steps[..],
+            &mut buffers,
+            options,
+            &mut rand::rng(),
+        );
+        (weights, stats)
+    }
+
+    fn run_once(targets: &[f64], options: OptimizeOptions<f64>) -> (Box<[f64]>, OptimizeReport) {
+        let (weights, stats) = run(targets, 1.0, options.with_minibatch_size(2));
+        (weights, stats.total)
+    }
+
+    #[test]
+    fn skip_non_finite() {
+        let (weights, report) = run_once(&[1.0, 1.0, f64::NAN, 1.0], OptimizeOptions::default());
+        assert!(weights.iter().all(|w| w.is_finite()), "{weights:?}");
+        assert_eq!(report.steps + report.skipped, 2);
+        assert_eq!(report.skipped, 1);
+        assert!(!report.is_finite());
+    }
+
+    #[test]
+    fn clipping() {
+        let targets = [100.0; 4];
+        let (weights, report) = run_once(&targets, OptimizeOptions::default().with_max_norm(0.5));
+        assert_eq!(report.clipped, 2);
+        let norm = weights.iter().map(|w| w * w).sum::<f64>().sqrt();
+        assert!(norm <= 1.0 + 1e-12, "{weights:?}");
+
+        let (weights, _) = run_once(&targets, OptimizeOptions::default().with_max_value(0.1));
+        assert!(
+            weights.iter().all(|w| w.abs() <= 0.2 + 1e-12),
+            "{weights:?}"
+        );
+    }
+
+    #[test]
+    fn guarded_step() {
+        let options = OptimizeOptions::default().with_max_norm(1.0);
+        let mut sgd = Sgd::new(1).with_alpha(1.0);
+        let mut weights = [0.0];
+        let report = sgd.guarded_step(&mut weights, &mut [f64::NAN], &options);
+        assert_eq!((report.steps, report.skipped), (0, 1));
+        assert_eq!(weights, [0.0]);
+        let report = sgd.guarded_step(&mut weights, &mut [-3.0], &options);
+        assert_eq!((report.steps, report.clipped), (1, 1));
+        assert_eq!(weights, [1.0]);
+    }
+
+    #[test]
+    fn remainder() {
+        let targets = [1.0; 5];
+        let options = OptimizeOptions::default().with_minibatch_size(2);
+        let (_, stats) = run(&targets, 0.1, options);
+        assert_eq!(stats.total.steps, 2);
+        let (_, stats) = run(&targets, 0.1, options.with_remainder(true));
+        assert_eq!(stats.total.steps, 3);
+    }
+
+    #[test]
+    fn epoch_stats_and_early_stop() {
+        let targets = [1.0, 2.0, 3.0, 2.0];
+        let options = OptimizeOptions::default()
+            .with_epochs(20)
+            .with_minibatch_size(2);
+        let (_, stats) = run(&targets, 0.1, options);
+        assert_eq!(stats.epochs, 20);
+        assert_eq!(stats.total.steps, 40);
+        assert!(!stats.stopped_early);
+        let (first, last) = (stats.first.unwrap(), stats.last.unwrap());
+        assert!(last.mean_objective < first.mean_objective, "{stats:?}");
+        assert!(
+            last.mean_gradient_norm < first.mean_gradient_norm,
+            "{stats:?}"
+        );
+
+        let mut seen = 0;
+        let early_stop =
+            |_: &LeastSquareValue<f64>, epoch: &EpochStats<f64>, time_steps: &[TimeStep<f64>]| {
+                seen += 1;
+                assert_eq!(time_steps.len(), targets.len());
+                epoch.mean_objective < 1.0
+            };
+        let (_, stats) = run(&targets, 0.1, options.with_early_stop(early_stop));
+        assert!(stats.stopped_early);
+        assert_eq!(stats.epochs, seen);
+        assert!(seen < 20, "{stats:?}");
+        assert!(stats.last.unwrap().mean_objective < 1.0, "{stats:?}");
+    }
+
+    #[test]
+    fn ppo_target_kl() {
+        let surrogate = ClippedSurrogate::new(NormalPolicy::new(
+            MLP::new(1, vec![Tanh::layer(8), Id::layer(1)]),
+            0.1,
+        ));
+        let value = LeastSquareValue::new(MLP::new(1, vec![Tanh::layer(8), Id::layer(1)]));
+        let policy_adam = Adam::new(surrogate.weights_len()).with_alpha(1e-1);
+        let value_adam = Adam::new(value.weights_len()).with_alpha(1e-3);
+        let mut ppo = Ppo::new(surrogate, value, policy_adam, value_adam, 16, 1)
+            .with_epochs(50)
+            .with_minibatch_size(8)
+            .with_target_kl(1e-3)
+            .with_seed(1);
+        let mut ctx_list = (0..16).map(|i| i as f64 / 8.0 - 1.0).collect::<Vec<_>>();
+        ppo.collect(
+            &mut ctx_list,
+            |&x, input| input[0] = x,
+            |&mut x, action| (-(x - action[0]).powi(2), true),
+        );
+        ppo.update();
+        let stats = ppo.last_policy_stats();
+        assert!(stats.stopped_early, "{stats:?}");
+        assert!(stats.epochs < 50, "{stats:?}");
+        assert!(ppo.last_approx_kl() > 1.5e-3, "{}", ppo.last_approx_kl());
+        assert_eq!(ppo.last_value_stats().epochs, 50);
+    }
+}
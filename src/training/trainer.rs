@@ -1,10 +1,10 @@
 use std::{fmt::LowerExp, iter::Sum};
 
 use num::Float;
-use rand::Rng;
+use rand::{Rng, SeedableRng, rngs::StdRng};
 use rand_distr::uniform::{SampleBorrow, SampleUniform};
 
-use super::{BackProp, Eval, Weights, mlp::MLP};
+use super::{BackProp, Eval, Weights, init::Init, mlp::MLP};
 
 pub struct Trainer<T: Float> {
     mlp: MLP<T>,
@@ -13,6 +13,7 @@ pub struct Trainer<T: Float> {
     back: Box<[T]>,
     front: Box<[T]>,
     gradient: Box<[T]>,
+    rng: StdRng,
 }
 impl<T: Float> Trainer<T> {
     pub fn new(mlp: MLP<T>) -> Self {
@@ -22,16 +23,27 @@ impl<T: Float> Trainer<T> {
             back: vec![T::zero(); mlp.min_back_front_len()].into_boxed_slice(),
             front: vec![T::zero(); mlp.min_back_front_len()].into_boxed_slice(),
             gradient: mlp.empty_weights(),
+            rng: StdRng::from_os_rng(),
             mlp,
         }
     }
+    /// Seed the initialization of the weights, for reproducible runs
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.rng = StdRng::seed_from_u64(seed);
+        self
+    }
     pub fn randomize_weights(&mut self, a: T)
     where
         T: SampleBorrow<T> + SampleUniform,
     {
-        let mut rng = rand::rng();
         let uni = rand::distr::Uniform::new(-a, a).unwrap();
-        self.weights.iter_mut().for_each(|w| *w = rng.sample(&uni));
+        self.weights
+            .iter_mut()
+            .for_each(|w| *w = self.rng.sample(&uni));
+    }
+    pub fn init_weights(&mut self, init: Init<T>) {
+        self.mlp
+            .init_weights(&mut self.weights, init, &mut self.rng);
     }
     pub fn train(&mut self, iterations: usize, alpha: T, training_data: &[(&[T], &[T])])
     where